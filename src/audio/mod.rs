@@ -0,0 +1,133 @@
+// Sound out through SDL. Samples are resampled from the APU's rate to the device's and queued,
+// and the rate is nudged up or down a little so the queue hovers around a target fill level.
+// That lets the queue pace the emulator: the main loop waits for it to drain instead of
+// sleeping on a timer, and the two clocks never drift far enough apart to crackle.
+
+mod recorder;
+mod resampler;
+mod vgm;
+mod wav;
+
+use sdl2;
+use sdl2::audio::{ AudioQueue, AudioSpecDesired };
+
+use std::thread::sleep;
+use std::time::Duration;
+
+use config;
+use gameboy;
+
+pub use self::recorder::{ RecordMode, Recorder };
+pub use self::resampler::Resampler;
+pub use self::vgm::VgmWriter;
+
+const DEFAULT_RATE: u32 = 48000;
+const DEFAULT_LATENCY_MS: u32 = 64;
+
+// the furthest the output rate is moved away from the device rate, half a percent can't be heard
+const MAX_RATE_DELTA: f64 = 0.005;
+
+const BYTES_PER_FRAME: u32 = 8;
+
+pub struct AudioOutput {
+    queue: AudioQueue<f32>,
+    resampler: Resampler,
+    device_rate: u32,
+    // stereo frames the queue should hold
+    target_frames: u32,
+    buffer: Vec<f32>,
+}
+
+impl AudioOutput {
+
+    // audio = false leaves the emulator silent and paced by its own timer. audio_rate and
+    // audio_latency (in ms) pick the device rate and how much is kept queued
+    pub fn from_config(context: &sdl2::Sdl, config: &config::Config) -> Option<AudioOutput> {
+        if !config.get_bool("audio", true) {
+            return None;
+        }
+
+        let rate = read_number(config, "audio_rate", DEFAULT_RATE);
+        let latency = read_number(config, "audio_latency", DEFAULT_LATENCY_MS).max(1);
+
+        match AudioOutput::new(context, rate, latency) {
+            Ok(output) => Some(output),
+            Err(e) => {
+                println!("couldn't open audio, carrying on without it: {}", e);
+                None
+            },
+        }
+    }
+
+    pub fn new(context: &sdl2::Sdl, rate: u32, latency_ms: u32) -> Result<AudioOutput, String> {
+        let desired = AudioSpecDesired {
+            freq: Some(rate as i32),
+            channels: Some(2),
+            samples: None,
+        };
+
+        let queue = context.audio()?.open_queue::<f32, _>(None, &desired)?;
+        // the device might not give us the rate we asked for
+        let device_rate = queue.spec().freq as u32;
+        queue.resume();
+
+        Ok(AudioOutput {
+            queue: queue,
+            resampler: Resampler::new(2, gameboy::AUDIO_SAMPLE_RATE, device_rate),
+            device_rate: device_rate,
+            target_frames: device_rate * latency_ms / 1000,
+            buffer: Vec::new(),
+        })
+    }
+
+    fn queued_frames(&self) -> u32 {
+        self.queue.size() / BYTES_PER_FRAME
+    }
+
+    // takes interleaved samples straight from Gameboy::drain_audio
+    pub fn push(&mut self, samples: &[f32]) {
+        // running low makes more output per input, running high makes less
+        let fill = self.queued_frames() as f64 / self.target_frames as f64;
+        let delta = (MAX_RATE_DELTA * (1.0 - fill)).max(-MAX_RATE_DELTA).min(MAX_RATE_DELTA);
+        self.resampler.set_output_rate(self.device_rate as f64 * (1.0 + delta));
+
+        self.buffer.clear();
+        self.resampler.process(samples, &mut self.buffer);
+        self.queue.queue(&self.buffer);
+    }
+
+    // drops whatever is still queued so pausing goes quiet straight away
+    pub fn clear(&mut self) {
+        self.queue.clear();
+    }
+
+    // blocks until the queue is down to its target, which keeps the emulator at full speed
+    pub fn wait(&self) {
+        while self.queued_frames() > self.target_frames {
+            sleep(Duration::from_millis(1));
+        }
+    }
+}
+
+// record_mode = mix | stems and record_rate, which can be 131072 to keep the APU's own rate
+pub fn recording_from_config(config: &config::Config) -> (RecordMode, u32) {
+    let mode = match config.get("record_mode") {
+        Some(name) => RecordMode::from_name(name).unwrap_or_else(|| {
+            println!("unknown record mode {}, using mix", name);
+            RecordMode::Mix
+        }),
+        None => RecordMode::Mix,
+    };
+
+    (mode, read_number(config, "record_rate", DEFAULT_RATE).max(1))
+}
+
+fn read_number(config: &config::Config, key: &str, default: u32) -> u32 {
+    match config.get(key) {
+        Some(value) => value.parse::<u32>().unwrap_or_else(|_| {
+            println!("{} should be a whole number, got {}", key, value);
+            default
+        }),
+        None => default,
+    }
+}
@@ -0,0 +1,124 @@
+// Records what the APU makes to WAV, either the stereo mix as it's heard or every channel as its
+// own mono file. Stems are taken before panning and master volume so each one is the whole
+// channel, which is what's wanted for ripping music out of a game.
+
+use std::io;
+
+use gameboy;
+
+use super::Resampler;
+use super::wav::WavWriter;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum RecordMode {
+    Mix,
+    Stems,
+}
+
+impl RecordMode {
+
+    pub fn from_name(name: &str) -> Option<RecordMode> {
+        match name {
+            "mix" => Some(RecordMode::Mix),
+            "stems" => Some(RecordMode::Stems),
+            _ => None,
+        }
+    }
+
+    fn channels(&self) -> usize {
+        match *self {
+            RecordMode::Mix => 2,
+            RecordMode::Stems => 4,
+        }
+    }
+}
+
+pub struct Recorder {
+    mode: RecordMode,
+    // one stereo file for the mix, or channels 1-4 in order
+    writers: Vec<WavWriter>,
+    // left out when the file rate is the APU's own
+    resampler: Option<Resampler>,
+    resampled: Vec<f32>,
+    stem: Vec<f32>,
+}
+
+impl Recorder {
+
+    // stems go next to path with the channel number added, so out.wav becomes out-ch1.wav and on
+    pub fn start(path: &str, mode: RecordMode, rate: u32) -> io::Result<Recorder> {
+        let writers = match mode {
+            RecordMode::Mix => vec![WavWriter::create(path, 2, rate)?],
+            RecordMode::Stems => {
+                let mut writers = Vec::with_capacity(4);
+                for channel in 1..=4 {
+                    writers.push(WavWriter::create(&stem_path(path, channel), 1, rate)?);
+                }
+                writers
+            },
+        };
+
+        let resampler = if rate == gameboy::AUDIO_SAMPLE_RATE {
+            None
+        } else {
+            Some(Resampler::new(mode.channels(), gameboy::AUDIO_SAMPLE_RATE, rate))
+        };
+
+        Ok(Recorder {
+            mode: mode,
+            writers: writers,
+            resampler: resampler,
+            resampled: Vec::new(),
+            stem: Vec::new(),
+        })
+    }
+
+    // stems need Gameboy::set_channel_capture on to have anything to record
+    pub fn wants_channels(&self) -> bool {
+        self.mode == RecordMode::Stems
+    }
+
+    // mix and channels are whatever Gameboy::drain_audio and drain_channel_audio gave this frame
+    pub fn update(&mut self, mix: &[f32], channels: &[f32]) -> io::Result<()> {
+        let input = match self.mode {
+            RecordMode::Mix => mix,
+            RecordMode::Stems => channels,
+        };
+
+        let samples = match self.resampler {
+            Some(ref mut resampler) => {
+                self.resampled.clear();
+                resampler.process(input, &mut self.resampled);
+                &self.resampled
+            },
+            None => input,
+        };
+
+        if self.mode == RecordMode::Mix {
+            return self.writers[0].write(samples);
+        }
+
+        for (channel, writer) in self.writers.iter_mut().enumerate() {
+            self.stem.clear();
+            self.stem.extend(samples.iter().skip(channel).step_by(4));
+            writer.write(&self.stem)?;
+        }
+
+        Ok(())
+    }
+
+    pub fn finish(self) -> io::Result<()> {
+        for writer in self.writers {
+            writer.finish()?;
+        }
+
+        Ok(())
+    }
+}
+
+fn stem_path(path: &str, channel: usize) -> String {
+    match path.rfind('.') {
+        Some(idx) if !path[idx..].contains('/') => format!("{}-ch{}{}", &path[..idx], channel, &path[idx..]),
+        _ => format!("{}-ch{}", path, channel),
+    }
+}
@@ -0,0 +1,141 @@
+// Windowed sinc resampler for interleaved samples with any number of channels. The APU makes
+// samples far above anything a sound card takes, so everything above the output's Nyquist
+// frequency has to be filtered out or it folds back down as noise. The output rate can be
+// nudged while running, which is what dynamic rate control uses to keep the audio buffer level.
+
+use std::f64::consts::PI;
+
+// zero crossings of the sinc on each side of the center, more is sharper and slower
+const ZERO_CROSSINGS: f64 = 12.0;
+
+// passband as a fraction of the output's Nyquist frequency, leaves room for the window's rolloff
+const PASSBAND: f64 = 0.9;
+
+// kernel values stored per input sample of distance, the rest is linear interpolation
+const TABLE_RESOLUTION: usize = 256;
+
+pub struct Resampler {
+    channels: usize,
+    input_rate: f64,
+    // input samples per output sample
+    step: f64,
+    half_width: f64,
+    table: Vec<f32>,
+    // interleaved like the input
+    history: Vec<f32>,
+    // where the next output frame falls, in input frames from the start of history
+    position: f64,
+    sums: Vec<f32>,
+}
+
+impl Resampler {
+
+    pub fn new(channels: usize, input_rate: u32, output_rate: u32) -> Resampler {
+        let input_rate = input_rate as f64;
+        let output_rate = output_rate as f64;
+
+        // the cutoff is fixed from the nominal rates, rate control only moves them a fraction
+        // of a percent
+        let cutoff = PASSBAND * (output_rate / input_rate).min(1.0);
+        let half_width = ZERO_CROSSINGS / cutoff;
+
+        let entries = (half_width * TABLE_RESOLUTION as f64).ceil() as usize + 2;
+        let table = (0..entries).map(|i| {
+            let x = i as f64 / TABLE_RESOLUTION as f64;
+            (cutoff * sinc(cutoff * x) * blackman(x / half_width)) as f32
+        }).collect();
+
+        Resampler {
+            channels: channels,
+            input_rate: input_rate,
+            step: input_rate / output_rate,
+            half_width: half_width,
+            table: table,
+            history: Vec::new(),
+            position: 0.0,
+            sums: vec![0.0; channels],
+        }
+    }
+
+    pub fn set_output_rate(&mut self, output_rate: f64) {
+        self.step = self.input_rate / output_rate;
+    }
+
+    // takes interleaved input and appends interleaved output. samples near the end of the
+    // input wait for the next call, when the samples after them are known
+    pub fn process(&mut self, input: &[f32], output: &mut Vec<f32>) {
+        let whole_frames = input.len() / self.channels * self.channels;
+        self.history.extend_from_slice(&input[..whole_frames]);
+
+        while self.position + self.half_width < self.frames() as f64 {
+            let position = self.position;
+            self.frame_at(position, output);
+            self.position += self.step;
+        }
+
+        // anything further back than the kernel reaches won't be needed again
+        let consumed = (self.position - self.half_width).floor();
+        if consumed > 0.0 {
+            let consumed = (consumed as usize).min(self.frames());
+            self.history.drain(..consumed * self.channels);
+            self.position -= consumed as f64;
+        }
+    }
+
+    fn frames(&self) -> usize {
+        self.history.len() / self.channels
+    }
+
+    fn frame_at(&mut self, position: f64, output: &mut Vec<f32>) {
+        let first = (position - self.half_width).ceil().max(0.0) as usize;
+        let last = ((position + self.half_width).floor() as usize).min(self.frames() - 1);
+
+        for sum in self.sums.iter_mut() {
+            *sum = 0.0;
+        }
+
+        let mut total = 0.0;
+        for i in first..=last {
+            let weight = self.kernel((i as f64 - position).abs());
+            let frame = &self.history[i * self.channels..(i + 1) * self.channels];
+            for (sum, sample) in self.sums.iter_mut().zip(frame) {
+                *sum += sample * weight;
+            }
+            total += weight;
+        }
+
+        // dividing by the total weight keeps DC exactly where it was
+        for sum in &self.sums {
+            output.push(if total.abs() > 1e-6 { sum / total } else { 0.0 });
+        }
+    }
+
+    fn kernel(&self, distance: f64) -> f32 {
+        let index = distance * TABLE_RESOLUTION as f64;
+        let base = index.floor() as usize;
+        if base + 1 >= self.table.len() {
+            return 0.0;
+        }
+
+        let fraction = (index - base as f64) as f32;
+        self.table[base] + (self.table[base + 1] - self.table[base]) * fraction
+    }
+}
+
+fn sinc(x: f64) -> f64 {
+    if x.abs() < 1e-9 {
+        1.0
+    } else {
+        (PI * x).sin() / (PI * x)
+    }
+}
+
+// x from 0 at the center to 1 at the edge
+fn blackman(x: f64) -> f64 {
+    if x >= 1.0 {
+        return 0.0;
+    }
+
+    let t = (x + 1.0) / 2.0;
+    0.42 - 0.5 * (2.0 * PI * t).cos() + 0.08 * (4.0 * PI * t).cos()
+}
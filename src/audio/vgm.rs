@@ -0,0 +1,105 @@
+// VGM 1.61 files from logged APU writes. Each write becomes a Game Boy DMG write command and
+// the time between them becomes waits counted in 44100 Hz samples. The header goes out first
+// and the lengths in it are filled in by finish.
+
+use std::fs::File;
+use std::io;
+use std::io::prelude::*;
+use std::io::{ BufWriter, SeekFrom };
+
+use gameboy::ApuWrite;
+
+const VERSION: u32 = 0x161;
+const HEADER_SIZE: usize = 0x100;
+const DMG_CLOCK: u32 = 4194304;
+const SAMPLE_RATE: u64 = 44100;
+
+// header fields
+const EOF_OFFSET: u64 = 0x04;
+const TOTAL_SAMPLES: u64 = 0x18;
+
+// commands
+const DMG_WRITE: u8 = 0xB3;
+const WAIT: u8 = 0x61;
+const WAIT_NTSC_FRAME: u8 = 0x62;
+const WAIT_PAL_FRAME: u8 = 0x63;
+// 0x70-0x7F waits 1-16 samples
+const WAIT_SHORT: u8 = 0x70;
+const END: u8 = 0x66;
+
+pub struct VgmWriter {
+    file: BufWriter<File>,
+    bytes: u32,
+    // the dot the last write was at, and the samples waited up to it
+    cycle: u64,
+    samples: u64,
+}
+
+impl VgmWriter {
+
+    pub fn create(path: &str) -> io::Result<VgmWriter> {
+        let mut header = [0; HEADER_SIZE];
+        header[0x00..0x04].copy_from_slice(b"Vgm ");
+        header[0x08..0x0C].copy_from_slice(&VERSION.to_le_bytes());
+        // the data offset is counted from where it's stored
+        header[0x34..0x38].copy_from_slice(&(HEADER_SIZE as u32 - 0x34).to_le_bytes());
+        header[0x80..0x84].copy_from_slice(&DMG_CLOCK.to_le_bytes());
+
+        let mut file = BufWriter::new(File::create(path)?);
+        file.write_all(&header)?;
+
+        Ok(VgmWriter {
+            file: file,
+            bytes: HEADER_SIZE as u32,
+            cycle: 0,
+            samples: 0,
+        })
+    }
+
+    // writes come from Gameboy::drain_apu_writes, oldest first
+    pub fn write(&mut self, writes: &[ApuWrite]) -> io::Result<()> {
+        for write in writes {
+            self.wait_until(write.cycle)?;
+            self.command(&[DMG_WRITE, write.register, write.value])?;
+        }
+
+        Ok(())
+    }
+
+    // time that passes with no writes still has to be in the file, cycle is where logging is up to
+    pub fn wait_until(&mut self, cycle: u64) -> io::Result<()> {
+        self.cycle = self.cycle.max(cycle);
+
+        // counted from the start every time so rounding never adds up
+        let target = self.cycle * SAMPLE_RATE / DMG_CLOCK as u64;
+        while self.samples < target {
+            let wait = (target - self.samples).min(0xFFFF);
+            match wait {
+                1..=16 => self.command(&[WAIT_SHORT + (wait - 1) as u8])?,
+                735 => self.command(&[WAIT_NTSC_FRAME])?,
+                882 => self.command(&[WAIT_PAL_FRAME])?,
+                _ => self.command(&[WAIT, wait as u8, (wait >> 8) as u8])?,
+            }
+            self.samples += wait;
+        }
+
+        Ok(())
+    }
+
+    fn command(&mut self, bytes: &[u8]) -> io::Result<()> {
+        self.file.write_all(bytes)?;
+        self.bytes += bytes.len() as u32;
+
+        Ok(())
+    }
+
+    pub fn finish(mut self) -> io::Result<()> {
+        self.command(&[END])?;
+
+        self.file.seek(SeekFrom::Start(EOF_OFFSET))?;
+        self.file.write_all(&(self.bytes - EOF_OFFSET as u32).to_le_bytes())?;
+        self.file.seek(SeekFrom::Start(TOTAL_SAMPLES))?;
+        self.file.write_all(&(self.samples as u32).to_le_bytes())?;
+        self.file.flush()
+    }
+}
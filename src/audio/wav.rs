@@ -0,0 +1,71 @@
+// 16-bit PCM WAV files. The header goes out first with the sizes left at 0, and they're filled
+// in by finish once it's known how much was written.
+
+use std::fs::File;
+use std::io;
+use std::io::prelude::*;
+use std::io::{ BufWriter, SeekFrom };
+
+const HEADER_SIZE: u32 = 44;
+// the RIFF size counts everything after its own 8 bytes in 32 bits
+const MAX_DATA_BYTES: u32 = u32::MAX - (HEADER_SIZE - 8);
+
+pub struct WavWriter {
+    file: BufWriter<File>,
+    data_bytes: u32,
+}
+
+impl WavWriter {
+
+    pub fn create(path: &str, channels: u16, rate: u32) -> io::Result<WavWriter> {
+        let mut file = BufWriter::new(File::create(path)?);
+
+        let block_align = channels * 2;
+        file.write_all(b"RIFF")?;
+        file.write_all(&0u32.to_le_bytes())?;
+        file.write_all(b"WAVE")?;
+
+        file.write_all(b"fmt ")?;
+        file.write_all(&16u32.to_le_bytes())?;
+        // 1 is plain PCM
+        file.write_all(&1u16.to_le_bytes())?;
+        file.write_all(&channels.to_le_bytes())?;
+        file.write_all(&rate.to_le_bytes())?;
+        file.write_all(&(rate * block_align as u32).to_le_bytes())?;
+        file.write_all(&block_align.to_le_bytes())?;
+        file.write_all(&16u16.to_le_bytes())?;
+
+        file.write_all(b"data")?;
+        file.write_all(&0u32.to_le_bytes())?;
+
+        Ok(WavWriter {
+            file: file,
+            data_bytes: 0,
+        })
+    }
+
+    // interleaved samples from -1.0 to 1.0, anything outside that is clipped. fails without
+    // writing anything once the file would be too big for its sizes
+    pub fn write(&mut self, samples: &[f32]) -> io::Result<()> {
+        let bytes = samples.len() as u64 * 2;
+        if self.data_bytes as u64 + bytes > MAX_DATA_BYTES as u64 {
+            return Err(io::Error::new(io::ErrorKind::Other, "WAV file is full"));
+        }
+
+        for sample in samples {
+            let value = (sample.max(-1.0).min(1.0) * 32767.0).round() as i16;
+            self.file.write_all(&value.to_le_bytes())?;
+        }
+
+        self.data_bytes += bytes as u32;
+        Ok(())
+    }
+
+    pub fn finish(mut self) -> io::Result<()> {
+        self.file.seek(SeekFrom::Start(4))?;
+        self.file.write_all(&(HEADER_SIZE - 8 + self.data_bytes).to_le_bytes())?;
+        self.file.seek(SeekFrom::Start(HEADER_SIZE as u64 - 4))?;
+        self.file.write_all(&self.data_bytes.to_le_bytes())?;
+        self.file.flush()
+    }
+}
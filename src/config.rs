@@ -0,0 +1,90 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::prelude::*;
+
+pub const DEFAULT_PATH: &str = "rustyboi.cfg";
+
+// Settings file, one `key = value` per line. Lines starting with # are comments.
+//
+//     palette = pocket
+//     color_correction = true
+pub struct Config {
+    values: HashMap<String, String>,
+}
+
+impl Default for Config {
+
+    fn default() -> Config {
+        Config { values: HashMap::new() }
+    }
+}
+
+impl Config {
+
+    pub fn new() -> Config {
+        Default::default()
+    }
+
+    // a missing file just means everything is left at its default
+    pub fn load(path: &str) -> Config {
+        let mut contents = String::new();
+        match File::open(path) {
+            Ok(mut f) => {
+                if let Err(e) = f.read_to_string(&mut contents) {
+                    println!("couldn't read config {}: {}", path, e);
+                }
+            },
+            Err(_) => return Config::new(),
+        }
+
+        Config::parse(&contents)
+    }
+
+    pub fn parse(contents: &str) -> Config {
+        let mut config = Config::new();
+
+        for (number, line) in contents.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            match line.find('=') {
+                Some(idx) => {
+                    let key = line[..idx].trim().to_lowercase();
+                    let value = line[idx + 1..].trim().to_string();
+                    config.values.insert(key, value);
+                },
+                None => println!("config line {} has no '=', ignoring it: {}", number + 1, line),
+            }
+        }
+
+        config
+    }
+
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.values.get(key).map(|value| value.as_str())
+    }
+
+    pub fn get_bool(&self, key: &str, default: bool) -> bool {
+        match self.get(key) {
+            Some("true") | Some("on") | Some("yes") | Some("1") => true,
+            Some("false") | Some("off") | Some("no") | Some("0") => false,
+            Some(value) => {
+                println!("config {} should be true or false, got {}", key, value);
+                default
+            },
+            None => default,
+        }
+    }
+}
+
+// accepts RRGGBB with an optional # or 0x in front
+pub fn parse_hex_color(value: &str) -> Option<u32> {
+    let hex = value.trim_start_matches('#').trim_start_matches("0x");
+    if hex.len() != 6 {
+        return None;
+    }
+
+    u32::from_str_radix(hex, 16).ok()
+}
@@ -0,0 +1,592 @@
+// APU registers:
+// FF10   NR10   square 1 sweep
+// FF11   NR11   square 1 duty and length
+// FF12   NR12   square 1 envelope
+// FF13   NR13   square 1 frequency low
+// FF14   NR14   square 1 trigger, length enable and frequency high
+// FF16   NR21   square 2 duty and length
+// FF17   NR22   square 2 envelope
+// FF18   NR23   square 2 frequency low
+// FF19   NR24   square 2 trigger, length enable and frequency high
+// FF1A   NR30   wave DAC enable
+// FF1B   NR31   wave length
+// FF1C   NR32   wave volume
+// FF1D   NR33   wave frequency low
+// FF1E   NR34   wave trigger, length enable and frequency high
+// FF20   NR41   noise length
+// FF21   NR42   noise envelope
+// FF22   NR43   noise clock shift, width and divisor
+// FF23   NR44   noise trigger and length enable
+// FF24   NR50   master volume
+// FF25   NR51   panning
+// FF26   NR52   power and channel status
+// FF30-FF3F     wave RAM
+
+mod noise;
+mod square;
+mod wave;
+
+use gameboy::state::{ StateReader, StateResult, StateWriter };
+
+// stereo samples come out at this rate, one every CYCLES_PER_SAMPLE t cycles
+pub const SAMPLE_RATE: u32 = 131072;
+const CYCLES_PER_SAMPLE: u32 = 32;
+
+// the frame sequencer runs at 512 Hz: length on even steps, sweep on 2 and 6, envelope on 7
+const FRAME_SEQUENCER_CYCLES: u32 = 8192;
+
+// channels only move on m cycle boundaries, so they're run 4 t cycles at a time
+const STEP_CYCLES: u32 = 4;
+
+// what a read of each register from FF10 to FF2F ORs in, write only bits read high
+const READ_MASKS: [u8; 32] = [
+    0x80, 0x3F, 0x00, 0xFF, 0xBF,
+    0xFF, 0x3F, 0x00, 0xFF, 0xBF,
+    0x7F, 0xFF, 0x9F, 0xFF, 0xBF,
+    0xFF, 0xFF, 0x00, 0x00, 0xBF,
+    0x00, 0x00, 0x70,
+    0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF,
+];
+
+// the output capacitor charges a little every t cycle, which takes out any DC offset
+const HIGH_PASS_CHARGE: f32 = 0.999958;
+
+// samples nobody has taken are dropped past this, about a second's worth
+const MAX_BUFFERED_SAMPLES: usize = SAMPLE_RATE as usize * 2;
+
+// NRx4 registers, as offsets from FF10
+const TRIGGER_REGISTERS: [usize; 4] = [0x04, 0x09, 0x0E, 0x13];
+
+const NR50: u16 = 0xFF24;
+const NR51: u16 = 0xFF25;
+const NR52: u16 = 0xFF26;
+
+// a register write with the dot it happened on, for logging the music a game plays
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RegisterWrite {
+    pub cycle: u64,
+    // offset from FF10
+    pub register: u8,
+    pub value: u8,
+}
+
+#[derive(Clone)]
+pub struct APU {
+    enabled: bool,
+    square1: square::Square,
+    square2: square::Square,
+    wave: wave::Wave,
+    noise: noise::Noise,
+    nr50: u8,
+    nr51: u8,
+    // the next step the frame sequencer will run, 0-7
+    frame_step: u8,
+    frame_cycles: u32,
+    sample_cycles: u32,
+    // left and right summed over the cycles of the sample being built
+    accumulator: [f32; 2],
+    capacitor: [f32; 2],
+    // interleaved left and right, -1.0 to 1.0
+    samples: Vec<f32>,
+    // each channel on its own before panning and volume, only kept while something wants it
+    channel_capture: bool,
+    channel_accumulator: [f32; 4],
+    channel_capacitor: [f32; 4],
+    // interleaved channels 1-4
+    channel_samples: Vec<f32>,
+    // muted channels are left out of the mix but still show up in the per channel samples
+    muted: [bool; 4],
+    // the last value written to every register from FF10 to FF3F, most can't be read back
+    written: [u8; 0x30],
+    write_logging: bool,
+    // dots since logging started
+    log_cycles: u64,
+    writes: Vec<RegisterWrite>,
+}
+
+impl Default for APU {
+
+    fn default() -> APU {
+        APU {
+            enabled: true,
+            square1: square::Square::new(true),
+            square2: square::Square::new(false),
+            wave: wave::Wave::new(),
+            noise: noise::Noise::new(),
+            nr50: 0x00,
+            nr51: 0x00,
+            frame_step: 0,
+            frame_cycles: 0,
+            sample_cycles: 0,
+            accumulator: [0.0; 2],
+            capacitor: [0.0; 2],
+            samples: Vec::new(),
+            channel_capture: false,
+            channel_accumulator: [0.0; 4],
+            channel_capacitor: [0.0; 4],
+            channel_samples: Vec::new(),
+            muted: [false; 4],
+            written: [0; 0x30],
+            write_logging: false,
+            log_cycles: 0,
+            writes: Vec::new(),
+        }
+    }
+}
+
+impl APU {
+
+    pub fn new() -> APU {
+        Default::default()
+    }
+
+    pub fn read_register(&self, address: u16) -> u8 {
+        let value = match address {
+            0xFF10..=0xFF14 => self.square1.read_register(address - 0xFF10),
+            0xFF16..=0xFF19 => self.square2.read_register(address - 0xFF15),
+            0xFF1A..=0xFF1E => self.wave.read_register(address - 0xFF1A),
+            0xFF20..=0xFF23 => self.noise.read_register(address - 0xFF1F),
+            NR50 => self.nr50,
+            NR51 => self.nr51,
+            NR52 => {
+                (self.enabled as u8) << 7
+                    | (self.noise.enabled() as u8) << 3
+                    | (self.wave.enabled() as u8) << 2
+                    | (self.square2.enabled() as u8) << 1
+                    | self.square1.enabled() as u8
+            },
+            0xFF30..=0xFF3F => return self.wave.read_ram(address - 0xFF30),
+            _ => 0x00,
+        };
+
+        value | READ_MASKS[(address - 0xFF10) as usize]
+    }
+
+    pub fn write_register(&mut self, address: u16, data: u8) {
+        let register = (address - 0xFF10) as u8;
+        self.written[register as usize] = data;
+        if self.write_logging {
+            self.writes.push(RegisterWrite { cycle: self.log_cycles, register: register, value: data });
+        }
+
+        // wave RAM and NR52 are the only things that can be written while powered off
+        match address {
+            0xFF30..=0xFF3F => return self.wave.write_ram(address - 0xFF30, data),
+            NR52 => return self.set_power(data & 0x80 != 0),
+            _ if !self.enabled => return,
+            _ => {}
+        }
+
+        // length gets an extra clock when enabled while the next step won't clock it
+        let extra_length_clock = self.frame_step % 2 == 1;
+
+        match address {
+            0xFF10..=0xFF14 => self.square1.write_register(address - 0xFF10, data, extra_length_clock),
+            0xFF16..=0xFF19 => self.square2.write_register(address - 0xFF15, data, extra_length_clock),
+            0xFF1A..=0xFF1E => self.wave.write_register(address - 0xFF1A, data, extra_length_clock),
+            0xFF20..=0xFF23 => self.noise.write_register(address - 0xFF1F, data, extra_length_clock),
+            NR50 => self.nr50 = data,
+            NR51 => self.nr51 = data,
+            _ => {}
+        }
+    }
+
+    // powering off clears every register, powering on starts the frame sequencer from step 0
+    fn set_power(&mut self, enabled: bool) {
+        if enabled == self.enabled {
+            return;
+        }
+
+        if !enabled {
+            let wave_ram = self.wave.ram();
+            self.square1 = square::Square::new(true);
+            self.square2 = square::Square::new(false);
+            self.wave = wave::Wave::new();
+            self.wave.set_ram(wave_ram);
+            self.noise = noise::Noise::new();
+            self.nr50 = 0x00;
+            self.nr51 = 0x00;
+        } else {
+            self.frame_step = 0;
+            self.frame_cycles = 0;
+        }
+
+        self.enabled = enabled;
+    }
+
+    // t cycles at normal speed, like the PPU
+    pub fn tick(&mut self, cycles: u32) {
+        if self.write_logging {
+            self.log_cycles += cycles as u64;
+        }
+
+        let mut remaining = cycles;
+
+        while remaining > 0 {
+            let step = remaining.min(STEP_CYCLES);
+            remaining -= step;
+
+            if self.enabled {
+                self.frame_cycles += step;
+                if self.frame_cycles >= FRAME_SEQUENCER_CYCLES {
+                    self.frame_cycles -= FRAME_SEQUENCER_CYCLES;
+                    self.clock_frame_sequencer();
+                }
+
+                self.square1.tick(step);
+                self.square2.tick(step);
+                self.wave.tick(step);
+                self.noise.tick(step);
+            }
+
+            self.mix(step);
+        }
+    }
+
+    fn clock_frame_sequencer(&mut self) {
+        if self.frame_step % 2 == 0 {
+            self.square1.clock_length();
+            self.square2.clock_length();
+            self.wave.clock_length();
+            self.noise.clock_length();
+        }
+
+        if self.frame_step == 2 || self.frame_step == 6 {
+            self.square1.clock_sweep();
+        }
+
+        if self.frame_step == 7 {
+            self.square1.clock_envelope();
+            self.square2.clock_envelope();
+            self.noise.clock_envelope();
+        }
+
+        self.frame_step = (self.frame_step + 1) % 8;
+    }
+
+    // every channel's DAC output, -1.0 to 1.0, or 0.0 with the DAC off
+    fn channel_outputs(&self) -> [f32; 4] {
+        [
+            self.square1.output(),
+            self.square2.output(),
+            self.wave.output(),
+            self.noise.output(),
+        ]
+    }
+
+    fn mix(&mut self, cycles: u32) {
+        let outputs = self.channel_outputs();
+        let (mut left, mut right) = (0.0, 0.0);
+
+        // NR51 bits 4-7 send channels 1-4 to the left, bits 0-3 to the right
+        for (channel, output) in outputs.iter().enumerate() {
+            if self.muted[channel] {
+                continue;
+            }
+            if self.nr51 & (0x10 << channel) != 0 {
+                left += output;
+            }
+            if self.nr51 & (0x01 << channel) != 0 {
+                right += output;
+            }
+        }
+
+        let left_volume = (((self.nr50 >> 4) & 0x07) + 1) as f32 / 8.0;
+        let right_volume = ((self.nr50 & 0x07) + 1) as f32 / 8.0;
+
+        self.accumulator[0] += left * left_volume / 4.0 * cycles as f32;
+        self.accumulator[1] += right * right_volume / 4.0 * cycles as f32;
+        self.sample_cycles += cycles;
+
+        if self.channel_capture {
+            for (sum, output) in self.channel_accumulator.iter_mut().zip(outputs.iter()) {
+                *sum += output * cycles as f32;
+            }
+        }
+
+        if self.sample_cycles >= CYCLES_PER_SAMPLE {
+            let charge = HIGH_PASS_CHARGE.powi(self.sample_cycles as i32);
+
+            for side in 0..2 {
+                let input = self.accumulator[side] / self.sample_cycles as f32;
+                let output = input - self.capacitor[side];
+                self.capacitor[side] = input - output * charge;
+
+                self.samples.push(output);
+                self.accumulator[side] = 0.0;
+            }
+
+            if self.channel_capture {
+                for channel in 0..4 {
+                    let input = self.channel_accumulator[channel] / self.sample_cycles as f32;
+                    let output = input - self.channel_capacitor[channel];
+                    self.channel_capacitor[channel] = input - output * charge;
+
+                    self.channel_samples.push(output);
+                    self.channel_accumulator[channel] = 0.0;
+                }
+            }
+
+            self.sample_cycles = 0;
+
+            trim_samples(&mut self.samples, MAX_BUFFERED_SAMPLES);
+            trim_samples(&mut self.channel_samples, MAX_BUFFERED_SAMPLES * 2);
+        }
+    }
+
+    // moves every sample made since the last call onto the end of output
+    pub fn drain_samples(&mut self, output: &mut Vec<f32>) {
+        output.extend_from_slice(&self.samples);
+        self.samples.clear();
+    }
+
+    pub fn set_channel_capture(&mut self, enabled: bool) {
+        if enabled == self.channel_capture {
+            return;
+        }
+
+        self.channel_capture = enabled;
+        self.channel_accumulator = [0.0; 4];
+        self.channel_samples.clear();
+    }
+
+    // the log starts with writes that bring a powered on APU to the current settings, with
+    // nothing triggered, so it plays back the same from there
+    pub fn set_write_logging(&mut self, enabled: bool) {
+        self.write_logging = enabled;
+        self.log_cycles = 0;
+        self.writes.clear();
+
+        if !enabled {
+            return;
+        }
+
+        // FF15 and FF1F don't exist
+        let mut setup = vec![(0x16, (self.enabled as u8) << 7)];
+        if self.enabled {
+            for register in (0x00..0x16).filter(|&register| register != 0x05 && register != 0x0F) {
+                let value = self.written[register];
+                let value = if TRIGGER_REGISTERS.contains(&register) { value & 0x7F } else { value };
+                setup.push((register, value));
+            }
+        }
+        for (offset, &value) in self.wave.ram().iter().enumerate() {
+            setup.push((0x20 + offset, value));
+        }
+
+        for (register, value) in setup {
+            self.writes.push(RegisterWrite { cycle: 0, register: register as u8, value: value });
+        }
+    }
+
+    // moves every logged write since the last call onto the end of output, and returns the
+    // dot logging is up to
+    pub fn drain_register_writes(&mut self, output: &mut Vec<RegisterWrite>) -> u64 {
+        output.extend_from_slice(&self.writes);
+        self.writes.clear();
+
+        self.log_cycles
+    }
+
+    // channel from 0 to 3
+    pub fn set_channel_muted(&mut self, channel: usize, muted: bool) {
+        self.muted[channel] = muted;
+    }
+
+    pub fn channel_muted(&self, channel: usize) -> bool {
+        self.muted[channel]
+    }
+
+    // same as drain_samples, but four per sample with every channel on its own
+    pub fn drain_channel_samples(&mut self, output: &mut Vec<f32>) {
+        output.extend_from_slice(&self.channel_samples);
+        self.channel_samples.clear();
+    }
+
+    pub fn save_state(&self, state: &mut StateWriter) {
+        state.write_bool(self.enabled);
+        self.square1.save_state(state);
+        self.square2.save_state(state);
+        self.wave.save_state(state);
+        self.noise.save_state(state);
+        state.write_u8(self.nr50);
+        state.write_u8(self.nr51);
+        state.write_u8(self.frame_step);
+        state.write_u32(self.frame_cycles);
+    }
+
+    pub fn load_state(&mut self, state: &mut StateReader) -> StateResult<()> {
+        self.enabled = state.read_bool()?;
+        self.square1.load_state(state)?;
+        self.square2.load_state(state)?;
+        self.wave.load_state(state)?;
+        self.noise.load_state(state)?;
+        self.nr50 = state.read_u8()?;
+        self.nr51 = state.read_u8()?;
+        self.frame_step = state.read_u8()? % 8;
+        self.frame_cycles = state.read_u32()? % FRAME_SEQUENCER_CYCLES;
+
+        // whatever was waiting to be played belongs to the old timeline
+        self.samples.clear();
+        self.channel_samples.clear();
+
+        Ok(())
+    }
+}
+
+// counts down to silence the channel, clocked at 256 Hz while enabled
+#[derive(Clone)]
+struct Length {
+    counter: u16,
+    max: u16,
+    enabled: bool,
+}
+
+impl Length {
+
+    fn new(max: u16) -> Length {
+        Length {
+            counter: 0,
+            max: max,
+            enabled: false,
+        }
+    }
+
+    fn load(&mut self, value: u16) {
+        self.counter = self.max - value;
+    }
+
+    // true when the counter just ran out and the channel has to stop
+    fn clock(&mut self) -> bool {
+        if !self.enabled || self.counter == 0 {
+            return false;
+        }
+
+        self.counter -= 1;
+        self.counter == 0
+    }
+
+    // handles the length enable bit and trigger of an NRx4 write, returns true if the channel
+    // has to be turned off
+    fn write_control(&mut self, enable: bool, trigger: bool, extra_clock: bool) -> bool {
+        let was_enabled = self.enabled;
+        self.enabled = enable;
+        let mut disable = false;
+
+        if !was_enabled && enable && extra_clock && self.counter != 0 {
+            self.counter -= 1;
+            disable = self.counter == 0 && !trigger;
+        }
+
+        if trigger && self.counter == 0 {
+            self.counter = self.max;
+            if enable && extra_clock {
+                self.counter -= 1;
+            }
+        }
+
+        disable
+    }
+
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write_u16(self.counter);
+        state.write_bool(self.enabled);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> StateResult<()> {
+        self.counter = state.read_u16()?.min(self.max);
+        self.enabled = state.read_bool()?;
+
+        Ok(())
+    }
+}
+
+// NRx2: starting volume in bits 4-7, direction in bit 3 and period in bits 0-2
+#[derive(Clone)]
+struct Envelope {
+    register: u8,
+    volume: u8,
+    timer: u8,
+}
+
+impl Envelope {
+
+    fn new() -> Envelope {
+        Envelope {
+            register: 0x00,
+            volume: 0,
+            timer: 0,
+        }
+    }
+
+    // with the starting volume at 0 and the direction down the DAC is off
+    fn dac_enabled(&self) -> bool {
+        self.register & 0xF8 != 0
+    }
+
+    fn period(&self) -> u8 {
+        self.register & 0x07
+    }
+
+    // a period of 0 counts as 8
+    fn reload_timer(&mut self) {
+        self.timer = if self.period() == 0 { 8 } else { self.period() };
+    }
+
+    fn trigger(&mut self) {
+        self.volume = self.register >> 4;
+        self.reload_timer();
+    }
+
+    fn clock(&mut self) {
+        if self.period() == 0 {
+            return;
+        }
+
+        // a period set before the channel was ever triggered, or after power off, finds the
+        // timer at 0. it starts counting from there instead of wrapping around
+        if self.timer == 0 {
+            self.reload_timer();
+            return;
+        }
+
+        self.timer -= 1;
+        if self.timer > 0 {
+            return;
+        }
+        self.reload_timer();
+
+        if self.register & 0x08 != 0 && self.volume < 15 {
+            self.volume += 1;
+        } else if self.register & 0x08 == 0 && self.volume > 0 {
+            self.volume -= 1;
+        }
+    }
+
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write_u8(self.register);
+        state.write_u8(self.volume);
+        state.write_u8(self.timer);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> StateResult<()> {
+        self.register = state.read_u8()?;
+        self.volume = state.read_u8()? & 0x0F;
+        self.timer = state.read_u8()?.min(8);
+
+        Ok(())
+    }
+}
+
+// drops the oldest half once a buffer nobody is draining gets past max
+fn trim_samples(samples: &mut Vec<f32>, max: usize) {
+    if samples.len() > max {
+        let excess = samples.len() - max / 2;
+        samples.drain(..excess);
+    }
+}
+
+// what the DAC makes of a digital value from 0 to 15
+fn dac_output(digital: u8) -> f32 {
+    digital as f32 / 7.5 - 1.0
+}
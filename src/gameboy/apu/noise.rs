@@ -0,0 +1,144 @@
+// Noise channel 4, a 15-bit LFSR (or 7-bit with NR43 bit 3 set). Registers are passed in as
+// offsets from NR40, which doesn't exist.
+
+use gameboy::state::{ StateReader, StateResult, StateWriter };
+
+use super::{ Envelope, Length, dac_output };
+
+const DIVISORS: [u32; 8] = [8, 16, 32, 48, 64, 80, 96, 112];
+
+#[derive(Clone)]
+pub struct Noise {
+    enabled: bool,
+    // NR43: clock shift in bits 4-7, width in bit 3, divisor code in bits 0-2
+    register: u8,
+    timer: u32,
+    lfsr: u16,
+    length: Length,
+    envelope: Envelope,
+}
+
+impl Noise {
+
+    pub fn new() -> Noise {
+        Noise {
+            enabled: false,
+            register: 0x00,
+            timer: 0,
+            lfsr: 0x7FFF,
+            length: Length::new(64),
+            envelope: Envelope::new(),
+        }
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.enabled
+    }
+
+    pub fn read_register(&self, offset: u16) -> u8 {
+        match offset {
+            2 => self.envelope.register,
+            3 => self.register,
+            4 => (self.length.enabled as u8) << 6,
+            _ => 0xFF,
+        }
+    }
+
+    pub fn write_register(&mut self, offset: u16, data: u8, extra_length_clock: bool) {
+        match offset {
+            1 => self.length.load((data & 0x3F) as u16),
+            2 => {
+                self.envelope.register = data;
+                if !self.envelope.dac_enabled() {
+                    self.enabled = false;
+                }
+            },
+            3 => self.register = data,
+            4 => {
+                let trigger = data & 0x80 != 0;
+                if self.length.write_control(data & 0x40 != 0, trigger, extra_length_clock) {
+                    self.enabled = false;
+                }
+
+                if trigger {
+                    self.enabled = self.envelope.dac_enabled();
+                    self.timer = self.period();
+                    self.lfsr = 0x7FFF;
+                    self.envelope.trigger();
+                }
+            },
+            _ => {}
+        }
+    }
+
+    fn period(&self) -> u32 {
+        DIVISORS[(self.register & 0x07) as usize] << (self.register >> 4)
+    }
+
+    pub fn tick(&mut self, cycles: u32) {
+        // shifts of 14 and 15 stop the LFSR
+        if !self.enabled || self.register >> 4 >= 14 {
+            return;
+        }
+
+        let mut cycles = cycles;
+        while cycles >= self.timer {
+            cycles -= self.timer;
+            self.timer = self.period();
+            self.clock_lfsr();
+        }
+
+        self.timer -= cycles;
+    }
+
+    // XOR of the low two bits goes into bit 14, and bit 6 too in 7-bit mode
+    fn clock_lfsr(&mut self) {
+        let bit = (self.lfsr ^ (self.lfsr >> 1)) & 1;
+        self.lfsr = (self.lfsr >> 1) | (bit << 14);
+
+        if self.register & 0x08 != 0 {
+            self.lfsr = (self.lfsr & !0x40) | (bit << 6);
+        }
+    }
+
+    pub fn clock_length(&mut self) {
+        if self.length.clock() {
+            self.enabled = false;
+        }
+    }
+
+    pub fn clock_envelope(&mut self) {
+        self.envelope.clock();
+    }
+
+    pub fn output(&self) -> f32 {
+        if !self.envelope.dac_enabled() {
+            return 0.0;
+        }
+
+        // the output is bit 0 inverted
+        let digital = if self.enabled && self.lfsr & 1 == 0 { self.envelope.volume } else { 0 };
+
+        dac_output(digital)
+    }
+
+    pub fn save_state(&self, state: &mut StateWriter) {
+        state.write_bool(self.enabled);
+        state.write_u8(self.register);
+        state.write_u32(self.timer);
+        state.write_u16(self.lfsr);
+        self.length.save_state(state);
+        self.envelope.save_state(state);
+    }
+
+    pub fn load_state(&mut self, state: &mut StateReader) -> StateResult<()> {
+        self.enabled = state.read_bool()?;
+        self.register = state.read_u8()?;
+        self.timer = state.read_u32()?;
+        self.lfsr = state.read_u16()? & 0x7FFF;
+        self.length.load_state(state)?;
+        self.envelope.load_state(state)?;
+
+        Ok(())
+    }
+}
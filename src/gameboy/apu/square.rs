@@ -0,0 +1,297 @@
+// Square channels 1 and 2. Registers are passed in as offsets from NRx0, channel 2 has no
+// sweep so its NR20 doesn't exist.
+
+use gameboy::state::{ StateReader, StateResult, StateWriter };
+
+use super::{ Envelope, Length, dac_output };
+
+// 12.5%, 25%, 50% and 75%
+const DUTY_PATTERNS: [u8; 4] = [0b00000001, 0b10000001, 0b10000111, 0b01111110];
+
+#[derive(Clone)]
+pub struct Square {
+    enabled: bool,
+    sweep: Option<Sweep>,
+    duty: u8,
+    duty_position: u8,
+    frequency: u16,
+    timer: u32,
+    length: Length,
+    envelope: Envelope,
+}
+
+impl Square {
+
+    pub fn new(with_sweep: bool) -> Square {
+        Square {
+            enabled: false,
+            sweep: if with_sweep { Some(Sweep::new()) } else { None },
+            duty: 0,
+            duty_position: 0,
+            frequency: 0,
+            timer: 0,
+            length: Length::new(64),
+            envelope: Envelope::new(),
+        }
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.enabled
+    }
+
+    pub fn read_register(&self, offset: u16) -> u8 {
+        match offset {
+            0 => self.sweep.as_ref().map(|sweep| sweep.register).unwrap_or(0xFF),
+            1 => self.duty << 6,
+            2 => self.envelope.register,
+            4 => (self.length.enabled as u8) << 6,
+            _ => 0xFF,
+        }
+    }
+
+    pub fn write_register(&mut self, offset: u16, data: u8, extra_length_clock: bool) {
+        match offset {
+            0 => {
+                if let Some(ref mut sweep) = self.sweep {
+                    // going from subtract back to add after a subtraction was used kills the channel
+                    if sweep.write(data) {
+                        self.enabled = false;
+                    }
+                }
+            },
+            1 => {
+                self.duty = data >> 6;
+                self.length.load((data & 0x3F) as u16);
+            },
+            2 => {
+                self.envelope.register = data;
+                if !self.envelope.dac_enabled() {
+                    self.enabled = false;
+                }
+            },
+            3 => self.frequency = (self.frequency & 0x0700) | data as u16,
+            4 => {
+                self.frequency = (self.frequency & 0x00FF) | ((data as u16 & 0x07) << 8);
+
+                let trigger = data & 0x80 != 0;
+                if self.length.write_control(data & 0x40 != 0, trigger, extra_length_clock) {
+                    self.enabled = false;
+                }
+
+                if trigger {
+                    self.trigger();
+                }
+            },
+            _ => {}
+        }
+    }
+
+    // the duty position carries on from wherever it was
+    fn trigger(&mut self) {
+        self.enabled = self.envelope.dac_enabled();
+        self.timer = self.period();
+        self.envelope.trigger();
+
+        if let Some(ref mut sweep) = self.sweep {
+            if sweep.trigger(self.frequency) {
+                self.enabled = false;
+            }
+        }
+    }
+
+    fn period(&self) -> u32 {
+        (2048 - self.frequency as u32) * 4
+    }
+
+    pub fn tick(&mut self, cycles: u32) {
+        let mut cycles = cycles;
+
+        while cycles >= self.timer {
+            cycles -= self.timer;
+            self.timer = self.period();
+            self.duty_position = (self.duty_position + 1) % 8;
+        }
+
+        self.timer -= cycles;
+    }
+
+    pub fn clock_length(&mut self) {
+        if self.length.clock() {
+            self.enabled = false;
+        }
+    }
+
+    pub fn clock_envelope(&mut self) {
+        self.envelope.clock();
+    }
+
+    pub fn clock_sweep(&mut self) {
+        let result = match self.sweep {
+            Some(ref mut sweep) => sweep.clock(),
+            None => return,
+        };
+
+        match result {
+            Some(SweepResult::Overflow) => self.enabled = false,
+            Some(SweepResult::Frequency(frequency)) => self.frequency = frequency,
+            None => {}
+        }
+    }
+
+    pub fn output(&self) -> f32 {
+        if !self.envelope.dac_enabled() {
+            return 0.0;
+        }
+
+        let high = DUTY_PATTERNS[self.duty as usize] & (0x80 >> self.duty_position) != 0;
+        let digital = if self.enabled && high { self.envelope.volume } else { 0 };
+
+        dac_output(digital)
+    }
+
+    pub fn save_state(&self, state: &mut StateWriter) {
+        state.write_bool(self.enabled);
+        if let Some(ref sweep) = self.sweep {
+            sweep.save_state(state);
+        }
+        state.write_u8(self.duty);
+        state.write_u8(self.duty_position);
+        state.write_u16(self.frequency);
+        state.write_u32(self.timer);
+        self.length.save_state(state);
+        self.envelope.save_state(state);
+    }
+
+    pub fn load_state(&mut self, state: &mut StateReader) -> StateResult<()> {
+        self.enabled = state.read_bool()?;
+        if let Some(ref mut sweep) = self.sweep {
+            sweep.load_state(state)?;
+        }
+        self.duty = state.read_u8()? & 0x03;
+        self.duty_position = state.read_u8()? % 8;
+        self.frequency = state.read_u16()? & 0x07FF;
+        self.timer = state.read_u32()?.min(self.period());
+        self.length.load_state(state)?;
+        self.envelope.load_state(state)?;
+
+        Ok(())
+    }
+}
+
+enum SweepResult {
+    Frequency(u16),
+    Overflow,
+}
+
+// NR10: period in bits 4-6, subtract in bit 3, shift in bits 0-2
+#[derive(Clone)]
+struct Sweep {
+    register: u8,
+    enabled: bool,
+    timer: u8,
+    shadow: u16,
+    // set once a subtraction happens, clearing the subtract bit after that stops the channel
+    subtracted: bool,
+}
+
+impl Sweep {
+
+    fn new() -> Sweep {
+        Sweep {
+            register: 0x00,
+            enabled: false,
+            timer: 8,
+            shadow: 0,
+            subtracted: false,
+        }
+    }
+
+    fn period(&self) -> u8 {
+        (self.register >> 4) & 0x07
+    }
+
+    fn subtract(&self) -> bool {
+        self.register & 0x08 != 0
+    }
+
+    fn shift(&self) -> u8 {
+        self.register & 0x07
+    }
+
+    // returns true if the channel has to be turned off
+    fn write(&mut self, data: u8) -> bool {
+        let was_subtracting = self.subtract();
+        self.register = data;
+
+        was_subtracting && !self.subtract() && self.subtracted
+    }
+
+    // returns true if the first overflow check already failed
+    fn trigger(&mut self, frequency: u16) -> bool {
+        self.shadow = frequency;
+        self.timer = if self.period() == 0 { 8 } else { self.period() };
+        self.enabled = self.period() != 0 || self.shift() != 0;
+        self.subtracted = false;
+
+        self.shift() != 0 && self.calculate() > 2047
+    }
+
+    fn calculate(&mut self) -> u16 {
+        let delta = self.shadow >> self.shift();
+
+        if self.subtract() {
+            self.subtracted = true;
+            self.shadow - delta
+        } else {
+            self.shadow + delta
+        }
+    }
+
+    fn clock(&mut self) -> Option<SweepResult> {
+        self.timer -= 1;
+        if self.timer > 0 {
+            return None;
+        }
+        self.timer = if self.period() == 0 { 8 } else { self.period() };
+
+        if !self.enabled || self.period() == 0 {
+            return None;
+        }
+
+        let frequency = self.calculate();
+        if frequency > 2047 {
+            return Some(SweepResult::Overflow);
+        }
+
+        if self.shift() == 0 {
+            return None;
+        }
+
+        self.shadow = frequency;
+
+        // the new frequency goes through the overflow check a second time but isn't written back
+        if self.calculate() > 2047 {
+            return Some(SweepResult::Overflow);
+        }
+
+        Some(SweepResult::Frequency(frequency))
+    }
+
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write_u8(self.register);
+        state.write_bool(self.enabled);
+        state.write_u8(self.timer);
+        state.write_u16(self.shadow);
+        state.write_bool(self.subtracted);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> StateResult<()> {
+        self.register = state.read_u8()?;
+        self.enabled = state.read_bool()?;
+        self.timer = state.read_u8()?.max(1);
+        self.shadow = state.read_u16()? & 0x07FF;
+        self.subtracted = state.read_bool()?;
+
+        Ok(())
+    }
+}
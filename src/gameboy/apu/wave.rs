@@ -0,0 +1,173 @@
+// Wave channel 3. Plays the 32 4-bit samples in wave RAM, high nibble first. Registers are
+// passed in as offsets from NR30.
+
+use gameboy::state::{ StateReader, StateResult, StateWriter };
+
+use super::{ Length, dac_output };
+
+const WAVE_RAM_SIZE: usize = 16;
+
+#[derive(Clone)]
+pub struct Wave {
+    enabled: bool,
+    dac_enabled: bool,
+    // NR32 bits 5-6: mute, 100%, 50% or 25%
+    volume_code: u8,
+    frequency: u16,
+    timer: u32,
+    position: u8,
+    // the last sample read, which is what plays until the next one
+    sample: u8,
+    length: Length,
+    ram: [u8; WAVE_RAM_SIZE],
+}
+
+impl Wave {
+
+    pub fn new() -> Wave {
+        Wave {
+            enabled: false,
+            dac_enabled: false,
+            volume_code: 0,
+            frequency: 0,
+            timer: 0,
+            position: 0,
+            sample: 0,
+            length: Length::new(256),
+            ram: [0; WAVE_RAM_SIZE],
+        }
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.enabled
+    }
+
+    pub fn read_register(&self, offset: u16) -> u8 {
+        match offset {
+            0 => (self.dac_enabled as u8) << 7,
+            2 => self.volume_code << 5,
+            4 => (self.length.enabled as u8) << 6,
+            _ => 0xFF,
+        }
+    }
+
+    pub fn write_register(&mut self, offset: u16, data: u8, extra_length_clock: bool) {
+        match offset {
+            0 => {
+                self.dac_enabled = data & 0x80 != 0;
+                if !self.dac_enabled {
+                    self.enabled = false;
+                }
+            },
+            1 => self.length.load(data as u16),
+            2 => self.volume_code = (data >> 5) & 0x03,
+            3 => self.frequency = (self.frequency & 0x0700) | data as u16,
+            4 => {
+                self.frequency = (self.frequency & 0x00FF) | ((data as u16 & 0x07) << 8);
+
+                let trigger = data & 0x80 != 0;
+                if self.length.write_control(data & 0x40 != 0, trigger, extra_length_clock) {
+                    self.enabled = false;
+                }
+
+                if trigger {
+                    // playback restarts from the first sample, the old one keeps playing until then
+                    self.enabled = self.dac_enabled;
+                    self.timer = self.period();
+                    self.position = 0;
+                }
+            },
+            _ => {}
+        }
+    }
+
+    // while the channel plays, the CPU only gets at the byte being played
+    pub fn read_ram(&self, offset: u16) -> u8 {
+        if self.enabled {
+            return self.ram[self.position as usize / 2];
+        }
+
+        self.ram[offset as usize]
+    }
+
+    pub fn write_ram(&mut self, offset: u16, data: u8) {
+        let idx = if self.enabled { self.position as usize / 2 } else { offset as usize };
+        self.ram[idx] = data;
+    }
+
+    pub fn ram(&self) -> [u8; WAVE_RAM_SIZE] {
+        self.ram
+    }
+
+    pub fn set_ram(&mut self, ram: [u8; WAVE_RAM_SIZE]) {
+        self.ram = ram;
+    }
+
+    fn period(&self) -> u32 {
+        (2048 - self.frequency as u32) * 2
+    }
+
+    pub fn tick(&mut self, cycles: u32) {
+        if !self.enabled {
+            return;
+        }
+
+        let mut cycles = cycles;
+        while cycles >= self.timer {
+            cycles -= self.timer;
+            self.timer = self.period();
+            self.position = (self.position + 1) % 32;
+
+            let byte = self.ram[self.position as usize / 2];
+            self.sample = if self.position % 2 == 0 { byte >> 4 } else { byte & 0x0F };
+        }
+
+        self.timer -= cycles;
+    }
+
+    pub fn clock_length(&mut self) {
+        if self.length.clock() {
+            self.enabled = false;
+        }
+    }
+
+    pub fn output(&self) -> f32 {
+        if !self.dac_enabled {
+            return 0.0;
+        }
+
+        let digital = match self.volume_code {
+            _ if !self.enabled => 0,
+            0 => 0,
+            code => self.sample >> (code - 1),
+        };
+
+        dac_output(digital)
+    }
+
+    pub fn save_state(&self, state: &mut StateWriter) {
+        state.write_bool(self.enabled);
+        state.write_bool(self.dac_enabled);
+        state.write_u8(self.volume_code);
+        state.write_u16(self.frequency);
+        state.write_u32(self.timer);
+        state.write_u8(self.position);
+        state.write_u8(self.sample);
+        self.length.save_state(state);
+        state.write_bytes(&self.ram);
+    }
+
+    pub fn load_state(&mut self, state: &mut StateReader) -> StateResult<()> {
+        self.enabled = state.read_bool()?;
+        self.dac_enabled = state.read_bool()?;
+        self.volume_code = state.read_u8()? & 0x03;
+        self.frequency = state.read_u16()? & 0x07FF;
+        self.timer = state.read_u32()?.min(self.period());
+        self.position = state.read_u8()? % 32;
+        self.sample = state.read_u8()? & 0x0F;
+        self.length.load_state(state)?;
+        state.read_bytes(&mut self.ram)?;
+
+        Ok(())
+    }
+}
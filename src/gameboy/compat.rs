@@ -0,0 +1,239 @@
+// When a CGB runs a cartridge without the CGB flag, the boot ROM colorizes it: games published
+// by Nintendo are looked up by a checksum of their title, everything else gets a default set,
+// and holding a direction (plus optionally A or B) while the logo plays picks one of 12 sets.
+//
+// Each set is three palettes of 4 colors: background, sprite palette 0 and sprite palette 1.
+// The DMG palette registers then pick shades out of these like they would on a DMG.
+
+pub type CompatPalettes = [[u16; 4]; 3];
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum CompatCombo {
+    Up,
+    UpA,
+    UpB,
+    Left,
+    LeftA,
+    LeftB,
+    Down,
+    DownA,
+    DownB,
+    Right,
+    RightA,
+    RightB,
+}
+
+impl CompatCombo {
+
+    pub fn from_name(name: &str) -> Option<CompatCombo> {
+        match name {
+            "up" => Some(CompatCombo::Up),
+            "up_a" => Some(CompatCombo::UpA),
+            "up_b" => Some(CompatCombo::UpB),
+            "left" => Some(CompatCombo::Left),
+            "left_a" => Some(CompatCombo::LeftA),
+            "left_b" => Some(CompatCombo::LeftB),
+            "down" => Some(CompatCombo::Down),
+            "down_a" => Some(CompatCombo::DownA),
+            "down_b" => Some(CompatCombo::DownB),
+            "right" => Some(CompatCombo::Right),
+            "right_a" => Some(CompatCombo::RightA),
+            "right_b" => Some(CompatCombo::RightB),
+            _ => None,
+        }
+    }
+}
+
+// the boot ROM's own colors in 15-bit form, 30 palettes of 4 back to back
+const BOOT_COLORS: [u16; 120] = [
+    0x7FFF, 0x32BF, 0x00D0, 0x0000,
+    0x639F, 0x4279, 0x15B0, 0x04CB,
+    0x7FFF, 0x6E31, 0x454A, 0x0000,
+    0x7FFF, 0x1BEF, 0x0200, 0x0000,
+    0x7FFF, 0x421F, 0x1CF2, 0x0000,
+    0x7FFF, 0x5294, 0x294A, 0x0000,
+    0x7FFF, 0x03FF, 0x012F, 0x0000,
+    0x7FFF, 0x03EF, 0x01D6, 0x0000,
+    0x7FFF, 0x42B5, 0x3DC8, 0x0000,
+    0x7E74, 0x03FF, 0x0180, 0x0000,
+    0x67FF, 0x77AC, 0x1A13, 0x2D6B,
+    0x7ED6, 0x4BFF, 0x2175, 0x0000,
+    0x53FF, 0x4A5F, 0x7E52, 0x0000,
+    0x4FFF, 0x7ED2, 0x3A4C, 0x1CE0,
+    0x03ED, 0x7FFF, 0x255F, 0x0000,
+    0x036A, 0x021F, 0x03FF, 0x7FFF,
+    0x7FFF, 0x01DF, 0x0112, 0x0000,
+    0x231F, 0x035F, 0x00F2, 0x0009,
+    0x7FFF, 0x03EA, 0x011F, 0x0000,
+    0x299F, 0x001A, 0x000C, 0x0000,
+    0x7FFF, 0x027F, 0x001F, 0x0000,
+    0x7FFF, 0x03E0, 0x0206, 0x0120,
+    0x7FFF, 0x7EEB, 0x001F, 0x7C00,
+    0x7FFF, 0x3FFF, 0x7E00, 0x001F,
+    0x7FFF, 0x03FF, 0x001F, 0x0000,
+    0x03FF, 0x001F, 0x000C, 0x0000,
+    0x7FFF, 0x033F, 0x0193, 0x0000,
+    0x0000, 0x4200, 0x037F, 0x7FFF,
+    0x7FFF, 0x7E8C, 0x7C00, 0x0000,
+    0x7FFF, 0x1BEF, 0x6180, 0x0000,
+];
+
+// (sprite palette 0, sprite palette 1, background) as the color each starts at in BOOT_COLORS.
+// most start on a palette, the few that don't really are misaligned in the boot ROM
+const COMBINATIONS: [(usize, usize, usize); 51] = [
+    (4 * 4, 4 * 4, 29 * 4),
+    (18 * 4, 18 * 4, 18 * 4),
+    (20 * 4, 20 * 4, 20 * 4),
+    (24 * 4, 24 * 4, 24 * 4),
+    (9 * 4, 9 * 4, 9 * 4),
+    (0, 0, 0),
+    (27 * 4, 27 * 4, 27 * 4),
+    (5 * 4, 5 * 4, 5 * 4),
+    (12 * 4, 12 * 4, 12 * 4),
+    (26 * 4, 26 * 4, 26 * 4),
+    (16 * 4, 8 * 4, 8 * 4),
+    (4 * 4, 28 * 4, 28 * 4),
+    (4 * 4, 2 * 4, 2 * 4),
+    (3 * 4, 4 * 4, 4 * 4),
+    (4 * 4, 29 * 4, 29 * 4),
+    (28 * 4, 4 * 4, 28 * 4),
+    (2 * 4, 17 * 4, 2 * 4),
+    (16 * 4, 16 * 4, 8 * 4),
+    (4 * 4, 4 * 4, 7 * 4),
+    (4 * 4, 4 * 4, 18 * 4),
+    (4 * 4, 4 * 4, 20 * 4),
+    (19 * 4, 19 * 4, 9 * 4),
+    (4 * 4 - 1, 4 * 4 - 1, 11 * 4),
+    (17 * 4, 17 * 4, 2 * 4),
+    (4 * 4, 4 * 4, 2 * 4),
+    (4 * 4, 4 * 4, 3 * 4),
+    (28 * 4, 28 * 4, 0),
+    (3 * 4, 3 * 4, 0),
+    (0, 0, 4),
+    (18 * 4, 22 * 4, 18 * 4),
+    (20 * 4, 22 * 4, 20 * 4),
+    (24 * 4, 22 * 4, 24 * 4),
+    (16 * 4, 22 * 4, 8 * 4),
+    (17 * 4, 4 * 4, 13 * 4),
+    (28 * 4 - 1, 0, 14 * 4),
+    (28 * 4 - 1, 4 * 4, 15 * 4),
+    (19 * 4, 22 * 4, 9 * 4),
+    (16 * 4, 28 * 4, 10 * 4),
+    (4 * 4, 23 * 4, 28 * 4),
+    (17 * 4, 22 * 4, 2 * 4),
+    (4 * 4, 0, 2 * 4),
+    (4 * 4, 28 * 4, 3 * 4),
+    (28 * 4, 3 * 4, 0),
+    (3 * 4, 28 * 4, 4 * 4),
+    (21 * 4, 28 * 4, 4 * 4),
+    (3 * 4, 28 * 4, 0),
+    (25 * 4, 3 * 4, 28 * 4),
+    (0, 28 * 4, 8 * 4),
+    (4 * 4, 3 * 4, 28 * 4),
+    (28 * 4, 3 * 4, 6 * 4),
+    (4 * 4, 28 * 4, 29 * 4),
+];
+
+// sums of the 16 title bytes the boot ROM knows. the last 14 are checksums shared by more than
+// one title, for those the 4th letter of the title has to match too
+const TITLE_CHECKSUMS: [u8; 79] = [
+    0x00, 0x88, 0x16, 0x36, 0xD1, 0xDB, 0xF2, 0x3C, 0x8C, 0x92, 0x3D, 0x5C, 0x58, 0xC9, 0x3E, 0x70,
+    0x1D, 0x59, 0x69, 0x19, 0x35, 0xA8, 0x14, 0xAA, 0x75, 0x95, 0x99, 0x34, 0x6F, 0x15, 0xFF, 0x97,
+    0x4B, 0x90, 0x17, 0x10, 0x39, 0xF7, 0xF6, 0xA2, 0x49, 0x4E, 0x43, 0x68, 0xE0, 0x8B, 0xF0, 0xCE,
+    0x0C, 0x29, 0xE8, 0xB7, 0x86, 0x9A, 0x52, 0x01, 0x9D, 0x71, 0x9C, 0xBD, 0x5D, 0x6D, 0x67, 0x3F,
+    0x6B, 0xB3, 0x46, 0x28, 0xA5, 0xC6, 0xD3, 0x27, 0x61, 0x18, 0x66, 0x6A, 0xBF, 0x0D, 0xF4,
+];
+
+const FIRST_SHARED_CHECKSUM: usize = 65;
+const SHARED_CHECKSUMS: usize = 14;
+
+// rows of 14 4th letters, one row for each title after the first that shares a checksum
+const FOURTH_LETTERS: &[u8; 29] = b"BEFAARBEKEK R-URAR INAILICE R";
+
+// the COMBINATIONS entry for each checksum, then for each of FOURTH_LETTERS
+const PALETTE_INDEXES: [u8; 94] = [
+    0, 4, 5, 35, 34, 3, 31, 15, 10, 5, 19, 36, 7, 37, 30, 44,
+    21, 32, 31, 20, 5, 33, 13, 14, 5, 29, 5, 18, 9, 3, 2, 26,
+    25, 25, 41, 42, 26, 45, 42, 45, 36, 38, 26, 42, 30, 41, 34, 34,
+    5, 42, 6, 5, 33, 25, 42, 42, 40, 2, 16, 25, 42, 42, 5, 0,
+    39, 36, 22, 25, 6, 32, 12, 36, 11, 39, 18, 39, 24, 31, 50, 17,
+    46, 6, 27, 0, 47, 41, 41, 0, 0, 19, 34, 23, 18, 29,
+];
+
+// the COMBINATIONS entry the boot ROM picks for each combo
+pub fn combo_palettes(combo: CompatCombo) -> CompatPalettes {
+    let index = match combo {
+        CompatCombo::Up => 5,
+        CompatCombo::UpA => 43,
+        CompatCombo::UpB => 28,
+        CompatCombo::Left => 48,
+        CompatCombo::LeftA => 40,
+        CompatCombo::LeftB => 7,
+        CompatCombo::Down => 8,
+        CompatCombo::DownA => 3,
+        CompatCombo::DownB => 49,
+        CompatCombo::Right => 1,
+        CompatCombo::RightA => 0,
+        CompatCombo::RightB => 6,
+    };
+
+    combination_palettes(index)
+}
+
+// picks the palettes the boot ROM would, header is the first 0x150 bytes of the cartridge
+pub fn select_palettes(header: &[u8], combo: Option<CompatCombo>) -> CompatPalettes {
+    if let Some(combo) = combo {
+        return combo_palettes(combo);
+    }
+
+    // everything else gets the same colors as right + A
+    if !licensed_by_nintendo(header) {
+        return combination_palettes(0);
+    }
+
+    combination_palettes(title_palette_index(&header[0x134..0x144]))
+}
+
+fn combination_palettes(index: usize) -> CompatPalettes {
+    let (sprites_0, sprites_1, background) = COMBINATIONS[index];
+
+    [boot_colors(background), boot_colors(sprites_0), boot_colors(sprites_1)]
+}
+
+// unknown titles get entry 0, the same as unlicensed games
+fn title_palette_index(title: &[u8]) -> usize {
+    let checksum = title.iter().fold(0u8, |sum, byte| sum.wrapping_add(*byte));
+
+    for (i, &entry) in TITLE_CHECKSUMS.iter().enumerate() {
+        if entry != checksum {
+            continue;
+        }
+        if i < FIRST_SHARED_CHECKSUM {
+            return PALETTE_INDEXES[i] as usize;
+        }
+
+        // each row of letters is another title with the same checksum
+        let mut letter = i - FIRST_SHARED_CHECKSUM;
+        while letter < FOURTH_LETTERS.len() {
+            if FOURTH_LETTERS[letter] == title[3] {
+                return PALETTE_INDEXES[FIRST_SHARED_CHECKSUM + letter] as usize;
+            }
+            letter += SHARED_CHECKSUMS;
+        }
+    }
+
+    0
+}
+
+fn boot_colors(start: usize) -> [u16; 4] {
+    [BOOT_COLORS[start], BOOT_COLORS[start + 1], BOOT_COLORS[start + 2], BOOT_COLORS[start + 3]]
+}
+
+// 0x14B is the old licensee code, 0x33 means the new two character code at 0x144 is used instead
+fn licensed_by_nintendo(header: &[u8]) -> bool {
+    match header[0x14B] {
+        0x01 => true,
+        0x33 => &header[0x144..0x146] == b"01",
+        _ => false,
+    }
+}
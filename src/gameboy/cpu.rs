@@ -1,5 +1,7 @@
 use gameboy::{ mmu, opcodes };
+use gameboy::state::{ StateReader, StateResult, StateWriter };
 
+#[derive(Clone)]
 struct Registers {
     a: u8,
     b: u8,
@@ -35,17 +37,24 @@ impl Default for Registers {
     }
 }
 
-#[derive(Default)]
+#[derive(Default, Clone)]
 struct Clock {
     m: u8,
     t: u8,
 }
 
+#[derive(Clone)]
 pub struct CPU {
     registers: Registers,
     clock: Clock,
     pc: u16,
     sp: u16,
+    halted: bool,
+    // interrupt master enable, and an EI waiting for the next instruction to finish
+    ime: bool,
+    ime_pending: bool,
+    // hung by an illegal opcode, nothing but a reset gets it going again
+    locked: bool,
 }
 
 impl Default for CPU {
@@ -56,6 +65,10 @@ impl Default for CPU {
             clock: Clock { ..Default::default() },
             pc: 0x100,
             sp: 0xFFFE,
+            halted: false,
+            ime: false,
+            ime_pending: false,
+            locked: false,
         }
     }
 }
@@ -66,9 +79,35 @@ impl CPU {
         Default::default()
     }
 
-    pub fn execute(&mut self, mmu: &mut mmu::MMU) {
+    // returns the number of t cycles the instruction took
+    pub fn execute(&mut self, mmu: &mut mmu::MMU) -> u8 {
+        if self.locked {
+            update_time_registers_and_clock(&mut self.registers, &mut self.clock, 1, 4);
+            return 4;
+        }
+
+        if self.halted {
+            if !mmu.interrupt_pending() {
+                update_time_registers_and_clock(&mut self.registers, &mut self.clock, 1, 4);
+                return 4;
+            }
+            self.halted = false;
+        }
+
+        if self.ime {
+            if let Some(vector) = mmu.acknowledge_interrupt() {
+                // like a CALL from the instruction that was about to run
+                self.ime = false;
+                mmu.push(&mut self.sp, (self.pc >> 8) as u8);
+                mmu.push(&mut self.sp, self.pc as u8);
+                self.pc = vector;
+                update_time_registers_and_clock(&mut self.registers, &mut self.clock, 5, 20);
+                return 20;
+            }
+        }
+
+        let enable_ime = self.ime_pending;
         let opcode = mmu.read(self.pc);
-        println!("opcode: {:x}", opcode);
 
         let (m, t) = match opcode {
             0x00 => {
@@ -82,22 +121,50 @@ impl CPU {
                 let bc = get_address(self.registers.b, self.registers.c);
                 opcodes::ld_mem_rr_r(&mut self.pc, mmu, bc, self.registers.a)
             },
+            0x03 => {
+                opcodes::inc_rr(&mut self.pc, &mut self.registers.b, &mut self.registers.c)
+            },
+            0x04 => {
+                opcodes::inc_r(&mut self.pc, &mut self.registers.b, &mut self.registers.f)
+            },
+            0x05 => {
+                opcodes::dec_r(&mut self.pc, &mut self.registers.b, &mut self.registers.f)
+            },
             0x06 => {
                 let n = get_n(&mut self.pc, mmu);
                 opcodes::ld_r_n(&mut self.pc, &mut self.registers.b, n)
             },
+            0x07 | 0x0F | 0x17 | 0x1F => {
+                opcodes::rotate_a(&mut self.pc, opcode >> 3, &mut self.registers.a, &mut self.registers.f)
+            },
             0x08 => {
                 let nn = get_nn(&mut self.pc, mmu, false);
                 opcodes::ld_mem_nn_sp(&mut self.pc, mmu, nn, self.sp)
             },
+            0x09 => {
+                let rr = get_address(self.registers.b, self.registers.c);
+                opcodes::add_hl_rr(&mut self.pc, &mut self.registers.h, &mut self.registers.l, rr, &mut self.registers.f)
+            },
             0x0A => {
                 let bc = get_address(self.registers.b, self.registers.c);
                 opcodes::ld_r_mem_rr(&mut self.pc, mmu, bc, &mut self.registers.a)
             },
+            0x0B => {
+                opcodes::dec_rr(&mut self.pc, &mut self.registers.b, &mut self.registers.c)
+            },
+            0x0C => {
+                opcodes::inc_r(&mut self.pc, &mut self.registers.c, &mut self.registers.f)
+            },
+            0x0D => {
+                opcodes::dec_r(&mut self.pc, &mut self.registers.c, &mut self.registers.f)
+            },
             0x0E => {
                 let n = get_n(&mut self.pc, mmu);
                 opcodes::ld_r_n(&mut self.pc, &mut self.registers.c, n)
             },
+            0x10 => {
+                opcodes::stop(&mut self.pc, mmu)
+            },
             0x11 => {
                 let nn = get_nn(&mut self.pc, mmu, false);
                 opcodes::ld_rr_nn(&mut self.pc, &mut self.registers.d, &mut self.registers.e, nn)
@@ -106,55 +173,144 @@ impl CPU {
                 let de = get_address(self.registers.d, self.registers.e);
                 opcodes::ld_mem_rr_r(&mut self.pc, mmu, de, self.registers.a)
             },
+            0x13 => {
+                opcodes::inc_rr(&mut self.pc, &mut self.registers.d, &mut self.registers.e)
+            },
+            0x14 => {
+                opcodes::inc_r(&mut self.pc, &mut self.registers.d, &mut self.registers.f)
+            },
+            0x15 => {
+                opcodes::dec_r(&mut self.pc, &mut self.registers.d, &mut self.registers.f)
+            },
             0x16 => {
                 let n = get_n(&mut self.pc, mmu);
                 opcodes::ld_r_n(&mut self.pc, &mut self.registers.d, n)
             },
+            0x18 => {
+                let n = get_n(&mut self.pc, mmu);
+                opcodes::jr_n(&mut self.pc, n)
+            },
+            0x19 => {
+                let rr = get_address(self.registers.d, self.registers.e);
+                opcodes::add_hl_rr(&mut self.pc, &mut self.registers.h, &mut self.registers.l, rr, &mut self.registers.f)
+            },
             0x1A => {
                 let de = get_address(self.registers.d, self.registers.e);
                 opcodes::ld_r_mem_rr(&mut self.pc, mmu, de, &mut self.registers.a)
             },
+            0x1B => {
+                opcodes::dec_rr(&mut self.pc, &mut self.registers.d, &mut self.registers.e)
+            },
+            0x1C => {
+                opcodes::inc_r(&mut self.pc, &mut self.registers.e, &mut self.registers.f)
+            },
+            0x1D => {
+                opcodes::dec_r(&mut self.pc, &mut self.registers.e, &mut self.registers.f)
+            },
             0x1E => {
                 let n = get_n(&mut self.pc, mmu);
                 opcodes::ld_r_n(&mut self.pc, &mut self.registers.e, n)
             },
+            0x20 | 0x28 | 0x30 | 0x38 => {
+                let n = get_n(&mut self.pc, mmu);
+                let condition = self.condition(opcode);
+                opcodes::jr_cc_n(&mut self.pc, condition, n)
+            },
             0x21 => {
                 let nn = get_nn(&mut self.pc, mmu, false);
                 opcodes::ld_rr_nn(&mut self.pc, &mut self.registers.h, &mut self.registers.l, nn)
             },
             0x22 => {
-                let hl = get_address(self.registers.h, self.registers.l);
-                opcodes::ld_mem_rr_inc_r(&mut self.pc, mmu, hl, self.registers.a)
+                opcodes::ld_mem_rr_inc_r(&mut self.pc, mmu, &mut self.registers.h, &mut self.registers.l, self.registers.a)
+            },
+            0x23 => {
+                opcodes::inc_rr(&mut self.pc, &mut self.registers.h, &mut self.registers.l)
+            },
+            0x24 => {
+                opcodes::inc_r(&mut self.pc, &mut self.registers.h, &mut self.registers.f)
+            },
+            0x25 => {
+                opcodes::dec_r(&mut self.pc, &mut self.registers.h, &mut self.registers.f)
             },
             0x26 => {
                 let n = get_n(&mut self.pc, mmu);
                 opcodes::ld_r_n(&mut self.pc, &mut self.registers.h, n)
             },
+            0x27 => {
+                opcodes::daa(&mut self.pc, &mut self.registers.a, &mut self.registers.f)
+            },
+            0x29 => {
+                let rr = get_address(self.registers.h, self.registers.l);
+                opcodes::add_hl_rr(&mut self.pc, &mut self.registers.h, &mut self.registers.l, rr, &mut self.registers.f)
+            },
             0x2A => {
-                let hl = get_address(self.registers.h, self.registers.l);
-                opcodes::ld_r_mem_rr_inc(&mut self.pc, mmu, hl, &mut self.registers.a)
+                opcodes::ld_r_mem_rr_inc(&mut self.pc, mmu, &mut self.registers.h, &mut self.registers.l, &mut self.registers.a)
+            },
+            0x2B => {
+                opcodes::dec_rr(&mut self.pc, &mut self.registers.h, &mut self.registers.l)
+            },
+            0x2C => {
+                opcodes::inc_r(&mut self.pc, &mut self.registers.l, &mut self.registers.f)
+            },
+            0x2D => {
+                opcodes::dec_r(&mut self.pc, &mut self.registers.l, &mut self.registers.f)
             },
             0x2E => {
                 let n = get_n(&mut self.pc, mmu);
                 opcodes::ld_r_n(&mut self.pc, &mut self.registers.l, n)
             },
+            0x2F => {
+                opcodes::cpl(&mut self.pc, &mut self.registers.a, &mut self.registers.f)
+            },
             0x31 => {
                 let nn = get_nn(&mut self.pc, mmu, false);
                 opcodes::ld_sp_nn(&mut self.pc, &mut self.sp, nn)
             },
             0x32 => {
+                opcodes::ld_mem_rr_dec_r(&mut self.pc, mmu, &mut self.registers.h, &mut self.registers.l, self.registers.a)
+            },
+            0x33 => {
+                opcodes::inc_sp(&mut self.pc, &mut self.sp)
+            },
+            0x34 => {
                 let hl = get_address(self.registers.h, self.registers.l);
-                opcodes::ld_mem_rr_dec_r(&mut self.pc, mmu, hl, self.registers.a)
+                opcodes::inc_mem_rr(&mut self.pc, mmu, hl, &mut self.registers.f)
             },
-            0x3A => {
+            0x35 => {
                 let hl = get_address(self.registers.h, self.registers.l);
-                opcodes::ld_r_mem_rr_dec(&mut self.pc, mmu, hl, &mut self.registers.a)
+                opcodes::dec_mem_rr(&mut self.pc, mmu, hl, &mut self.registers.f)
             },
             0x36 => {
                 let hl = get_address(self.registers.h, self.registers.l);
                 let n = get_n(&mut self.pc, mmu);
                 opcodes::ld_mem_rr_n(&mut self.pc, mmu, hl, n)
             },
+            0x37 => {
+                opcodes::scf(&mut self.pc, &mut self.registers.f)
+            },
+            0x39 => {
+                let rr = self.sp;
+                opcodes::add_hl_rr(&mut self.pc, &mut self.registers.h, &mut self.registers.l, rr, &mut self.registers.f)
+            },
+            0x3A => {
+                opcodes::ld_r_mem_rr_dec(&mut self.pc, mmu, &mut self.registers.h, &mut self.registers.l, &mut self.registers.a)
+            },
+            0x3B => {
+                opcodes::dec_sp(&mut self.pc, &mut self.sp)
+            },
+            0x3C => {
+                opcodes::inc_r(&mut self.pc, &mut self.registers.a, &mut self.registers.f)
+            },
+            0x3D => {
+                opcodes::dec_r(&mut self.pc, &mut self.registers.a, &mut self.registers.f)
+            },
+            0x3E => {
+                let n = get_n(&mut self.pc, mmu);
+                opcodes::ld_r_n(&mut self.pc, &mut self.registers.a, n)
+            },
+            0x3F => {
+                opcodes::ccf(&mut self.pc, &mut self.registers.f)
+            },
             0x40 => {
                 opcodes::ld_r1_r1(&mut self.pc, &mut self.registers.b)
             },
@@ -329,6 +485,9 @@ impl CPU {
                 let hl = get_address(self.registers.h, self.registers.l);
                 opcodes::ld_mem_rr_r(&mut self.pc, mmu, hl, self.registers.l)
             },
+            0x76 => {
+                opcodes::halt(&mut self.pc, &mut self.halted)
+            },
             0x77 => {
                 let hl = get_address(self.registers.h, self.registers.l);
                 opcodes::ld_mem_rr_r(&mut self.pc, mmu, hl, self.registers.a)
@@ -358,25 +517,83 @@ impl CPU {
             0x7F => {
                 opcodes::ld_r1_r1(&mut self.pc, &mut self.registers.a)
             },
-            0xAF => {
-                opcodes::xor_a(&mut self.pc, self.registers.a, &mut self.registers.f)
+            0x80..=0xBF => {
+                let op = (opcode >> 3) & 0x07;
+                match opcode & 0x07 {
+                    0x06 => {
+                        let hl = get_address(self.registers.h, self.registers.l);
+                        opcodes::alu_a_mem_rr(&mut self.pc, mmu, op, &mut self.registers.a, hl, &mut self.registers.f)
+                    },
+                    index => {
+                        let r = self.register(index);
+                        opcodes::alu_a_r(&mut self.pc, op, &mut self.registers.a, r, &mut self.registers.f)
+                    },
+                }
+            },
+            0xC0 | 0xC8 | 0xD0 | 0xD8 => {
+                let condition = self.condition(opcode);
+                opcodes::ret_cc(&mut self.pc, &mut self.sp, mmu, condition)
             },
             0xC1 => {
                 opcodes::pop_rr(&mut self.pc, mmu, &mut self.sp, &mut self.registers.b, & mut self.registers.c)
             },
+            0xC2 | 0xCA | 0xD2 | 0xDA => {
+                let nn = get_nn(&mut self.pc, mmu, true);
+                let condition = self.condition(opcode);
+                opcodes::jp_cc_nn(&mut self.pc, condition, nn)
+            },
             0xC3 => {
                 let nn = get_nn(&mut self.pc, mmu, true);
                 opcodes::jp_nn(&mut self.pc, nn)
             },
+            0xC4 | 0xCC | 0xD4 | 0xDC => {
+                let nn = get_nn(&mut self.pc, mmu, true);
+                let condition = self.condition(opcode);
+                opcodes::call_cc_nn(&mut self.pc, &mut self.sp, mmu, condition, nn)
+            },
             0xC5 => {
                 opcodes::push_rr(&mut self.pc, mmu, &mut self.sp, self.registers.b, self.registers.c)
             },
+            0xC6 | 0xCE | 0xD6 | 0xDE | 0xE6 | 0xEE | 0xF6 | 0xFE => {
+                let n = get_n(&mut self.pc, mmu);
+                opcodes::alu_a_n(&mut self.pc, (opcode >> 3) & 0x07, &mut self.registers.a, n, &mut self.registers.f)
+            },
+            0xC7 | 0xCF | 0xD7 | 0xDF | 0xE7 | 0xEF | 0xF7 | 0xFF => {
+                opcodes::rst(&mut self.pc, &mut self.sp, mmu, (opcode & 0x38) as u16)
+            },
+            0xC9 => {
+                opcodes::ret(&mut self.pc, &mut self.sp, mmu)
+            },
+            0xCB => {
+                let op = get_n(&mut self.pc, mmu);
+                let r = &mut self.registers;
+                match op & 0x07 {
+                    0x00 => opcodes::cb_r(&mut self.pc, op, &mut r.b, &mut r.f),
+                    0x01 => opcodes::cb_r(&mut self.pc, op, &mut r.c, &mut r.f),
+                    0x02 => opcodes::cb_r(&mut self.pc, op, &mut r.d, &mut r.f),
+                    0x03 => opcodes::cb_r(&mut self.pc, op, &mut r.e, &mut r.f),
+                    0x04 => opcodes::cb_r(&mut self.pc, op, &mut r.h, &mut r.f),
+                    0x05 => opcodes::cb_r(&mut self.pc, op, &mut r.l, &mut r.f),
+                    0x06 => {
+                        let hl = get_address(r.h, r.l);
+                        opcodes::cb_mem_rr(&mut self.pc, mmu, op, hl, &mut r.f)
+                    },
+                    _ => opcodes::cb_r(&mut self.pc, op, &mut r.a, &mut r.f),
+                }
+            },
+            0xCD => {
+                let nn = get_nn(&mut self.pc, mmu, true);
+                opcodes::call_nn(&mut self.pc, &mut self.sp, mmu, nn)
+            },
             0xD1 => {
                 opcodes::pop_rr(&mut self.pc, mmu, &mut self.sp, &mut self.registers.d, &mut self.registers.e)
             },
             0xD5 => {
                 opcodes::push_rr(&mut self.pc, mmu, &mut self.sp, self.registers.d, self.registers.e)
             },
+            0xD9 => {
+                opcodes::reti(&mut self.pc, &mut self.sp, mmu, &mut self.ime)
+            },
             0xE0 => {
                 let n = get_n(&mut self.pc, mmu);
                 opcodes::ld_mem_n_r(&mut self.pc, mmu, 0xFF00+(n as u16), self.registers.a)
@@ -390,6 +607,14 @@ impl CPU {
             0xE5 => {
                 opcodes::push_rr(&mut self.pc, mmu, &mut self.sp, self.registers.h, self.registers.l)
             },
+            0xE8 => {
+                let n = get_n(&mut self.pc, mmu);
+                opcodes::add_sp_n(&mut self.pc, &mut self.sp, n, &mut self.registers.f)
+            },
+            0xE9 => {
+                let hl = get_address(self.registers.h, self.registers.l);
+                opcodes::jp_hl(&mut self.pc, hl)
+            },
             0xEA => {
                 let nn = get_nn(&mut self.pc, mmu, true);
                 opcodes::ld_mem_nn_r(&mut self.pc, mmu, nn, self.registers.a)
@@ -399,10 +624,16 @@ impl CPU {
                 opcodes::ld_r_mem_n(&mut self.pc, mmu, n, &mut self.registers.a)
             },
             0xF1 => {
-                opcodes::pop_rr(&mut self.pc, mmu, &mut self.sp, &mut self.registers.a, &mut self.registers.f)
+                // the low nibble of f always reads 0
+                let (m, t) = opcodes::pop_rr(&mut self.pc, mmu, &mut self.sp, &mut self.registers.a, &mut self.registers.f);
+                self.registers.f &= 0xF0;
+                (m, t)
             },
             0xF2 => {
-                opcodes::ld_r1_mem_r2(&mut self.pc, mmu, &mut self.registers.c, self.registers.a)
+                opcodes::ld_r1_mem_r2(&mut self.pc, mmu, &mut self.registers.a, self.registers.c)
+            },
+            0xF3 => {
+                opcodes::di(&mut self.pc, &mut self.ime, &mut self.ime_pending)
             },
             0xF5 => {
                 opcodes::push_rr(&mut self.pc, mmu, &mut self.sp, self.registers.a, self.registers.f)
@@ -416,20 +647,105 @@ impl CPU {
                 opcodes::ld_sp_hl(&mut self.pc, &mut self.sp, hl)
             },
             0xFA => {
-                let rr = get_nn(&mut self.pc, mmu, true);
-                opcodes::ld_r_mem_rr(&mut self.pc, mmu, rr, &mut self.registers.a)
+                let nn = get_nn(&mut self.pc, mmu, true);
+                opcodes::ld_r_mem_nn(&mut self.pc, mmu, nn, &mut self.registers.a)
+            },
+            0xFB => {
+                opcodes::ei(&mut self.pc, &mut self.ime_pending)
             },
-            0xFF => {
-                opcodes::rst_38(&mut self.pc, &mut self.sp, mmu)
-            }
             _ => {
-                println!("{:x} not implemented yet", opcode);
-                self.pc += 1;
-                (0, 0)
+                opcodes::lock(&mut self.locked)
             }
         };
 
+        // EI takes effect after the instruction following it, unless that was a DI
+        if enable_ime && self.ime_pending {
+            self.ime = true;
+            self.ime_pending = false;
+        }
+
         update_time_registers_and_clock(&mut self.registers, &mut self.clock, m, t);
+
+        t
+    }
+
+    // registers by the number opcodes give them, 6 is (hl) and has to be read through the mmu
+    fn register(&self, index: u8) -> u8 {
+        match index {
+            0 => self.registers.b,
+            1 => self.registers.c,
+            2 => self.registers.d,
+            3 => self.registers.e,
+            4 => self.registers.h,
+            5 => self.registers.l,
+            _ => self.registers.a,
+        }
+    }
+
+    // bits 3-4 of conditional jumps, calls and returns: NZ Z NC C
+    fn condition(&self, opcode: u8) -> bool {
+        match (opcode >> 3) & 0x03 {
+            0 => self.registers.f & 0x80 == 0,
+            1 => self.registers.f & 0x80 != 0,
+            2 => self.registers.f & 0x10 == 0,
+            _ => self.registers.f & 0x10 != 0,
+        }
+    }
+
+    pub fn is_halted(&self) -> bool {
+        self.halted
+    }
+
+    pub fn pc(&self) -> u16 {
+        self.pc
+    }
+
+    pub fn set_sp(&mut self, sp: u16) {
+        self.sp = sp;
+    }
+
+    pub fn set_a(&mut self, a: u8) {
+        self.registers.a = a;
+    }
+
+    // starts running the routine at address as if it had been CALLed from return_address
+    pub fn call(&mut self, mmu: &mut mmu::MMU, address: u16, return_address: u16) {
+        mmu.push(&mut self.sp, (return_address >> 8) as u8);
+        mmu.push(&mut self.sp, return_address as u8);
+        self.pc = address;
+        self.halted = false;
+    }
+
+    pub fn save_state(&self, state: &mut StateWriter) {
+        let r = &self.registers;
+        for value in &[r.a, r.b, r.c, r.d, r.e, r.f, r.h, r.l, r.m, r.t, self.clock.m, self.clock.t] {
+            state.write_u8(*value);
+        }
+        state.write_u16(self.pc);
+        state.write_u16(self.sp);
+        state.write_bool(self.halted);
+        state.write_bool(self.ime);
+        state.write_bool(self.ime_pending);
+        state.write_bool(self.locked);
+    }
+
+    pub fn load_state(&mut self, state: &mut StateReader) -> StateResult<()> {
+        {
+            let r = &mut self.registers;
+            for value in &mut [&mut r.a, &mut r.b, &mut r.c, &mut r.d, &mut r.e, &mut r.f, &mut r.h, &mut r.l, &mut r.m, &mut r.t] {
+                **value = state.read_u8()?;
+            }
+        }
+        self.clock.m = state.read_u8()?;
+        self.clock.t = state.read_u8()?;
+        self.pc = state.read_u16()?;
+        self.sp = state.read_u16()?;
+        self.halted = state.read_bool()?;
+        self.ime = state.read_bool()?;
+        self.ime_pending = state.read_bool()?;
+        self.locked = state.read_bool()?;
+
+        Ok(())
     }
 
     pub fn print_registers(&mut self) {
@@ -440,8 +756,8 @@ impl CPU {
 fn update_time_registers_and_clock(registers: &mut Registers, clock: &mut Clock, m: u8, t: u8) {
     registers.m = m;
     registers.t = t;
-    clock.m += m;
-    clock.t += t;
+    clock.m = clock.m.wrapping_add(m);
+    clock.t = clock.t.wrapping_add(t);
 }
 
 fn get_address(r1: u8, r2: u8) -> u16 {
@@ -464,3 +780,137 @@ fn get_nn(pc: &mut u16, mmu: &mut mmu::MMU, little_endian: bool) -> u16 {
         false => high_byte + low_byte
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // places each piece of code in ROM bank 0, the CPU starts at 0x100
+    fn load(code: &[(usize, &[u8])]) -> (CPU, mmu::MMU) {
+        let mut bank_0 = vec![0; 0x4000];
+        for &(address, bytes) in code {
+            bank_0[address..address + bytes.len()].copy_from_slice(bytes);
+        }
+
+        let mut mmu = mmu::MMU::new();
+        mmu.load_game(bank_0, vec![0; 0x4000]);
+
+        (CPU::new(), mmu)
+    }
+
+    fn run(cpu: &mut CPU, mmu: &mut mmu::MMU, instructions: usize) {
+        for _ in 0..instructions {
+            cpu.execute(mmu);
+        }
+    }
+
+    #[test]
+    fn daa_after_add_and_sub() {
+        // LD A,45  ADD A,38  DAA
+        let (mut cpu, mut mmu) = load(&[(0x100, &[0x3E, 0x45, 0xC6, 0x38, 0x27])]);
+        run(&mut cpu, &mut mmu, 3);
+        assert_eq!((cpu.registers.a, cpu.registers.f), (0x83, 0x00));
+
+        // LD A,83  SUB 38  DAA
+        let (mut cpu, mut mmu) = load(&[(0x100, &[0x3E, 0x83, 0xD6, 0x38, 0x27])]);
+        run(&mut cpu, &mut mmu, 3);
+        assert_eq!((cpu.registers.a, cpu.registers.f), (0x45, 0x40));
+
+        // LD A,99  ADD A,01  DAA carries out of the top digit
+        let (mut cpu, mut mmu) = load(&[(0x100, &[0x3E, 0x99, 0xC6, 0x01, 0x27])]);
+        run(&mut cpu, &mut mmu, 3);
+        assert_eq!((cpu.registers.a, cpu.registers.f), (0x00, 0x90));
+    }
+
+    #[test]
+    fn add_sp_and_ld_hl_sp_flags_come_from_the_low_byte() {
+        // LD SP,FFF8  ADD SP,08
+        let (mut cpu, mut mmu) = load(&[(0x100, &[0x31, 0xF8, 0xFF, 0xE8, 0x08])]);
+        run(&mut cpu, &mut mmu, 2);
+        assert_eq!((cpu.sp, cpu.registers.f), (0x0000, 0x30));
+
+        // LD SP,0000  ADD SP,-1
+        let (mut cpu, mut mmu) = load(&[(0x100, &[0x31, 0x00, 0x00, 0xE8, 0xFF])]);
+        run(&mut cpu, &mut mmu, 2);
+        assert_eq!((cpu.sp, cpu.registers.f), (0xFFFF, 0x00));
+
+        // LD SP,00FF  LD HL,SP+1
+        let (mut cpu, mut mmu) = load(&[(0x100, &[0x31, 0xFF, 0x00, 0xF8, 0x01])]);
+        run(&mut cpu, &mut mmu, 2);
+        assert_eq!((cpu.registers.h, cpu.registers.l, cpu.sp, cpu.registers.f), (0x01, 0x00, 0x00FF, 0x30));
+
+        // LD SP,1000  LD HL,SP-1
+        let (mut cpu, mut mmu) = load(&[(0x100, &[0x31, 0x00, 0x10, 0xF8, 0xFF])]);
+        run(&mut cpu, &mut mmu, 2);
+        assert_eq!((cpu.registers.h, cpu.registers.l, cpu.registers.f), (0x0F, 0xFF, 0x00));
+    }
+
+    #[test]
+    fn sbc_half_carry_counts_the_carry_in() {
+        // SCF  LD A,10  SBC A,00
+        let (mut cpu, mut mmu) = load(&[(0x100, &[0x37, 0x3E, 0x10, 0xDE, 0x00])]);
+        run(&mut cpu, &mut mmu, 3);
+        assert_eq!((cpu.registers.a, cpu.registers.f), (0x0F, 0x60));
+
+        // SCF  LD A,10  LD B,0F  SBC A,B
+        let (mut cpu, mut mmu) = load(&[(0x100, &[0x37, 0x3E, 0x10, 0x06, 0x0F, 0x98])]);
+        run(&mut cpu, &mut mmu, 4);
+        assert_eq!((cpu.registers.a, cpu.registers.f), (0x00, 0xE0));
+
+        // SCF  LD A,00  SBC A,00
+        let (mut cpu, mut mmu) = load(&[(0x100, &[0x37, 0x3E, 0x00, 0xDE, 0x00])]);
+        run(&mut cpu, &mut mmu, 3);
+        assert_eq!((cpu.registers.a, cpu.registers.f), (0xFF, 0x70));
+    }
+
+    #[test]
+    fn ei_waits_for_the_next_instruction_and_di_cancels_it() {
+        // EI  DI  NOP  NOP with an interrupt waiting the whole time
+        let (mut cpu, mut mmu) = load(&[(0x100, &[0xFB, 0xF3, 0x00, 0x00])]);
+        mmu.write(0xFFFF, 0x01);
+        mmu.write(0xFF0F, 0x01);
+        run(&mut cpu, &mut mmu, 4);
+        assert!(!cpu.ime);
+        assert_eq!(cpu.pc, 0x104);
+
+        // EI  NOP  NOP, the interrupt goes after the NOP rather than before it
+        let (mut cpu, mut mmu) = load(&[(0x100, &[0xFB, 0x00, 0x00])]);
+        mmu.write(0xFFFF, 0x01);
+        mmu.write(0xFF0F, 0x01);
+        run(&mut cpu, &mut mmu, 2);
+        assert!(cpu.ime);
+        assert_eq!(cpu.pc, 0x102);
+        assert_eq!(cpu.execute(&mut mmu), 20);
+        assert_eq!(cpu.pc, 0x40);
+        assert!(!cpu.ime);
+    }
+
+    #[test]
+    fn interrupts_go_by_priority_and_clear_their_if_bit() {
+        // EI  NOP, timer and joypad are enabled and requested, VBlank is only requested.
+        // the timer handler is a RETI
+        let (mut cpu, mut mmu) = load(&[(0x100, &[0xFB, 0x00, 0x00]), (0x50, &[0xD9])]);
+        mmu.write(0xFFFF, 0x14);
+        mmu.write(0xFF0F, 0x15);
+        run(&mut cpu, &mut mmu, 3);
+        assert_eq!(cpu.pc, 0x50);
+        assert_eq!(mmu.read(0xFF0F) & 0x1F, 0x11);
+        assert_eq!((cpu.sp, mmu.read(0xFFFD), mmu.read(0xFFFC)), (0xFFFC, 0x01, 0x02));
+
+        // RETI turns interrupts back on straight away, so joypad goes next
+        run(&mut cpu, &mut mmu, 2);
+        assert_eq!(cpu.pc, 0x60);
+        assert_eq!(mmu.read(0xFF0F) & 0x1F, 0x01);
+    }
+
+    #[test]
+    fn illegal_opcodes_hang_the_cpu() {
+        // EI  0xD3, not even an interrupt gets it going again
+        let (mut cpu, mut mmu) = load(&[(0x100, &[0xFB, 0xD3, 0x00])]);
+        mmu.write(0xFFFF, 0x01);
+        mmu.write(0xFF0F, 0x01);
+        run(&mut cpu, &mut mmu, 10);
+        assert!(cpu.locked);
+        assert_eq!(cpu.pc, 0x101);
+    }
+}
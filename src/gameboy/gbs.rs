@@ -0,0 +1,190 @@
+// GBS files are the sound driver and music data ripped out of a game. There's no cartridge
+// mapper, the player loads the code at its load address, calls init once with the track
+// number in A and then calls play at the rate the header asks for.
+//
+// 00   "GBS" and version 1
+// 04   number of tracks
+// 05   first track, counting from 1
+// 06   load address
+// 08   init address
+// 0A   play address
+// 0C   stack pointer
+// 0E   TMA
+// 0F   TAC
+// 10   title, 32 bytes
+// 30   author, 32 bytes
+// 50   copyright, 32 bytes
+// 70   code and data
+
+use std::str;
+
+use gameboy::ppu;
+
+const HEADER_SIZE: usize = 0x70;
+const TEXT_SIZE: usize = 32;
+const BANK_SIZE: usize = 0x4000;
+
+// where init and play return to. nothing ever runs code in the unusable area after OAM, so
+// the CPU reaching it means the routine is done
+pub const RETURN_ADDRESS: u16 = 0xFEA0;
+
+// timer clock periods in t cycles for TAC bits 0-1
+const TIMER_PERIODS: [u32; 4] = [1024, 16, 64, 256];
+
+#[derive(Clone, Debug)]
+pub struct GbsHeader {
+    pub track_count: u8,
+    // counting from 0, the header itself counts from 1
+    pub first_track: u8,
+    pub load_address: u16,
+    pub init_address: u16,
+    pub play_address: u16,
+    pub stack_pointer: u16,
+    pub timer_modulo: u8,
+    pub timer_control: u8,
+    pub title: String,
+    pub author: String,
+    pub copyright: String,
+}
+
+impl GbsHeader {
+
+    fn parse(data: &[u8]) -> Result<GbsHeader, &'static str> {
+        if data.len() < HEADER_SIZE || &data[..3] != b"GBS" {
+            return Err("not a GBS file");
+        }
+        if data[3] != 1 {
+            return Err("unsupported GBS version");
+        }
+
+        let word = |offset: usize| data[offset] as u16 | (data[offset + 1] as u16) << 8;
+        let header = GbsHeader {
+            track_count: data[4],
+            first_track: data[5].saturating_sub(1),
+            load_address: word(0x06),
+            init_address: word(0x08),
+            play_address: word(0x0A),
+            stack_pointer: word(0x0C),
+            timer_modulo: data[0x0E],
+            timer_control: data[0x0F],
+            title: read_text(&data[0x10..0x10 + TEXT_SIZE]),
+            author: read_text(&data[0x30..0x30 + TEXT_SIZE]),
+            copyright: read_text(&data[0x50..0x50 + TEXT_SIZE]),
+        };
+
+        if header.track_count == 0 {
+            return Err("GBS file has no tracks");
+        }
+        // the code has to start somewhere in ROM after the RST and interrupt vectors
+        if header.load_address < 0x0400 || header.load_address >= 0x8000 {
+            return Err("GBS load address is outside of ROM");
+        }
+
+        Ok(header)
+    }
+
+    // TAC bit 2 drives play from the timer, otherwise it's called every VBlank. bit 7 asks for
+    // CGB double speed
+    pub fn uses_timer(&self) -> bool {
+        self.timer_control & 0x04 != 0
+    }
+
+    pub fn double_speed(&self) -> bool {
+        self.timer_control & 0x80 != 0
+    }
+
+    // how often play is called, in dots
+    fn play_period(&self) -> u32 {
+        if !self.uses_timer() {
+            return ppu::DOTS_PER_FRAME;
+        }
+
+        let cycles = TIMER_PERIODS[(self.timer_control & 0x03) as usize] * (256 - self.timer_modulo as u32);
+
+        // the timer runs off the CPU clock, so it goes twice as fast in double speed
+        if self.double_speed() { cycles / 2 } else { cycles }
+    }
+}
+
+#[derive(Clone)]
+pub struct Gbs {
+    header: GbsHeader,
+    // the code placed at its load address, cut into 16KB ROM banks
+    banks: Vec<Vec<u8>>,
+    track: u8,
+    play_period: u32,
+    // dots until play is due
+    play_timer: u32,
+}
+
+impl Gbs {
+
+    pub fn parse(data: &[u8]) -> Result<Gbs, &'static str> {
+        let header = GbsHeader::parse(data)?;
+        let code = &data[HEADER_SIZE..];
+
+        // everything below the load address is left empty, and there are always at least the
+        // two banks that are mapped at the start
+        let size = header.load_address as usize + code.len();
+        let bank_count = ((size + BANK_SIZE - 1) / BANK_SIZE).max(2);
+        let mut image = vec![0; bank_count * BANK_SIZE];
+        image[header.load_address as usize..size].copy_from_slice(code);
+
+        // a rip's RSTs go to the same offsets from its load address, so each vector jumps on
+        // to there. an interrupt the driver enables just returns
+        for vector in (0x00..0x40).step_by(8) {
+            let target = header.load_address + vector as u16;
+            image[vector..vector + 3].copy_from_slice(&[0xC3, target as u8, (target >> 8) as u8]);
+        }
+        for vector in (0x40..0x68).step_by(8) {
+            image[vector] = 0xD9;
+        }
+
+        Ok(Gbs {
+            play_period: header.play_period(),
+            play_timer: 0,
+            track: header.first_track,
+            header: header,
+            banks: image.chunks(BANK_SIZE).map(|bank| bank.to_vec()).collect(),
+        })
+    }
+
+    pub fn header(&self) -> &GbsHeader {
+        &self.header
+    }
+
+    pub fn banks(&self) -> &[Vec<u8>] {
+        &self.banks
+    }
+
+    pub fn track(&self) -> u8 {
+        self.track
+    }
+
+    // starts the play timer over for a new track
+    pub fn start_track(&mut self, track: u8) {
+        self.track = track;
+        self.play_timer = self.play_period;
+    }
+
+    // counts down dots, returns true each time play is due
+    pub fn tick(&mut self, dots: u32) -> bool {
+        if dots < self.play_timer {
+            self.play_timer -= dots;
+            return false;
+        }
+
+        self.play_timer = self.play_period - (dots - self.play_timer) % self.play_period;
+        true
+    }
+}
+
+// zero padded, and anything that isn't valid text is left out
+fn read_text(bytes: &[u8]) -> String {
+    let end = bytes.iter().position(|&byte| byte == 0).unwrap_or(bytes.len());
+
+    match str::from_utf8(&bytes[..end]) {
+        Ok(text) => text.trim().to_string(),
+        Err(_) => bytes[..end].iter().filter(|byte| byte.is_ascii_graphic() || **byte == b' ').map(|&byte| byte as char).collect(),
+    }
+}
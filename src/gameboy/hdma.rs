@@ -0,0 +1,126 @@
+// CGB VRAM DMA registers:
+// FF51   HDMA1   source high
+// FF52   HDMA2   source low (lower 4 bits ignored)
+// FF53   HDMA3   destination high (only bits 0-4, always inside VRAM)
+// FF54   HDMA4   destination low (lower 4 bits ignored)
+// FF55   HDMA5   length/mode/start
+//
+// Writing FF55 with bit 7 clear copies (length + 1) blocks of 16 bytes right away while the
+// CPU waits (general purpose DMA). With bit 7 set one block is copied at the start of every
+// HBlank instead. Writing bit 7 clear during an HBlank DMA cancels it.
+
+use gameboy::state::{ StateReader, StateResult, StateWriter };
+
+pub const BLOCK_SIZE: u16 = 16;
+
+// every block keeps the CPU waiting for 32 dots, whatever the CPU speed is
+pub const BLOCK_DOTS: u32 = 32;
+
+pub enum Transfer {
+    General(u8),
+    HBlank,
+    Cancelled,
+}
+
+#[derive(Clone)]
+pub struct HDMA {
+    source: u16,
+    destination: u16,
+    // blocks left to copy minus one, which is what FF55 reads back
+    length: u8,
+    hblank_active: bool,
+}
+
+impl Default for HDMA {
+
+    fn default() -> HDMA {
+        HDMA {
+            source: 0x0000,
+            destination: 0x8000,
+            length: 0x7F,
+            hblank_active: false,
+        }
+    }
+}
+
+impl HDMA {
+
+    pub fn new() -> HDMA {
+        Default::default()
+    }
+
+    pub fn read_register(&self, address: u16) -> u8 {
+        match address {
+            // bit 7 reads 0 while an HBlank DMA is running, 1 once it has finished or was cancelled
+            0xFF55 if self.hblank_active => self.length,
+            0xFF55 => 0x80 | self.length,
+            _ => 0xFF,
+        }
+    }
+
+    pub fn write_register(&mut self, address: u16, data: u8) -> Option<Transfer> {
+        match address {
+            0xFF51 => self.source = (self.source & 0x00FF) | ((data as u16) << 8),
+            0xFF52 => self.source = (self.source & 0xFF00) | (data as u16 & 0xF0),
+            0xFF53 => self.destination = 0x8000 | (self.destination & 0x00FF) | ((data as u16 & 0x1F) << 8),
+            0xFF54 => self.destination = (self.destination & 0xFF00) | (data as u16 & 0xF0),
+            0xFF55 => {
+                // a cancelled transfer keeps its remaining length for FF55 to report
+                if self.hblank_active && data & 0x80 == 0 {
+                    self.hblank_active = false;
+                    return Some(Transfer::Cancelled);
+                }
+
+                self.length = data & 0x7F;
+
+                if data & 0x80 != 0 {
+                    self.hblank_active = true;
+                    return Some(Transfer::HBlank);
+                }
+
+                return Some(Transfer::General(self.length + 1));
+            },
+            _ => {}
+        }
+
+        None
+    }
+
+    pub fn hblank_active(&self) -> bool {
+        self.hblank_active
+    }
+
+    // returns the source and destination of the next block and moves past it
+    pub fn next_block(&mut self) -> (u16, u16) {
+        let block = (self.source, self.destination);
+
+        self.source = self.source.wrapping_add(BLOCK_SIZE);
+        // the destination wraps around inside VRAM
+        self.destination = 0x8000 | (self.destination.wrapping_add(BLOCK_SIZE) & 0x1FF0);
+
+        if self.length == 0 {
+            self.length = 0x7F;
+            self.hblank_active = false;
+        } else {
+            self.length -= 1;
+        }
+
+        block
+    }
+
+    pub fn save_state(&self, state: &mut StateWriter) {
+        state.write_u16(self.source);
+        state.write_u16(self.destination);
+        state.write_u8(self.length);
+        state.write_bool(self.hblank_active);
+    }
+
+    pub fn load_state(&mut self, state: &mut StateReader) -> StateResult<()> {
+        self.source = state.read_u16()?;
+        self.destination = state.read_u16()?;
+        self.length = state.read_u8()?;
+        self.hblank_active = state.read_bool()?;
+
+        Ok(())
+    }
+}
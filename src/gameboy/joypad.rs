@@ -0,0 +1,113 @@
+// P1 (FF00), the button matrix:
+// bit 5   select action buttons (0 = selected)
+// bit 4   select direction buttons (0 = selected)
+// bit 3   down or start (0 = pressed)
+// bit 2   up or select
+// bit 1   left or B
+// bit 0   right or A
+//
+// Bits 6 and 7 aren't connected and read high. With both groups selected a line reads low if
+// either of its buttons is down, with neither selected every line reads high.
+
+use gameboy::state::{ StateReader, StateResult, StateWriter };
+
+pub const INT_JOYPAD: u8 = 0b00010000;
+
+// the SGB can have up to 4 controllers plugged in
+pub const MAX_PLAYERS: usize = 4;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Button {
+    Right,
+    Left,
+    Up,
+    Down,
+    A,
+    B,
+    Select,
+    Start,
+}
+
+impl Button {
+
+    // directions in the low nibble and actions in the high one, both in P1 bit order
+    fn mask(self) -> u8 {
+        match self {
+            Button::Right => 0x01,
+            Button::Left => 0x02,
+            Button::Up => 0x04,
+            Button::Down => 0x08,
+            Button::A => 0x10,
+            Button::B => 0x20,
+            Button::Select => 0x40,
+            Button::Start => 0x80,
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct Joypad {
+    // bits 4 and 5 as last written
+    select: u8,
+    // a set bit is a held button, laid out like Button::mask
+    pressed: [u8; MAX_PLAYERS],
+}
+
+impl Default for Joypad {
+
+    fn default() -> Joypad {
+        Joypad {
+            select: 0x30,
+            pressed: [0; MAX_PLAYERS],
+        }
+    }
+}
+
+impl Joypad {
+
+    pub fn new() -> Joypad {
+        Default::default()
+    }
+
+    pub fn select(&self) -> u8 {
+        self.select
+    }
+
+    pub fn write(&mut self, data: u8) {
+        self.select = data & 0x30;
+    }
+
+    // what P1 reads with player's buttons on the lines
+    pub fn read(&self, player: usize) -> u8 {
+        let pressed = self.pressed[player];
+        let mut lines = 0x0F;
+
+        if self.select & 0x10 == 0 {
+            lines &= !pressed & 0x0F;
+        }
+        if self.select & 0x20 == 0 {
+            lines &= !(pressed >> 4) & 0x0F;
+        }
+
+        0xC0 | self.select | lines
+    }
+
+    pub fn set_button(&mut self, player: usize, button: Button, pressed: bool) {
+        if pressed {
+            self.pressed[player] |= button.mask();
+        } else {
+            self.pressed[player] &= !button.mask();
+        }
+    }
+
+    // held buttons come from the frontend, so only the select lines are part of the state
+    pub fn save_state(&self, state: &mut StateWriter) {
+        state.write_u8(self.select);
+    }
+
+    pub fn load_state(&mut self, state: &mut StateReader) -> StateResult<()> {
+        self.select = state.read_u8()? & 0x30;
+
+        Ok(())
+    }
+}
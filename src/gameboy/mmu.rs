@@ -1,5 +1,8 @@
 use std::str;
 
+use gameboy::{ apu, hdma, joypad, ppu, serial, sgb, timer };
+use gameboy::state::{ StateReader, StateResult, StateWriter };
+
 // Memory Layout:
 // 0000-3FFF   16KB ROM Bank 00            (ROM)  (in cartridge, fixed at bank 00)
 // 4000-7FFF   16KB ROM Bank 01..NN        (ROM)  (in cartridge, switchable bank number)
@@ -14,14 +17,58 @@ use std::str;
 // FF80-FFFE   High RAM                    (ZRAM)
 // FFFF        Interrupt Enable Register
 
+const VRAM_BANK_SIZE: usize = 8192;
+const VRAM_BANKS: usize = 2;
+const WRAM_BANK_SIZE: usize = 4096;
+const WRAM_BANKS: usize = 8;
+
+// joypad
+const P1: u16 = 0xFF00;
+
+// interrupt registers
+const IF: u16 = 0xFF0F;
+const IE: u16 = 0xFFFF;
+
+// CGB speed switch, bit 0 arms the switch and the next STOP performs it
+const KEY1: u16 = 0xFF4D;
+
+// switching speed keeps the CPU stopped for 2050 m cycles
+const SPEED_SWITCH_CYCLES: u32 = 8200;
+
+// CGB bank select registers
+const VBK: u16 = 0xFF4F;
+const SVBK: u16 = 0xFF70;
+
+#[derive(Clone)]
 pub struct MMU {
     rom_bank_0: Vec<u8>,
     rom_bank_nn: Vec<u8>,
+    // every ROM bank when playing a GBS file, which switches them itself instead of going
+    // through a mapper. empty otherwise
+    gbs_banks: Vec<Vec<u8>>,
     vram: Vec<u8>,
     eram: Vec<u8>,
     wram: Vec<u8>,
+    oam: Vec<u8>,
     io: Vec<u8>,
     zram: Vec<u8>,
+    cgb_mode: bool,
+    sgb_mode: bool,
+    vram_bank: usize,
+    wram_bank: usize,
+    double_speed: bool,
+    speed_switch_armed: bool,
+    // t cycles the CPU has to sit out while DMA or a speed switch finishes
+    stall_cycles: u32,
+    // set at the start of every VBlank until someone takes it
+    frame_ready: bool,
+    ppu: ppu::PPU,
+    apu: apu::APU,
+    hdma: hdma::HDMA,
+    sgb: sgb::SGB,
+    timer: timer::Timer,
+    joypad: joypad::Joypad,
+    serial: serial::Serial,
 }
 
 impl Default for MMU {
@@ -29,11 +76,28 @@ impl Default for MMU {
         MMU {
             rom_bank_0: vec![0; 16384],
             rom_bank_nn: vec![0; 16384],
-            vram: vec![0; 8192],
+            gbs_banks: Vec::new(),
+            vram: vec![0; VRAM_BANK_SIZE * VRAM_BANKS],
             eram: vec![0; 8192],
-            wram: vec![0; 8192],
+            wram: vec![0; WRAM_BANK_SIZE * WRAM_BANKS],
+            oam: vec![0; 160],
             io: vec![0; 128],
             zram: vec![0; 128],
+            cgb_mode: false,
+            sgb_mode: false,
+            vram_bank: 0,
+            wram_bank: 1,
+            double_speed: false,
+            speed_switch_armed: false,
+            stall_cycles: 0,
+            frame_ready: false,
+            ppu: ppu::PPU::new(),
+            apu: apu::APU::new(),
+            hdma: hdma::HDMA::new(),
+            sgb: sgb::SGB::new(),
+            timer: timer::Timer::new(),
+            joypad: joypad::Joypad::new(),
+            serial: serial::Serial::new(),
         }
     }
 }
@@ -45,42 +109,284 @@ impl MMU {
     }
 
     pub fn write(&mut self, address: u16, data: u8) {
-        let (memory_slice, offset) = self.get_memory_slice(address);
-        let idx = (address - offset) as usize;
+        match address {
+            // GBS code picks the bank at 4000-7FFF by writing to 2000-3FFF, the rest of ROM
+            // can't be written
+            0x0000..=0x7FFF if !self.gbs_banks.is_empty() => {
+                if address >= 0x2000 && address < 0x4000 {
+                    self.select_gbs_bank(data);
+                }
+                return;
+            },
+            0x8000..=0x9FFF if !self.ppu.vram_accessible() => return,
+            0xFE00..=0xFE9F if !self.ppu.oam_accessible() => return,
+            0xFEA0..=0xFEFF => return,  // not usable
+            P1 => {
+                let before = self.read_joypad();
+                self.joypad.write(data);
+                // the SGB listens to the select lines for packets
+                if self.sgb_mode {
+                    self.sgb.write_joypad(data);
+                }
+                self.check_joypad_interrupt(before);
+                return;
+            },
+            0xFF01..=0xFF02 => {
+                self.serial.write_register(address, data, self.cgb_mode);
+                return;
+            },
+            0xFF04..=0xFF07 => {
+                self.timer.write_register(address, data);
+                return;
+            },
+            0xFF10..=0xFF3F => {
+                self.apu.write_register(address, data);
+                return;
+            },
+            0xFF40..=0xFF45 | 0xFF47..=0xFF4B | 0xFF68..=0xFF6C => {
+                let interrupts = self.ppu.write_register(address, data);
+                self.request_interrupt(interrupts);
+                return;
+            },
+            0xFF51..=0xFF55 => {
+                if self.cgb_mode {
+                    self.write_hdma(address, data);
+                }
+                return;
+            },
+            KEY1 => {
+                if self.cgb_mode {
+                    self.speed_switch_armed = data & 0x01 != 0;
+                }
+                return;
+            },
+            VBK => {
+                if self.cgb_mode {
+                    self.vram_bank = (data & 0x01) as usize;
+                }
+                return;
+            },
+            SVBK => {
+                if self.cgb_mode {
+                    self.wram_bank = select_wram_bank(data);
+                }
+                return;
+            },
+            _ => {}
+        }
+
+        let (memory_slice, idx) = self.get_memory_slice(address);
 
         memory_slice[idx] = data;
     }
 
     pub fn read(&mut self, address: u16) -> u8 {
-        let (memory_slice, offset) = self.get_memory_slice(address);
-        let idx = (address - offset) as usize;
+        match address {
+            0x8000..=0x9FFF if !self.ppu.vram_accessible() => return 0xFF,
+            0xFE00..=0xFE9F if !self.ppu.oam_accessible() => return 0xFF,
+            0xFEA0..=0xFEFF => return 0xFF,
+            P1 => return self.read_joypad(),
+            0xFF01..=0xFF02 => return self.serial.read_register(address, self.cgb_mode),
+            0xFF04..=0xFF07 => return self.timer.read_register(address),
+            0xFF10..=0xFF3F => return self.apu.read_register(address),
+            0xFF40..=0xFF45 | 0xFF47..=0xFF4B | 0xFF68..=0xFF6C => return self.ppu.read_register(address),
+            // unused bits read high, and the CGB registers read 0xFF outside of CGB mode
+            0xFF51..=0xFF55 if self.cgb_mode => return self.hdma.read_register(address),
+            KEY1 if self.cgb_mode => return 0x7E | (self.double_speed as u8) << 7 | self.speed_switch_armed as u8,
+            VBK if self.cgb_mode => return 0xFE | self.vram_bank as u8,
+            SVBK if self.cgb_mode => return 0xF8 | self.wram_bank as u8,
+            0xFF51..=0xFF55 | KEY1 | VBK | SVBK => return 0xFF,
+            _ => {}
+        }
+
+        let (memory_slice, idx) = self.get_memory_slice(address);
 
         memory_slice[idx]
     }
 
-    pub fn incr(&mut self, address: u16) {
-        let (memory_slice, offset) = self.get_memory_slice(address);
-        let idx = (address - offset) as usize;
+    // runs the rest of the hardware for the t cycles the CPU just spent. in double speed mode
+    // the CPU runs twice as fast as everything tied to the dot clock
+    pub fn tick(&mut self, cycles: u32, cpu_halted: bool) {
+        self.stall_cycles = self.stall_cycles.saturating_sub(cycles);
+
+        // the timer and serial clock run off the CPU clock, so they're what speeds up
+        let interrupts = self.timer.tick(cycles) | self.serial.tick(cycles);
+        self.request_interrupt(interrupts);
+
+        let dots = if self.double_speed { cycles / 2 } else { cycles };
+        let interrupts = self.ppu.tick(dots, &self.vram, &self.oam);
+        self.request_interrupt(interrupts);
+        self.apu.tick(dots);
+
+        if interrupts & ppu::INT_VBLANK != 0 {
+            self.frame_ready = true;
+
+            if self.sgb_mode {
+                let lcdc = self.ppu.read_register(0xFF40);
+                self.sgb.vblank(&self.vram[..VRAM_BANK_SIZE], lcdc, self.ppu.framebuffer());
+            }
+        }
+
+        // HBlank DMA waits while the CPU is halted and picks up again once it wakes
+        if self.ppu.take_hblank_started() && self.hdma.hblank_active() && !cpu_halted {
+            self.copy_hdma_block();
+        }
+    }
+
+    pub fn take_frame_ready(&mut self) -> bool {
+        let ready = self.frame_ready;
+        self.frame_ready = false;
+
+        ready
+    }
+
+    pub fn double_speed(&self) -> bool {
+        self.double_speed
+    }
+
+    // the CPU doesn't execute anything while a DMA or speed switch has it stalled
+    pub fn cpu_stalled(&self) -> bool {
+        self.stall_cycles > 0
+    }
+
+    // true when an enabled interrupt is waiting, which is what wakes the CPU from HALT
+    pub fn interrupt_pending(&mut self) -> bool {
+        self.read(IE) & self.read(IF) & 0x1F != 0
+    }
+
+    // called by STOP, returns true if a speed switch happened
+    pub fn switch_speed(&mut self) -> bool {
+        // STOP clears DIV whether or not the speed changes
+        self.timer.write_register(0xFF04, 0);
+
+        if !self.cgb_mode || !self.speed_switch_armed {
+            return false;
+        }
+
+        self.double_speed = !self.double_speed;
+        self.speed_switch_armed = false;
+        self.stall_cycles += SPEED_SWITCH_CYCLES;
+
+        true
+    }
+
+    // clears the highest priority interrupt that's both enabled and requested, and returns the
+    // address of its handler
+    pub fn acknowledge_interrupt(&mut self) -> Option<u16> {
+        let pending = self.read(IE) & self.read(IF) & 0x1F;
+        if pending == 0 {
+            return None;
+        }
+
+        let bit = pending.trailing_zeros();
+        let idx = (IF - 0xFF00) as usize;
+        self.io[idx] &= !(1 << bit);
+
+        Some(0x40 + 8 * bit as u16)
+    }
+
+    // sets bits in IF, the CPU decides whether to service them
+    pub fn request_interrupt(&mut self, interrupts: u8) {
+        let idx = (IF - 0xFF00) as usize;
+        self.io[idx] |= interrupts;
+    }
+
+    // during SGB multiplayer the buttons on the lines are the player being polled, and with
+    // both groups deselected the low bits say which player that is
+    fn read_joypad(&self) -> u8 {
+        if !self.sgb_mode {
+            return self.joypad.read(0);
+        }
+
+        let select = self.joypad.select();
+        match self.sgb.joypad_id() {
+            Some(id) if select == 0x30 => 0xC0 | select | id,
+            _ => self.joypad.read(self.sgb.current_player() as usize),
+        }
+    }
+
+    // player is 0 outside of SGB multiplayer
+    pub fn set_button(&mut self, player: usize, button: joypad::Button, pressed: bool) {
+        let before = self.read_joypad();
+        self.joypad.set_button(player, button, pressed);
+        self.check_joypad_interrupt(before);
+    }
+
+    // the interrupt fires when any of the low 4 lines goes from high to low
+    fn check_joypad_interrupt(&mut self, before: u8) {
+        let after = self.read_joypad();
+        if before & !after & 0x0F != 0 {
+            self.request_interrupt(joypad::INT_JOYPAD);
+        }
+    }
 
-        memory_slice[idx] += 1;
+    fn write_hdma(&mut self, address: u16, data: u8) {
+        match self.hdma.write_register(address, data) {
+            Some(hdma::Transfer::General(blocks)) => {
+                for _ in 0..blocks {
+                    self.copy_hdma_block();
+                }
+            },
+            // with the LCD off there won't be an HBlank, so the first block goes right away
+            Some(hdma::Transfer::HBlank) if !self.ppu.lcd_enabled() => self.copy_hdma_block(),
+            _ => {}
+        }
     }
 
-    pub fn decr(&mut self, address: u16) {
-        let (memory_slice, offset) = self.get_memory_slice(address);
-        let idx = (address - offset) as usize;
+    fn copy_hdma_block(&mut self) {
+        let (source, destination) = self.hdma.next_block();
+
+        for i in 0..hdma::BLOCK_SIZE {
+            let data = self.read(source.wrapping_add(i));
+            let idx = self.vram_bank * VRAM_BANK_SIZE + ((destination + i) & 0x1FFF) as usize;
+            self.vram[idx] = data;
+        }
+
+        let speed = if self.double_speed { 2 } else { 1 };
+        self.stall_cycles += hdma::BLOCK_DOTS * speed;
+    }
 
-        memory_slice[idx] -= 1;
+    // both VRAM banks, bank 1 starts at 0x2000
+    pub fn vram(&self) -> &[u8] {
+        &self.vram
+    }
+
+    pub fn oam(&self) -> &[u8] {
+        &self.oam
+    }
+
+    pub fn eram(&self) -> &[u8] {
+        &self.eram
+    }
+
+    pub fn set_eram(&mut self, eram: &[u8]) {
+        self.eram.copy_from_slice(eram);
+    }
+
+    pub fn ppu(&self) -> &ppu::PPU {
+        &self.ppu
+    }
+
+    pub fn ppu_mut(&mut self) -> &mut ppu::PPU {
+        &mut self.ppu
+    }
+
+    pub fn apu(&self) -> &apu::APU {
+        &self.apu
+    }
+
+    pub fn apu_mut(&mut self) -> &mut apu::APU {
+        &mut self.apu
     }
 
     pub fn push(&mut self, sp: &mut u16, data: u8) {
-        *sp -= 8;
+        *sp = sp.wrapping_sub(1);
         self.write(*sp, data);
     }
 
     pub fn pop(&mut self, sp: &mut u16) -> u8 {
         let val = self.read(*sp);
-        self.write(*sp, 0);
-        *sp += 8;
+        *sp = sp.wrapping_add(1);
 
         val
     }
@@ -90,6 +396,133 @@ impl MMU {
         self.rom_bank_nn = bank_1;
     }
 
+    pub fn load_gbs(&mut self, banks: Vec<Vec<u8>>) {
+        self.rom_bank_0 = banks[0].clone();
+        self.rom_bank_nn = banks[1].clone();
+        self.gbs_banks = banks;
+    }
+
+    // bank 0 selects bank 1, and banks past the end of the file wrap around
+    fn select_gbs_bank(&mut self, data: u8) {
+        let bank = (data as usize).max(1) % self.gbs_banks.len();
+        self.rom_bank_nn = self.gbs_banks[bank].clone();
+    }
+
+    pub fn drain_serial_sent(&mut self, output: &mut Vec<u8>) {
+        self.serial.drain_sent(output);
+    }
+
+    // clears every RAM area and I/O register, for starting a GBS track from scratch
+    pub fn clear_ram(&mut self) {
+        for memory in &mut [&mut self.eram, &mut self.wram, &mut self.io, &mut self.zram] {
+            for byte in memory.iter_mut() {
+                *byte = 0;
+            }
+        }
+
+        self.timer = timer::Timer::new();
+        self.serial = serial::Serial::new();
+
+        if !self.gbs_banks.is_empty() {
+            self.select_gbs_bank(1);
+        }
+    }
+
+    // GBS files can ask for double speed without going through KEY1 and STOP
+    pub fn set_double_speed(&mut self, double_speed: bool) {
+        self.double_speed = double_speed;
+    }
+
+    pub fn header(&self) -> &[u8] {
+        &self.rom_bank_0[..0x150]
+    }
+
+    // 0x143 in the cartridge header, bit 7 is set for carts that support CGB functions
+    pub fn has_cgb_flag(&self) -> bool {
+        self.rom_bank_0[0x143] & 0x80 != 0
+    }
+
+    // 0x146 is 0x03 for carts with SGB functions, which the SGB only honors when the old
+    // licensee code at 0x14B is 0x33
+    pub fn has_sgb_flag(&self) -> bool {
+        self.rom_bank_0[0x146] == 0x03 && self.rom_bank_0[0x14B] == 0x33
+    }
+
+    pub fn sgb_mode(&self) -> bool {
+        self.sgb_mode
+    }
+
+    pub fn set_sgb_mode(&mut self, sgb_mode: bool) {
+        self.sgb_mode = sgb_mode;
+        self.sgb = sgb::SGB::new();
+    }
+
+    pub fn sgb(&self) -> &sgb::SGB {
+        &self.sgb
+    }
+
+    pub fn cgb_mode(&self) -> bool {
+        self.cgb_mode
+    }
+
+    pub fn set_cgb_mode(&mut self, cgb_mode: bool) {
+        self.cgb_mode = cgb_mode;
+        self.vram_bank = 0;
+        self.wram_bank = 1;
+        self.double_speed = false;
+        self.speed_switch_armed = false;
+        self.ppu.set_cgb_mode(cgb_mode);
+        self.timer.set_cgb_mode(cgb_mode);
+    }
+
+    pub fn save_state(&self, state: &mut StateWriter) {
+        state.write_bytes(&self.vram);
+        state.write_bytes(&self.eram);
+        state.write_bytes(&self.wram);
+        state.write_bytes(&self.oam);
+        state.write_bytes(&self.io);
+        state.write_bytes(&self.zram);
+        state.write_bool(self.cgb_mode);
+        state.write_bool(self.sgb_mode);
+        state.write_u8(self.vram_bank as u8);
+        state.write_u8(self.wram_bank as u8);
+        state.write_bool(self.double_speed);
+        state.write_bool(self.speed_switch_armed);
+        state.write_u32(self.stall_cycles);
+        self.ppu.save_state(state);
+        self.apu.save_state(state);
+        self.hdma.save_state(state);
+        self.sgb.save_state(state);
+        self.timer.save_state(state);
+        self.joypad.save_state(state);
+        self.serial.save_state(state);
+    }
+
+    pub fn load_state(&mut self, state: &mut StateReader) -> StateResult<()> {
+        state.read_bytes(&mut self.vram)?;
+        state.read_bytes(&mut self.eram)?;
+        state.read_bytes(&mut self.wram)?;
+        state.read_bytes(&mut self.oam)?;
+        state.read_bytes(&mut self.io)?;
+        state.read_bytes(&mut self.zram)?;
+        self.cgb_mode = state.read_bool()?;
+        self.sgb_mode = state.read_bool()?;
+        self.vram_bank = (state.read_u8()? & 0x01) as usize;
+        self.wram_bank = select_wram_bank(state.read_u8()?);
+        self.double_speed = state.read_bool()?;
+        self.speed_switch_armed = state.read_bool()?;
+        self.stall_cycles = state.read_u32()?;
+        self.ppu.load_state(state)?;
+        self.apu.load_state(state)?;
+        self.hdma.load_state(state)?;
+        self.sgb.load_state(state)?;
+        self.timer.load_state(state)?;
+        self.joypad.load_state(state)?;
+        self.serial.load_state(state)?;
+
+        Ok(())
+    }
+
     pub fn get_game_title(&mut self) -> &str {
         let buffer = &self.rom_bank_0[0x134..0x144];
 
@@ -121,23 +554,48 @@ impl MMU {
         self.write(0xFF49, 0xFF);
     }
 
-    fn get_memory_slice(&mut self, address: u16) -> (&mut Vec<u8>, u16) {
+    // returns the backing memory for an address along with the index into it
+    fn get_memory_slice(&mut self, address: u16) -> (&mut Vec<u8>, usize) {
         if address < 0x4000 {
-            return (&mut self.rom_bank_0, 0x0000);
+            return (&mut self.rom_bank_0, address as usize);
         } else if address < 0x8000 {
-            return (&mut self.rom_bank_nn, 0x4000);
+            return (&mut self.rom_bank_nn, (address - 0x4000) as usize);
         } else if address < 0xA000 {
-            return (&mut self.vram, 0x8000);
+            let idx = self.vram_bank * VRAM_BANK_SIZE + (address - 0x8000) as usize;
+            return (&mut self.vram, idx);
         } else if address < 0xC000 {
-            return (&mut self.eram, 0xA000);
-        } else if address < 0xE000 {
-            return (&mut self.wram, 0xC000);
+            return (&mut self.eram, (address - 0xA000) as usize);
+        } else if address < 0xFE00 {
+            // E000-FDFF echoes C000-DDFF, including whichever bank is switched in
+            let address = if address >= 0xE000 { address - 0x2000 } else { address };
+            let idx = self.wram_index(address);
+            return (&mut self.wram, idx);
+        } else if address < 0xFF00 {
+            // FEA0-FEFF is handled by read and write
+            return (&mut self.oam, (address - 0xFE00) as usize);
         } else if address < 0xFF80 {
-            return (&mut self.io, 0xFF00);
+            return (&mut self.io, (address - 0xFF00) as usize);
         } else if address >= 0xFF80 && address <= 0xFFFE {
-            return (&mut self.zram, 0xFF80);
+            return (&mut self.zram, (address - 0xFF80) as usize);
         }
 
-        return (&mut self.zram, 0xFF80);
+        return (&mut self.zram, (0xFFFF - 0xFF80) as usize);
+    }
+
+    // C000-CFFF is always bank 0, D000-DFFF is the bank selected by SVBK
+    fn wram_index(&self, address: u16) -> usize {
+        if address < 0xD000 {
+            (address - 0xC000) as usize
+        } else {
+            self.wram_bank * WRAM_BANK_SIZE + (address - 0xD000) as usize
+        }
+    }
+}
+
+// writing 0 to SVBK selects bank 1
+fn select_wram_bank(data: u8) -> usize {
+    match data & 0x07 {
+        0 => 1,
+        bank => bank as usize,
     }
 }
\ No newline at end of file
@@ -1,15 +1,63 @@
+mod apu;
+mod compat;
 mod cpu;
+mod gbs;
+mod hdma;
+mod joypad;
 mod mmu;
 mod opcodes;
+mod ppu;
+mod serial;
+mod sgb;
+mod state;
+mod timer;
 
 use std::io::prelude::*;
 use std::io::SeekFrom;
 use std::fs::File;
 use std::str;
 
+use self::state::{ StateReader, StateWriter };
+
+pub use self::apu::RegisterWrite as ApuWrite;
+pub use self::apu::SAMPLE_RATE as AUDIO_SAMPLE_RATE;
+pub use self::compat::CompatCombo;
+pub use self::gbs::GbsHeader;
+pub use self::joypad::{ Button, MAX_PLAYERS };
+pub use self::ppu::{ Layer, Renderer, SCREEN_HEIGHT, SCREEN_WIDTH, bg_tile_address, tall_sprites, tile_pixel };
+pub use self::sgb::{ SGB_HEIGHT, SGB_WIDTH };
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Model {
+    DMG,
+    CGB,
+    SGB,
+}
+
+// what the values in the framebuffer mean
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum PixelFormat {
+    // 0 (lightest) to 3 (darkest)
+    Shades,
+    // 15-bit colors with red in the low bits, as they'd show on the CGB LCD
+    CgbColors,
+    // same layout, but meant for a TV through the SNES
+    SnesColors,
+}
+
+// called with the Gameboy after every frame run_frame finishes
+pub type FrameCallback = Box<dyn FnMut(&Gameboy)>;
+
 pub struct Gameboy {
     cpu: cpu::CPU,
     mmu: mmu::MMU,
+    model: Model,
+    compat_combo: Option<CompatCombo>,
+    frame_callback: Option<FrameCallback>,
+    // set while playing a GBS file instead of a game
+    gbs: Option<gbs::Gbs>,
+    // the state right after load_game, which reset goes back to
+    reset_state: Option<Vec<u8>>,
 }
 
 const ROM_BANK_SIZE: u16 = 16384;
@@ -19,7 +67,12 @@ impl Default for Gameboy {
     fn default() -> Gameboy {
         Gameboy {
             cpu: cpu::CPU::new(),
-            mmu: mmu::MMU::new()
+            mmu: mmu::MMU::new(),
+            model: Model::DMG,
+            compat_combo: None,
+            frame_callback: None,
+            gbs: None,
+            reset_state: None,
         }
     }
 }
@@ -40,9 +93,156 @@ impl Gameboy {
         let bank_1 = get_rom_bank_vec(&mut f, 1);
 
         self.mmu.load_game(bank_0, bank_1);
+
+        // CGB hardware only runs in CGB mode when the cart asks for it, otherwise the boot ROM
+        // picks colors for the DMG game
+        let cgb_mode = self.model == Model::CGB && self.mmu.has_cgb_flag();
+        self.mmu.set_cgb_mode(cgb_mode);
+
+        if self.model == Model::CGB && !cgb_mode {
+            let palettes = compat::select_palettes(self.mmu.header(), self.compat_combo);
+            self.mmu.ppu_mut().set_compat_palettes(&palettes);
+        }
+
+        let sgb_mode = self.model == Model::SGB && self.mmu.has_sgb_flag();
+        self.mmu.set_sgb_mode(sgb_mode);
+
+        self.reset_state = Some(self.save_state());
+    }
+
+    // starts the game over from where load_game left it, or the track over for a GBS file.
+    // the cartridge RAM is kept like it is on a real reset, so battery saves survive. settings
+    // like hidden layers and muted channels aren't part of the state so they stay too
+    pub fn reset(&mut self) -> Result<(), &'static str> {
+        if let Some(track) = self.gbs_track() {
+            self.play_gbs_track(track);
+            return Ok(());
+        }
+
+        let state = match self.reset_state.take() {
+            Some(state) => state,
+            None => return Err("no game loaded"),
+        };
+        let eram = self.mmu.eram().to_vec();
+
+        let result = self.load_state(&state);
+        self.mmu.set_eram(&eram);
+        self.reset_state = Some(state);
+
+        result
+    }
+
+    // the button combo held during the CGB boot logo to choose the colors of a DMG game,
+    // must be set before load_game
+    pub fn set_compat_combo(&mut self, combo: Option<CompatCombo>) {
+        self.compat_combo = combo;
+    }
+
+    // must be called before load_game to take effect
+    pub fn set_model(&mut self, model: Model) {
+        self.model = model;
+    }
+
+    // for player 1, which is the only player unless an SGB game turns on multiplayer
+    pub fn set_button(&mut self, button: Button, pressed: bool) {
+        self.mmu.set_button(0, button, pressed);
+    }
+
+    // player from 0 to MAX_PLAYERS - 1
+    pub fn set_player_button(&mut self, player: usize, button: Button, pressed: bool) {
+        if player < MAX_PLAYERS {
+            self.mmu.set_button(player, button, pressed);
+        }
+    }
+
+    // bytes this side sent on its own clock since the last call
+    pub fn drain_serial_output(&mut self, output: &mut Vec<u8>) {
+        self.mmu.drain_serial_sent(output);
+    }
+
+    // loads a GBS file in place of a game and starts its first track. power_on has to come
+    // first, like with load_game
+    pub fn load_gbs(&mut self, data: &[u8]) -> Result<(), &'static str> {
+        let gbs = gbs::Gbs::parse(data)?;
+        let first_track = gbs.header().first_track;
+
+        self.mmu.load_gbs(gbs.banks().to_vec());
+        self.gbs = Some(gbs);
+        self.play_gbs_track(first_track);
+
+        Ok(())
+    }
+
+    pub fn gbs_header(&self) -> Option<&GbsHeader> {
+        self.gbs.as_ref().map(|gbs| gbs.header())
+    }
+
+    // counting from 0
+    pub fn gbs_track(&self) -> Option<u8> {
+        self.gbs.as_ref().map(|gbs| gbs.track())
+    }
+
+    // starts a track from silence: RAM and I/O are cleared, the sound hardware is reset and init
+    // is called with the track number in A. tracks past the last one wrap around
+    pub fn play_gbs_track(&mut self, track: u8) {
+        let (header, track) = match self.gbs {
+            Some(ref mut gbs) => {
+                let track = track % gbs.header().track_count;
+                gbs.start_track(track);
+                (gbs.header().clone(), track)
+            },
+            None => return,
+        };
+
+        self.mmu.clear_ram();
+        // powering the APU off clears its registers, init_io powers it back on
+        self.mmu.write(0xFF26, 0x00);
+        self.mmu.init_io();
+        self.mmu.write(0xFF06, header.timer_modulo);
+        self.mmu.write(0xFF07, header.timer_control);
+        self.mmu.set_double_speed(header.double_speed());
+
+        self.cpu = cpu::CPU::new();
+        self.cpu.set_sp(header.stack_pointer);
+        self.cpu.set_a(track);
+        self.cpu.call(&mut self.mmu, header.init_address, gbs::RETURN_ADDRESS);
+    }
+
+    // a frame's worth of time for a GBS file. the CPU sits idle whenever init or play has
+    // returned, and play is only called again once it has
+    fn run_gbs_frame(&mut self) {
+        let mut dots = 0;
+
+        while dots < ppu::DOTS_PER_FRAME {
+            let idle = self.cpu.pc() == gbs::RETURN_ADDRESS;
+            let cycles = if idle {
+                self.mmu.tick(4, true);
+                4
+            } else {
+                self.step()
+            };
+
+            let elapsed = if self.mmu.double_speed() { cycles / 2 } else { cycles };
+            dots += elapsed;
+
+            let play_due = match self.gbs {
+                Some(ref mut gbs) => gbs.tick(elapsed),
+                None => return,
+            };
+
+            // a play that comes due while the last one is still running is skipped
+            if play_due && self.cpu.pc() == gbs::RETURN_ADDRESS {
+                let play_address = self.gbs.as_ref().map(|gbs| gbs.header().play_address).unwrap_or(0);
+                self.cpu.call(&mut self.mmu, play_address, gbs::RETURN_ADDRESS);
+            }
+        }
     }
 
     pub fn get_game_title(&mut self) -> &str {
+        if let Some(ref gbs) = self.gbs {
+            return &gbs.header().title;
+        }
+
         self.mmu.get_game_title()
     }
 
@@ -50,8 +250,212 @@ impl Gameboy {
         self.mmu.init_io();
     }
 
-    pub fn step(&mut self) {
-        self.cpu.execute(&mut self.mmu);
+    // runs one instruction and returns the t cycles it took
+    pub fn step(&mut self) -> u32 {
+        // DMA keeps the CPU off the bus but the rest of the hardware carries on
+        let cycles = if self.mmu.cpu_stalled() {
+            4
+        } else {
+            self.cpu.execute(&mut self.mmu)
+        } as u32;
+
+        self.mmu.tick(cycles, self.cpu.is_halted());
+
+        cycles
+    }
+
+    // runs until the next VBlank starts, so the framebuffer holds a whole new frame
+    pub fn run_frame(&mut self) {
+        self.mmu.take_frame_ready();
+
+        if self.gbs.is_some() {
+            self.run_gbs_frame();
+        } else {
+            let mut dots = 0;
+            loop {
+                let cycles = self.step();
+                dots += if self.mmu.double_speed() { cycles / 2 } else { cycles };
+
+                if self.mmu.take_frame_ready() {
+                    break;
+                }
+
+                // with the LCD off there's no VBlank, a frame's worth of time still counts as a frame
+                if !self.mmu.ppu().lcd_enabled() && dots >= ppu::DOTS_PER_FRAME {
+                    break;
+                }
+            }
+        }
+
+        // taken out while it runs so it can look at the whole Gameboy
+        if let Some(mut callback) = self.frame_callback.take() {
+            callback(self);
+            self.frame_callback = Some(callback);
+        }
+    }
+
+    pub fn set_frame_callback(&mut self, callback: Option<FrameCallback>) {
+        self.frame_callback = callback;
+    }
+
+    // the FIFO renderer is slower but gets mid-scanline effects right
+    pub fn set_renderer(&mut self, renderer: Renderer) {
+        self.mmu.ppu_mut().set_renderer(renderer);
+    }
+
+    // row major, screen_size pixels in the format given by pixel_format: a palette index for
+    // Shades, otherwise a 15-bit color. in SGB mode it's the colorized screen inside its border,
+    // updated once per frame
+    pub fn framebuffer(&self) -> &[u16] {
+        if self.mmu.sgb_mode() {
+            self.mmu.sgb().screen()
+        } else {
+            self.mmu.ppu().framebuffer()
+        }
+    }
+
+    // 4 bytes per pixel, for tools that want pixels without going through the video pipeline.
+    // shades come out as plain greys and colors aren't corrected
+    pub fn framebuffer_rgba(&self) -> Vec<u8> {
+        let format = self.pixel_format();
+        let mut rgba = Vec::with_capacity(self.framebuffer().len() * 4);
+
+        for &pixel in self.framebuffer() {
+            let (r, g, b) = match format {
+                PixelFormat::Shades => {
+                    let grey = 0xFF - (pixel & 0b11) as u8 * 0x55;
+                    (grey, grey, grey)
+                },
+                PixelFormat::CgbColors | PixelFormat::SnesColors => {
+                    (expand_5bit(pixel), expand_5bit(pixel >> 5), expand_5bit(pixel >> 10))
+                },
+            };
+
+            rgba.extend_from_slice(&[r, g, b, 0xFF]);
+        }
+
+        rgba
+    }
+
+    pub fn screen_size(&self) -> (usize, usize) {
+        if self.mmu.sgb_mode() {
+            (SGB_WIDTH, SGB_HEIGHT)
+        } else {
+            (SCREEN_WIDTH, SCREEN_HEIGHT)
+        }
+    }
+
+    pub fn is_cgb_mode(&self) -> bool {
+        self.mmu.cgb_mode()
+    }
+
+    pub fn is_sgb_mode(&self) -> bool {
+        self.mmu.sgb_mode()
+    }
+
+    pub fn pixel_format(&self) -> PixelFormat {
+        if self.mmu.sgb_mode() {
+            PixelFormat::SnesColors
+        } else if self.mmu.ppu().color_output() {
+            // CGB mode, or a DMG game colorized by CGB hardware
+            PixelFormat::CgbColors
+        } else {
+            PixelFormat::Shades
+        }
+    }
+
+    // appends the audio made since the last call: interleaved left and right samples from
+    // -1.0 to 1.0 at AUDIO_SAMPLE_RATE
+    pub fn drain_audio(&mut self, output: &mut Vec<f32>) {
+        self.mmu.apu_mut().drain_samples(output);
+    }
+
+    // channel audio is only collected while this is on, it's for recording stems and scopes
+    pub fn set_channel_capture(&mut self, enabled: bool) {
+        self.mmu.apu_mut().set_channel_capture(enabled);
+    }
+
+    // muting only takes a channel out of what's heard, channels are numbered 0 to 3
+    pub fn set_channel_muted(&mut self, channel: usize, muted: bool) {
+        self.mmu.apu_mut().set_channel_muted(channel, muted);
+    }
+
+    pub fn channel_muted(&self, channel: usize) -> bool {
+        self.mmu.apu().channel_muted(channel)
+    }
+
+    // logs every write to FF10-FF3F with the dot it happened on, counting from when logging
+    // was turned on
+    pub fn set_apu_write_logging(&mut self, enabled: bool) {
+        self.mmu.apu_mut().set_write_logging(enabled);
+    }
+
+    // returns the dot the log is up to, which is later than the last write when things go quiet
+    pub fn drain_apu_writes(&mut self, output: &mut Vec<ApuWrite>) -> u64 {
+        self.mmu.apu_mut().drain_register_writes(output)
+    }
+
+    // four samples per audio sample, channels 1-4 each before panning and master volume
+    pub fn drain_channel_audio(&mut self, output: &mut Vec<f32>) {
+        self.mmu.apu_mut().drain_channel_samples(output);
+    }
+
+    // hiding layers only changes what's drawn, the game runs exactly the same
+    pub fn set_layer_visible(&mut self, layer: Layer, visible: bool) {
+        self.mmu.ppu_mut().set_layer_visible(layer, visible);
+    }
+
+    pub fn layer_visible(&self, layer: Layer) -> bool {
+        self.mmu.ppu().layer_visible(layer)
+    }
+
+    // hides or shows a single OAM entry, 0-39
+    pub fn set_sprite_visible(&mut self, oam_index: usize, visible: bool) {
+        self.mmu.ppu_mut().set_sprite_visible(oam_index, visible);
+    }
+
+    // read only views for debugging tools, nothing here goes through the access checks the
+    // CPU would hit
+
+    // both VRAM banks, bank 1 starts at 0x2000
+    pub fn vram(&self) -> &[u8] {
+        self.mmu.vram()
+    }
+
+    pub fn oam(&self) -> &[u8] {
+        self.mmu.oam()
+    }
+
+    // the PPU registers FF40-FF4B, and FF68-FF6C in CGB mode
+    pub fn ppu_register(&self, address: u16) -> u8 {
+        self.mmu.ppu().read_register(address)
+    }
+
+    pub fn cgb_palette(&self, sprites: bool, palette: u8) -> [u16; 4] {
+        self.mmu.ppu().cgb_palette(sprites, palette)
+    }
+
+    pub fn save_state(&self) -> Vec<u8> {
+        let mut state = StateWriter::new();
+        self.cpu.save_state(&mut state);
+        self.mmu.save_state(&mut state);
+
+        state.into_vec()
+    }
+
+    // the current state is left untouched if the data can't be loaded
+    pub fn load_state(&mut self, data: &[u8]) -> Result<(), &'static str> {
+        let mut state = StateReader::new(data)?;
+        let mut cpu = self.cpu.clone();
+        let mut mmu = self.mmu.clone();
+
+        cpu.load_state(&mut state)?;
+        mmu.load_state(&mut state)?;
+
+        self.cpu = cpu;
+        self.mmu = mmu;
+
+        Ok(())
     }
 
     pub fn print_registers(&mut self) {
@@ -59,6 +463,12 @@ impl Gameboy {
     }
 }
 
+fn expand_5bit(value: u16) -> u8 {
+    let value = (value & 0x1F) as u8;
+
+    (value << 3) | (value >> 2)
+}
+
 fn get_rom_bank_vec(file: &mut File, bank_number: u16) -> Vec<u8> {
     let mut buffer = [0; ROM_BANK_SIZE as usize];
 
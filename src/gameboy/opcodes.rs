@@ -5,9 +5,15 @@ use gameboy::mmu;
 
 // all opcode executions return values for the timing registers and clock
 
+// f: flag register
+const FLAG_Z: u8 = 0b10000000;
+const FLAG_N: u8 = 0b01000000;
+const FLAG_H: u8 = 0b00100000;
+const FLAG_C: u8 = 0b00010000;
+
 ///// 8 bit loads /////
 
-// 0x06, 0x0E, 0x16, 0x1E, 0x26, 0x2E
+// 0x06, 0x0E, 0x16, 0x1E, 0x26, 0x2E, 0x3E
 // loads value n into register r
 pub fn ld_r_n(pc: &mut u16, r: &mut u8, n: u8) -> (u8, u8) {
     *r = n;
@@ -73,6 +79,15 @@ pub fn ld_mem_nn_r(pc: &mut u16, mmu: &mut mmu::MMU, nn: u16, r: u8) -> (u8, u8)
     (4, 16)
 }
 
+// 0xFA
+// loads value at (nn) into register r
+pub fn ld_r_mem_nn(pc: &mut u16, mmu: &mut mmu::MMU, nn: u16, r: &mut u8) -> (u8, u8) {
+    *r = mmu.read(nn);
+    *pc += 3;
+
+    (4, 16)
+}
+
 // 0xF2
 // loads value at (r2+0xFF00) into register r1
 pub fn ld_r1_mem_r2(pc: &mut u16, mmu: &mut mmu::MMU, r1: &mut u8, r2: u8) -> (u8, u8) {
@@ -86,46 +101,50 @@ pub fn ld_r1_mem_r2(pc: &mut u16, mmu: &mut mmu::MMU, r1: &mut u8, r2: u8) -> (u
 // loads value in register r2 into (r1 + 0xFF00)
 pub fn ld_mem_r1_r2(pc: &mut u16, mmu: &mut mmu::MMU, r1: u8, r2: u8) -> (u8, u8) {
     mmu.write((r1 as u16)+0xFF00, r2);
-    *pc += 2;
+    *pc += 1;
 
     (2, 8)
 }
 
 // 0x22
-// loads value in register r into (rr), then increments value at (rr)
-pub fn ld_mem_rr_inc_r(pc: &mut u16, mmu: &mut mmu::MMU, rr: u16, r: u8) -> (u8, u8) {
+// loads value in register r into (r1r2), then increments r1r2
+pub fn ld_mem_rr_inc_r(pc: &mut u16, mmu: &mut mmu::MMU, r1: &mut u8, r2: &mut u8, r: u8) -> (u8, u8) {
+    let rr = (*r1 as u16) << 8 | *r2 as u16;
     mmu.write(rr, r);
-    mmu.incr(rr);
-    *pc += 2;
+    set_rr(r1, r2, rr.wrapping_add(1));
+    *pc += 1;
 
     (2, 8)
 }
 
 // 0x2A
-// loads value at (rr) into r, then increments value at (rr)
-pub fn ld_r_mem_rr_inc(pc: &mut u16, mmu: &mut mmu::MMU, rr: u16, r: &mut u8) -> (u8, u8) {
+// loads value at (r1r2) into r, then increments r1r2
+pub fn ld_r_mem_rr_inc(pc: &mut u16, mmu: &mut mmu::MMU, r1: &mut u8, r2: &mut u8, r: &mut u8) -> (u8, u8) {
+    let rr = (*r1 as u16) << 8 | *r2 as u16;
     *r = mmu.read(rr);
-    mmu.incr(rr);
+    set_rr(r1, r2, rr.wrapping_add(1));
     *pc += 1;
 
     (2, 8)
 }
 
 // 0x32
-// loads value in register r into (rr), then decrements value at (rr)
-pub fn ld_mem_rr_dec_r(pc: &mut u16, mmu: &mut mmu::MMU, rr: u16, r: u8) -> (u8, u8) {
+// loads value in register r into (r1r2), then decrements r1r2
+pub fn ld_mem_rr_dec_r(pc: &mut u16, mmu: &mut mmu::MMU, r1: &mut u8, r2: &mut u8, r: u8) -> (u8, u8) {
+    let rr = (*r1 as u16) << 8 | *r2 as u16;
     mmu.write(rr, r);
-    mmu.decr(rr);
-    *pc += 2;
+    set_rr(r1, r2, rr.wrapping_sub(1));
+    *pc += 1;
 
     (2, 8)
 }
 
 // 0x3A
-// loads value at (rr) into r, then decrements value at (rr)
-pub fn ld_r_mem_rr_dec(pc: &mut u16, mmu: &mut mmu::MMU, rr: u16, r: &mut u8) -> (u8, u8) {
+// loads value at (r1r2) into r, then decrements r1r2
+pub fn ld_r_mem_rr_dec(pc: &mut u16, mmu: &mut mmu::MMU, r1: &mut u8, r2: &mut u8, r: &mut u8) -> (u8, u8) {
+    let rr = (*r1 as u16) << 8 | *r2 as u16;
     *r = mmu.read(rr);
-    mmu.decr(rr);
+    set_rr(r1, r2, rr.wrapping_sub(1));
     *pc += 1;
 
     (2, 8)
@@ -162,10 +181,10 @@ pub fn ld_rr_nn(pc: &mut u16, r1: &mut u8, r2: &mut u8, nn: u16) -> (u8, u8) {
 }
 
 // 0x08
-// loads sp into (nn)
+// loads sp into (nn), low byte first
 pub fn ld_mem_nn_sp(pc: &mut u16, mmu: &mut mmu::MMU, nn: u16, sp: u16) -> (u8, u8) {
-    mmu.write(nn, (sp >> 8) as u8);
-    mmu.write(nn+8, sp as u8);
+    mmu.write(nn, sp as u8);
+    mmu.write(nn.wrapping_add(1), (sp >> 8) as u8);
     *pc += 3;
 
     (5, 20)
@@ -201,26 +220,11 @@ pub fn push_rr(pc: &mut u16, mmu: &mut mmu::MMU, sp: &mut u16, r1: u8, r2: u8) -
 }
 
 // 0xF8
-// loads sp + n into rr
+// loads sp + n into rr, n is signed
 pub fn ld_rr_spn(pc: &mut u16, r1: &mut u8, r2: &mut u8, sp: u16, n: u8, f: &mut u8) -> (u8, u8) {
-    *r1 = ((sp + (n as u16)) >> 8) as u8;
-    *r2 = (sp + (n as u16)) as u8;
-
-    // reset z and n (might as well reset everything)
-    *f &= 0;
-
-    // check if carry happened in last nibble and set h if so
-    if (sp & 0b1111) + ((n as u16) & 0b1111) > 0b1111 {
-        // set h
-        *f |= 0b00100000
-    }
-
-    // check if carry happened and set c if so
-    if sp + (n as u16) > 0b1111111111111111 {
-        // set c
-        *f |= 0b00010000
-    }
-
+    let (result, flags) = add_sp(sp, n);
+    set_rr(r1, r2, result);
+    *f = flags;
     *pc += 2;
 
     (3, 12)
@@ -236,20 +240,317 @@ pub fn ld_sp_hl(pc: &mut u16, sp: &mut u16, hl: u16) -> (u8, u8) {
 }
 
 
+
 ///// 8 bit ALU /////
 
-// 0xAF
-pub fn xor_a(pc: &mut u16, a: u8, f: &mut u8) -> (u8, u8) {
-    if a ^ a == 0 {
-        *f |= 0b10000000;
+// op is bits 3-5 of the opcode: ADD ADC SUB SBC AND XOR OR CP
+
+// 0x80-0xBF apart from 0x86, 0x8E, 0x96, 0x9E, 0xA6, 0xAE, 0xB6, 0xBE
+// does op with register a and register r, into register a
+pub fn alu_a_r(pc: &mut u16, op: u8, a: &mut u8, r: u8, f: &mut u8) -> (u8, u8) {
+    alu(op, a, r, f);
+    *pc += 1;
+
+    (1, 4)
+}
+
+// 0x86, 0x8E, 0x96, 0x9E, 0xA6, 0xAE, 0xB6, 0xBE
+// does op with register a and the value at (rr), into register a
+pub fn alu_a_mem_rr(pc: &mut u16, mmu: &mut mmu::MMU, op: u8, a: &mut u8, rr: u16, f: &mut u8) -> (u8, u8) {
+    alu(op, a, mmu.read(rr), f);
+    *pc += 1;
+
+    (2, 8)
+}
+
+// 0xC6, 0xCE, 0xD6, 0xDE, 0xE6, 0xEE, 0xF6, 0xFE
+// does op with register a and value n, into register a
+pub fn alu_a_n(pc: &mut u16, op: u8, a: &mut u8, n: u8, f: &mut u8) -> (u8, u8) {
+    alu(op, a, n, f);
+    *pc += 2;
+
+    (2, 8)
+}
+
+fn alu(op: u8, a: &mut u8, n: u8, f: &mut u8) {
+    let carry = if *f & FLAG_C != 0 { 1 } else { 0 };
+
+    match op {
+        // ADD, ADC
+        0 | 1 => {
+            let carry = if op == 1 { carry } else { 0 };
+            let result = *a as u16 + n as u16 + carry as u16;
+            let half = (*a & 0x0F) + (n & 0x0F) + carry > 0x0F;
+            *f = flags(result as u8 == 0, false, half, result > 0xFF);
+            *a = result as u8;
+        },
+        // SUB, SBC, and CP which throws the result away
+        2 | 3 | 7 => {
+            let carry = if op == 3 { carry } else { 0 };
+            let result = *a as i16 - n as i16 - carry as i16;
+            let half = (*a & 0x0F) as i16 - (n & 0x0F) as i16 - (carry as i16) < 0;
+            *f = flags(result as u8 == 0, true, half, result < 0);
+            if op != 7 {
+                *a = result as u8;
+            }
+        },
+        4 => {
+            *a &= n;
+            *f = flags(*a == 0, false, true, false);
+        },
+        5 => {
+            *a ^= n;
+            *f = flags(*a == 0, false, false, false);
+        },
+        _ => {
+            *a |= n;
+            *f = flags(*a == 0, false, false, false);
+        },
+    }
+}
+
+// 0x04, 0x0C, 0x14, 0x1C, 0x24, 0x2C, 0x3C
+// increments register r, c is left alone
+pub fn inc_r(pc: &mut u16, r: &mut u8, f: &mut u8) -> (u8, u8) {
+    *r = inc(*r, f);
+    *pc += 1;
+
+    (1, 4)
+}
+
+// 0x34
+// increments the value at (rr)
+pub fn inc_mem_rr(pc: &mut u16, mmu: &mut mmu::MMU, rr: u16, f: &mut u8) -> (u8, u8) {
+    let value = inc(mmu.read(rr), f);
+    mmu.write(rr, value);
+    *pc += 1;
+
+    (3, 12)
+}
+
+// 0x05, 0x0D, 0x15, 0x1D, 0x25, 0x2D, 0x3D
+// decrements register r, c is left alone
+pub fn dec_r(pc: &mut u16, r: &mut u8, f: &mut u8) -> (u8, u8) {
+    *r = dec(*r, f);
+    *pc += 1;
+
+    (1, 4)
+}
+
+// 0x35
+// decrements the value at (rr)
+pub fn dec_mem_rr(pc: &mut u16, mmu: &mut mmu::MMU, rr: u16, f: &mut u8) -> (u8, u8) {
+    let value = dec(mmu.read(rr), f);
+    mmu.write(rr, value);
+    *pc += 1;
+
+    (3, 12)
+}
+
+fn inc(value: u8, f: &mut u8) -> u8 {
+    let result = value.wrapping_add(1);
+    *f = flags(result == 0, false, value & 0x0F == 0x0F, *f & FLAG_C != 0);
+
+    result
+}
+
+fn dec(value: u8, f: &mut u8) -> u8 {
+    let result = value.wrapping_sub(1);
+    *f = flags(result == 0, true, value & 0x0F == 0x00, *f & FLAG_C != 0);
+
+    result
+}
+
+// 0x27
+// turns register a back into BCD after an addition or subtraction of two BCD numbers
+pub fn daa(pc: &mut u16, a: &mut u8, f: &mut u8) -> (u8, u8) {
+    let subtract = *f & FLAG_N != 0;
+    let mut carry = *f & FLAG_C != 0;
+    let mut adjust = 0;
+
+    if carry || (!subtract && *a > 0x99) {
+        adjust |= 0x60;
+        carry = true;
     }
+    if *f & FLAG_H != 0 || (!subtract && *a & 0x0F > 0x09) {
+        adjust |= 0x06;
+    }
+
+    *a = if subtract { a.wrapping_sub(adjust) } else { a.wrapping_add(adjust) };
+    *f = flags(*a == 0, subtract, false, carry);
+    *pc += 1;
+
+    (1, 4)
+}
+
+// 0x2F
+// flips every bit of register a
+pub fn cpl(pc: &mut u16, a: &mut u8, f: &mut u8) -> (u8, u8) {
+    *a = !*a;
+    *f |= FLAG_N | FLAG_H;
+    *pc += 1;
+
+    (1, 4)
+}
+
+// 0x37
+// sets c
+pub fn scf(pc: &mut u16, f: &mut u8) -> (u8, u8) {
+    *f = (*f & FLAG_Z) | FLAG_C;
+    *pc += 1;
 
-    *f &= 0b10000000;
+    (1, 4)
+}
+
+// 0x3F
+// flips c
+pub fn ccf(pc: &mut u16, f: &mut u8) -> (u8, u8) {
+    *f = (*f & (FLAG_Z | FLAG_C)) ^ FLAG_C;
     *pc += 1;
 
     (1, 4)
 }
 
+///// 16 bit ALU /////
+
+// 0x09, 0x19, 0x29, 0x39
+// adds rr to hl, z is left alone
+pub fn add_hl_rr(pc: &mut u16, h: &mut u8, l: &mut u8, rr: u16, f: &mut u8) -> (u8, u8) {
+    let hl = (*h as u16) << 8 | *l as u16;
+    let result = hl as u32 + rr as u32;
+    let half = (hl & 0x0FFF) + (rr & 0x0FFF) > 0x0FFF;
+
+    set_rr(h, l, result as u16);
+    *f = flags(*f & FLAG_Z != 0, false, half, result > 0xFFFF);
+    *pc += 1;
+
+    (2, 8)
+}
+
+// 0xE8
+// adds signed n to sp
+pub fn add_sp_n(pc: &mut u16, sp: &mut u16, n: u8, f: &mut u8) -> (u8, u8) {
+    let (result, flags) = add_sp(*sp, n);
+    *sp = result;
+    *f = flags;
+    *pc += 2;
+
+    (4, 16)
+}
+
+// h and c come from the low byte, as if n were unsigned
+fn add_sp(sp: u16, n: u8) -> (u16, u8) {
+    let result = sp.wrapping_add(n as i8 as u16);
+    let half = (sp & 0x0F) + (n as u16 & 0x0F) > 0x0F;
+    let carry = (sp & 0xFF) + n as u16 > 0xFF;
+
+    (result, flags(false, false, half, carry))
+}
+
+// 0x03, 0x13, 0x23
+// increments r1r2, no flags change
+pub fn inc_rr(pc: &mut u16, r1: &mut u8, r2: &mut u8) -> (u8, u8) {
+    let rr = (*r1 as u16) << 8 | *r2 as u16;
+    set_rr(r1, r2, rr.wrapping_add(1));
+    *pc += 1;
+
+    (2, 8)
+}
+
+// 0x0B, 0x1B, 0x2B
+// decrements r1r2, no flags change
+pub fn dec_rr(pc: &mut u16, r1: &mut u8, r2: &mut u8) -> (u8, u8) {
+    let rr = (*r1 as u16) << 8 | *r2 as u16;
+    set_rr(r1, r2, rr.wrapping_sub(1));
+    *pc += 1;
+
+    (2, 8)
+}
+
+// 0x33
+pub fn inc_sp(pc: &mut u16, sp: &mut u16) -> (u8, u8) {
+    *sp = sp.wrapping_add(1);
+    *pc += 1;
+
+    (2, 8)
+}
+
+// 0x3B
+pub fn dec_sp(pc: &mut u16, sp: &mut u16) -> (u8, u8) {
+    *sp = sp.wrapping_sub(1);
+    *pc += 1;
+
+    (2, 8)
+}
+
+///// rotates and shifts /////
+
+// 0x07, 0x0F, 0x17, 0x1F
+// RLCA RRCA RLA RRA, the same as the CB rotates on register a except z is always reset
+pub fn rotate_a(pc: &mut u16, op: u8, a: &mut u8, f: &mut u8) -> (u8, u8) {
+    *a = rotate_shift(op, *a, f);
+    *f &= !FLAG_Z;
+    *pc += 1;
+
+    (1, 4)
+}
+
+// 0xCB
+// the second byte picks the operation in bits 3-7 and the register in bits 0-2:
+// 00-3F rotates and shifts, 40-7F BIT, 80-BF RES, C0-FF SET
+pub fn cb_r(pc: &mut u16, op: u8, r: &mut u8, f: &mut u8) -> (u8, u8) {
+    *r = cb(op, *r, f);
+    *pc += 2;
+
+    (2, 8)
+}
+
+// 0xCB with (hl), BIT only reads it
+pub fn cb_mem_rr(pc: &mut u16, mmu: &mut mmu::MMU, op: u8, rr: u16, f: &mut u8) -> (u8, u8) {
+    let value = cb(op, mmu.read(rr), f);
+    *pc += 2;
+
+    if op >> 6 == 1 {
+        return (3, 12);
+    }
+    mmu.write(rr, value);
+
+    (4, 16)
+}
+
+fn cb(op: u8, value: u8, f: &mut u8) -> u8 {
+    let bit = (op >> 3) & 0x07;
+
+    match op >> 6 {
+        0 => rotate_shift(bit, value, f),
+        1 => {
+            *f = flags(value & (1 << bit) == 0, false, true, *f & FLAG_C != 0);
+            value
+        },
+        2 => value & !(1 << bit),
+        _ => value | (1 << bit),
+    }
+}
+
+// RLC RRC RL RR SLA SRA SWAP SRL
+fn rotate_shift(op: u8, value: u8, f: &mut u8) -> u8 {
+    let carry = if *f & FLAG_C != 0 { 1 } else { 0 };
+
+    let (result, carry) = match op {
+        0 => (value.rotate_left(1), value >> 7),
+        1 => (value.rotate_right(1), value & 0x01),
+        2 => (value << 1 | carry, value >> 7),
+        3 => (value >> 1 | carry << 7, value & 0x01),
+        4 => (value << 1, value >> 7),
+        5 => (value >> 1 | (value & 0x80), value & 0x01),
+        6 => (value.rotate_left(4), 0),
+        _ => (value >> 1, value & 0x01),
+    };
+    *f = flags(result == 0, false, false, carry != 0);
+
+    result
+}
+
 ///// jumps /////
 
 // JP nn
@@ -257,20 +558,120 @@ pub fn xor_a(pc: &mut u16, a: u8, f: &mut u8) -> (u8, u8) {
 pub fn jp_nn(pc: &mut u16, addr: u16) -> (u8, u8) {
     *pc = addr;
 
+    (4, 16)
+}
+
+// JP cc, nn
+// 0xC2, 0xCA, 0xD2, 0xDA
+pub fn jp_cc_nn(pc: &mut u16, condition: bool, addr: u16) -> (u8, u8) {
+    if !condition {
+        *pc += 3;
+        return (3, 12);
+    }
+
+    jp_nn(pc, addr)
+}
+
+// JP (hl)
+// 0xE9
+pub fn jp_hl(pc: &mut u16, hl: u16) -> (u8, u8) {
+    *pc = hl;
+
+    (1, 4)
+}
+
+// JR n
+// 0x18
+// jumps by signed n from the next instruction
+pub fn jr_n(pc: &mut u16, n: u8) -> (u8, u8) {
+    *pc = pc.wrapping_add(2).wrapping_add(n as i8 as u16);
+
     (3, 12)
 }
 
+// JR cc, n
+// 0x20, 0x28, 0x30, 0x38
+pub fn jr_cc_n(pc: &mut u16, condition: bool, n: u8) -> (u8, u8) {
+    if !condition {
+        *pc += 2;
+        return (2, 8);
+    }
+
+    jr_n(pc, n)
+}
+
+///// calls /////
+
+// CALL nn
+// 0xCD
+// pushes the address of the next instruction and jumps to nn
+pub fn call_nn(pc: &mut u16, sp: &mut u16, mmu: &mut mmu::MMU, addr: u16) -> (u8, u8) {
+    let next = *pc + 3;
+    mmu.push(sp, (next >> 8) as u8);
+    mmu.push(sp, next as u8);
+    *pc = addr;
+
+    (6, 24)
+}
+
+// CALL cc, nn
+// 0xC4, 0xCC, 0xD4, 0xDC
+pub fn call_cc_nn(pc: &mut u16, sp: &mut u16, mmu: &mut mmu::MMU, condition: bool, addr: u16) -> (u8, u8) {
+    if !condition {
+        *pc += 3;
+        return (3, 12);
+    }
+
+    call_nn(pc, sp, mmu, addr)
+}
+
 ///// restarts /////
 
-// 0xFF
-pub fn rst_38(pc: &mut u16, sp: &mut u16, mmu: &mut mmu::MMU) -> (u8, u8) {
-    mmu.push(sp, *pc as u8);
-    mmu.push(sp, (*pc >> 8) as u8);
+// RST n
+// 0xC7, 0xCF, 0xD7, 0xDF, 0xE7, 0xEF, 0xF7, 0xFF
+// a one byte CALL to one of the eight addresses from 0x00 to 0x38
+pub fn rst(pc: &mut u16, sp: &mut u16, mmu: &mut mmu::MMU, addr: u16) -> (u8, u8) {
+    let next = *pc + 1;
+    mmu.push(sp, (next >> 8) as u8);
+    mmu.push(sp, next as u8);
+    *pc = addr;
+
+    (4, 16)
+}
+
+///// returns /////
 
-    // TODO: unclear if this is what's supposed to happen
-    *pc += 0x38;
+// RET
+// 0xC9
+// pops the return address pushed by CALL and jumps to it
+pub fn ret(pc: &mut u16, sp: &mut u16, mmu: &mut mmu::MMU) -> (u8, u8) {
+    let low = mmu.pop(sp) as u16;
+    let high = mmu.pop(sp) as u16;
+    *pc = (high << 8) | low;
 
-    (8, 32)
+    (4, 16)
+}
+
+// RET cc
+// 0xC0, 0xC8, 0xD0, 0xD8
+pub fn ret_cc(pc: &mut u16, sp: &mut u16, mmu: &mut mmu::MMU, condition: bool) -> (u8, u8) {
+    if !condition {
+        *pc += 1;
+        return (2, 8);
+    }
+
+    ret(pc, sp, mmu);
+
+    (5, 20)
+}
+
+// RETI
+// 0xD9
+// returns and enables interrupts straight away
+pub fn reti(pc: &mut u16, sp: &mut u16, mmu: &mut mmu::MMU, ime: &mut bool) -> (u8, u8) {
+    *ime = true;
+
+    ret(pc, sp, mmu)
 }
 
 ///// misc /////
@@ -282,5 +683,63 @@ pub fn nop(pc: &mut u16) -> (u8, u8) {
     (1, 4)
 }
 
-// TODO: skipped these, address them later
-// 0x3E
\ No newline at end of file
+// 0x10
+// performs a CGB speed switch if one is armed in KEY1
+// TODO: low power mode until a button is pressed isn't emulated
+pub fn stop(pc: &mut u16, mmu: &mut mmu::MMU) -> (u8, u8) {
+    mmu.switch_speed();
+    *pc += 2;
+
+    (1, 4)
+}
+
+// 0xD3, 0xDB, 0xDD, 0xE3, 0xE4, 0xEB, 0xEC, 0xED, 0xF4, 0xFC, 0xFD
+// illegal opcodes hang the CPU, pc stays where it is
+pub fn lock(locked: &mut bool) -> (u8, u8) {
+    *locked = true;
+
+    (1, 4)
+}
+
+// 0x76
+// stops executing until an enabled interrupt is requested
+pub fn halt(pc: &mut u16, halted: &mut bool) -> (u8, u8) {
+    *halted = true;
+    *pc += 1;
+
+    (1, 4)
+}
+
+// 0xF3
+// disables interrupts, and cancels an EI that hasn't taken effect yet
+pub fn di(pc: &mut u16, ime: &mut bool, ime_pending: &mut bool) -> (u8, u8) {
+    *ime = false;
+    *ime_pending = false;
+    *pc += 1;
+
+    (1, 4)
+}
+
+// 0xFB
+// enables interrupts once the instruction after it has run
+pub fn ei(pc: &mut u16, ime_pending: &mut bool) -> (u8, u8) {
+    *ime_pending = true;
+    *pc += 1;
+
+    (1, 4)
+}
+
+fn set_rr(r1: &mut u8, r2: &mut u8, rr: u16) {
+    *r1 = (rr >> 8) as u8;
+    *r2 = rr as u8;
+}
+
+fn flags(z: bool, n: bool, h: bool, c: bool) -> u8 {
+    let mut f = 0;
+    if z { f |= FLAG_Z; }
+    if n { f |= FLAG_N; }
+    if h { f |= FLAG_H; }
+    if c { f |= FLAG_C; }
+
+    f
+}
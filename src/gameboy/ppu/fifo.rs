@@ -0,0 +1,313 @@
+// Pixel FIFO renderer. Instead of drawing a whole line at once, this steps the background
+// fetcher, the sprite fetcher and the pixel shifter one dot at a time during mode 3, so
+// writes to SCX, the palettes or LCDC in the middle of a line land on the right pixels and
+// mode 3 takes as long as it would on hardware.
+
+use std::collections::VecDeque;
+
+use super::{ BgPixel, PPU, Sprite, SpritePixel, bank_offset, bg_flip, bg_tile_address };
+use super::{ LCDC_BG_MAP, LCDC_OBJ_ENABLE, LCDC_WINDOW_ENABLE, LCDC_WINDOW_MAP, SCREEN_WIDTH };
+
+// the first fetch of every line is thrown away, which is where mode 3's 172 dot minimum comes from
+const DISCARDED_FETCH_DOTS: u8 = 6;
+
+// fetching a sprite stalls the shifter for 6 dots, plus however long the background fetcher
+// needs to finish the tile it's working on
+const SPRITE_FETCH_DOTS: u8 = 6;
+
+#[derive(Clone, Copy, PartialEq)]
+enum FetchStep {
+    Tile,
+    DataLow,
+    DataHigh,
+    Push,
+}
+
+#[derive(Clone)]
+pub struct Fifo {
+    bg_pixels: VecDeque<BgPixel>,
+    sprite_pixels: VecDeque<SpritePixel>,
+    step: FetchStep,
+    // each fetcher step takes two dots
+    step_dots: u8,
+    // next tile the fetcher will read, counted from the left edge of the line or window
+    tile_x: u8,
+    tile_number: u8,
+    tile_attributes: u8,
+    tile_row: u8,
+    tile_low: u8,
+    tile_high: u8,
+    // pixels sent to the LCD on this line
+    lcd_x: u8,
+    // SCX fine scroll, these pixels are shifted out without being drawn
+    discard: u8,
+    stall: u8,
+    in_window: bool,
+    // WY is only checked against LY, once it matches the window can start on any later line
+    window_y_matched: bool,
+    // sprites on this line that haven't been fetched yet, in OAM order
+    sprites: Vec<Sprite>,
+    pending_sprite: Option<Sprite>,
+}
+
+impl Default for Fifo {
+
+    fn default() -> Fifo {
+        Fifo {
+            bg_pixels: VecDeque::with_capacity(16),
+            sprite_pixels: VecDeque::with_capacity(8),
+            step: FetchStep::Tile,
+            step_dots: 0,
+            tile_x: 0,
+            tile_number: 0,
+            tile_attributes: 0,
+            tile_row: 0,
+            tile_low: 0,
+            tile_high: 0,
+            lcd_x: 0,
+            discard: 0,
+            stall: 0,
+            in_window: false,
+            window_y_matched: false,
+            sprites: Vec::new(),
+            pending_sprite: None,
+        }
+    }
+}
+
+impl Fifo {
+
+    pub fn new() -> Fifo {
+        Default::default()
+    }
+
+    pub fn start_frame(&mut self) {
+        self.window_y_matched = false;
+    }
+}
+
+impl PPU {
+
+    // called at the end of OAM scan
+    pub(super) fn start_fifo_line(&mut self, oam: &[u8]) {
+        let height = self.sprite_height();
+        let sprites = self.sprites_on_line(oam, height);
+        let scx = self.scx;
+        let ly = self.ly;
+        let wy = self.wy;
+
+        let fifo = &mut self.fifo;
+        fifo.bg_pixels.clear();
+        fifo.sprite_pixels.clear();
+        fifo.step = FetchStep::Tile;
+        fifo.step_dots = 0;
+        fifo.tile_x = 0;
+        fifo.lcd_x = 0;
+        fifo.discard = scx % 8;
+        fifo.stall = DISCARDED_FETCH_DOTS;
+        fifo.in_window = false;
+        fifo.sprites = sprites;
+        fifo.pending_sprite = None;
+
+        if ly == wy {
+            fifo.window_y_matched = true;
+        }
+    }
+
+    // runs a single dot of mode 3, returns true once all 160 pixels of the line are out
+    pub(super) fn step_fifo(&mut self, vram: &[u8]) -> bool {
+        if self.fifo.stall > 0 {
+            self.fifo.stall -= 1;
+            if self.fifo.stall == 0 {
+                if let Some(sprite) = self.fifo.pending_sprite.take() {
+                    self.fetch_sprite(vram, &sprite);
+                }
+            }
+            return false;
+        }
+
+        if self.start_sprite_fetch() {
+            return false;
+        }
+
+        self.start_window();
+        self.step_fetcher(vram);
+        self.shift_pixel();
+
+        if self.fifo.lcd_x as usize == SCREEN_WIDTH {
+            if self.fifo.in_window {
+                self.window_line += 1;
+            }
+            return true;
+        }
+
+        false
+    }
+
+    // sprites start fetching once the shifter reaches their left edge
+    fn start_sprite_fetch(&mut self) -> bool {
+        if self.lcdc & LCDC_OBJ_ENABLE == 0 || self.fifo.discard > 0 {
+            return false;
+        }
+
+        let lcd_x = self.fifo.lcd_x as i16;
+        let position = match self.fifo.sprites.iter().position(|sprite| sprite.x <= lcd_x) {
+            Some(position) => position,
+            None => return false,
+        };
+
+        let sprite = self.fifo.sprites.remove(position);
+        let bg_wait = 5u8.saturating_sub((self.fifo.lcd_x.wrapping_add(self.scx)) % 8);
+
+        self.fifo.pending_sprite = Some(sprite);
+        self.fifo.stall = SPRITE_FETCH_DOTS + bg_wait;
+
+        true
+    }
+
+    // when the shifter reaches WX the background is thrown away and the fetcher restarts on the window
+    fn start_window(&mut self) {
+        if self.fifo.in_window || !self.fifo.window_y_matched || self.lcdc & LCDC_WINDOW_ENABLE == 0 {
+            return;
+        }
+
+        if (self.fifo.lcd_x as u16) + 7 < self.wx as u16 {
+            return;
+        }
+
+        let fifo = &mut self.fifo;
+        fifo.in_window = true;
+        fifo.bg_pixels.clear();
+        fifo.step = FetchStep::Tile;
+        fifo.step_dots = 0;
+        fifo.tile_x = 0;
+    }
+
+    fn step_fetcher(&mut self, vram: &[u8]) {
+        if self.fifo.step == FetchStep::Push {
+            // the fetcher can only push into an empty FIFO
+            if self.fifo.bg_pixels.is_empty() {
+                let attributes = self.fifo.tile_attributes;
+                for x in 0..8 {
+                    let (x, _) = bg_flip(attributes, x, 0);
+                    self.fifo.bg_pixels.push_back(BgPixel {
+                        color: fetched_pixel(self.fifo.tile_low, self.fifo.tile_high, x),
+                        attributes: attributes,
+                        window: self.fifo.in_window,
+                    });
+                }
+                self.fifo.tile_x = self.fifo.tile_x.wrapping_add(1);
+                self.fifo.step = FetchStep::Tile;
+            }
+            return;
+        }
+
+        self.fifo.step_dots += 1;
+        if self.fifo.step_dots < 2 {
+            return;
+        }
+        self.fifo.step_dots = 0;
+
+        match self.fifo.step {
+            FetchStep::Tile => {
+                // SCX and SCY are read again for every tile
+                let (map_base, x, y) = if self.fifo.in_window {
+                    let map_base = if self.lcdc & LCDC_WINDOW_MAP != 0 { 0x1C00 } else { 0x1800 };
+                    (map_base, self.fifo.tile_x & 31, self.window_line)
+                } else {
+                    let map_base = if self.lcdc & LCDC_BG_MAP != 0 { 0x1C00 } else { 0x1800 };
+                    (map_base, (self.scx / 8).wrapping_add(self.fifo.tile_x) & 31, self.ly.wrapping_add(self.scy))
+                };
+
+                let map_index = map_base + (y as usize / 8) * 32 + x as usize;
+                self.fifo.tile_number = vram[map_index];
+                self.fifo.tile_attributes = self.bg_attributes(vram, map_index);
+                self.fifo.tile_row = y % 8;
+                self.fifo.step = FetchStep::DataLow;
+            },
+            FetchStep::DataLow => {
+                let address = self.fetcher_row_address();
+                self.fifo.tile_low = vram[address];
+                self.fifo.step = FetchStep::DataHigh;
+            },
+            FetchStep::DataHigh => {
+                let address = self.fetcher_row_address();
+                self.fifo.tile_high = vram[address + 1];
+                self.fifo.step = FetchStep::Push;
+            },
+            FetchStep::Push => {},
+        }
+    }
+
+    fn fetcher_row_address(&self) -> usize {
+        let attributes = self.fifo.tile_attributes;
+        let (_, row) = bg_flip(attributes, 0, self.fifo.tile_row);
+
+        bg_tile_address(self.lcdc, self.fifo.tile_number) + bank_offset(attributes) + row as usize * 2
+    }
+
+    fn shift_pixel(&mut self) {
+        let bg = match self.fifo.bg_pixels.pop_front() {
+            Some(pixel) => pixel,
+            None => return,
+        };
+
+        if self.fifo.discard > 0 {
+            self.fifo.discard -= 1;
+            return;
+        }
+
+        // LCDC and the palettes are sampled as each pixel leaves the FIFO
+        let sprite = match self.fifo.sprite_pixels.pop_front() {
+            Some(sprite) if self.lcdc & LCDC_OBJ_ENABLE != 0 => Some(sprite),
+            _ => None,
+        };
+        let shade = self.mix_pixel(bg, sprite);
+
+        let idx = self.ly as usize * SCREEN_WIDTH + self.fifo.lcd_x as usize;
+        self.framebuffer[idx] = shade;
+        self.fifo.lcd_x += 1;
+    }
+
+    // mixes a sprite's row into the sprite FIFO. on DMG pixels already in there came from sprites
+    // with a smaller x or an earlier OAM slot, so they keep priority unless they are transparent.
+    // CGB lets the earlier OAM slot win no matter which was fetched first
+    fn fetch_sprite(&mut self, vram: &[u8], sprite: &Sprite) {
+        let height = self.sprite_height();
+        let by_x = self.sprite_priority_by_x();
+        let row = (self.ly as i16 - sprite.y) as u8;
+        let lcd_x = self.fifo.lcd_x as i16;
+
+        for x in 0..8 {
+            // sprites hanging off the left edge only contribute the pixels that are still on screen
+            let position = sprite.x + x as i16 - lcd_x;
+            if position < 0 {
+                continue;
+            }
+
+            let pixel = SpritePixel {
+                color: self.sprite_pixel(vram, sprite, x, row, height),
+                attributes: sprite.attributes,
+                oam_index: sprite.oam_index,
+            };
+
+            let position = position as usize;
+            if position < self.fifo.sprite_pixels.len() {
+                let existing = self.fifo.sprite_pixels[position];
+                let replace = existing.color == 0
+                    || (!by_x && pixel.color != 0 && pixel.oam_index < existing.oam_index);
+                if replace {
+                    self.fifo.sprite_pixels[position] = pixel;
+                }
+            } else {
+                self.fifo.sprite_pixels.push_back(pixel);
+            }
+        }
+    }
+}
+
+fn fetched_pixel(low: u8, high: u8, x: u8) -> u8 {
+    let bit = 7 - x;
+
+    (((high >> bit) & 1) << 1) | ((low >> bit) & 1)
+}
@@ -0,0 +1,818 @@
+// PPU registers:
+// FF40   LCDC   LCD control
+// FF41   STAT   LCD status
+// FF42   SCY    background scroll y
+// FF43   SCX    background scroll x
+// FF44   LY     current scanline (read only)
+// FF45   LYC    scanline compare
+// FF47   BGP    background palette
+// FF48   OBP0   sprite palette 0
+// FF49   OBP1   sprite palette 1
+// FF4A   WY     window y position
+// FF4B   WX     window x position + 7
+// FF68   BCPS   CGB background palette index
+// FF69   BCPD   CGB background palette data
+// FF6A   OCPS   CGB sprite palette index
+// FF6B   OCPD   CGB sprite palette data
+// FF6C   OPRI   CGB sprite priority mode
+
+mod fifo;
+
+use gameboy::compat::CompatPalettes;
+use gameboy::state::{ StateReader, StateResult, StateWriter };
+
+pub const SCREEN_WIDTH: usize = 160;
+pub const SCREEN_HEIGHT: usize = 144;
+
+// every line is 456 dots: OAM scan, then drawing, then HBlank for whatever is left.
+// lines 144-153 are VBlank
+const DOTS_PER_LINE: u32 = 456;
+const OAM_SCAN_DOTS: u32 = 80;
+const DRAWING_DOTS: u32 = 172;
+const LINES_PER_FRAME: u8 = 154;
+pub const DOTS_PER_FRAME: u32 = DOTS_PER_LINE * LINES_PER_FRAME as u32;
+const MAX_SPRITES_PER_LINE: usize = 10;
+const OAM_ENTRIES: usize = 40;
+
+// LCDC bits
+const LCDC_ENABLE: u8 = 0b10000000;
+const LCDC_WINDOW_MAP: u8 = 0b01000000;
+const LCDC_WINDOW_ENABLE: u8 = 0b00100000;
+const LCDC_TILE_DATA: u8 = 0b00010000;
+const LCDC_BG_MAP: u8 = 0b00001000;
+const LCDC_OBJ_SIZE: u8 = 0b00000100;
+const LCDC_OBJ_ENABLE: u8 = 0b00000010;
+const LCDC_BG_ENABLE: u8 = 0b00000001;
+
+// STAT bits
+const STAT_LYC_INT: u8 = 0b01000000;
+const STAT_OAM_INT: u8 = 0b00100000;
+const STAT_VBLANK_INT: u8 = 0b00010000;
+const STAT_HBLANK_INT: u8 = 0b00001000;
+const STAT_COINCIDENCE: u8 = 0b00000100;
+
+// interrupt flags returned from tick, same bits as IF
+pub const INT_VBLANK: u8 = 0b00000001;
+pub const INT_STAT: u8 = 0b00000010;
+
+// sprite attribute bits, CGB background attributes use the same layout except for bit 4
+const ATTR_PRIORITY: u8 = 0b10000000;
+const ATTR_Y_FLIP: u8 = 0b01000000;
+const ATTR_X_FLIP: u8 = 0b00100000;
+const ATTR_PALETTE: u8 = 0b00010000;
+const ATTR_BANK: u8 = 0b00001000;
+const ATTR_CGB_PALETTE: u8 = 0b00000111;
+
+const VRAM_BANK_SIZE: usize = 8192;
+const PALETTE_RAM_SIZE: usize = 64;
+
+// BCPS and OCPS bit 7 moves the index forward after every write to the data register
+const PALETTE_AUTO_INCREMENT: u8 = 0b10000000;
+const PALETTE_INDEX: u8 = 0b00111111;
+
+// the scanline renderer draws a whole line at the end of mode 3, which is fast but misses
+// register writes made in the middle of a line. the FIFO renderer draws pixel by pixel
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Renderer {
+    Scanline,
+    Fifo,
+}
+
+// layers that can be hidden for debugging
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Layer {
+    Background,
+    Window,
+    Sprites,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Mode {
+    HBlank = 0,
+    VBlank = 1,
+    OamScan = 2,
+    Drawing = 3,
+}
+
+// color number plus CGB attributes, which stay 0 on DMG
+#[derive(Clone, Copy)]
+struct BgPixel {
+    color: u8,
+    attributes: u8,
+    window: bool,
+}
+
+#[derive(Clone, Copy)]
+struct SpritePixel {
+    color: u8,
+    attributes: u8,
+    oam_index: usize,
+}
+
+#[derive(Clone, Copy)]
+struct Sprite {
+    y: i16,
+    x: i16,
+    tile: u8,
+    attributes: u8,
+    oam_index: usize,
+}
+
+#[derive(Clone)]
+pub struct PPU {
+    lcdc: u8,
+    stat: u8,
+    scy: u8,
+    scx: u8,
+    ly: u8,
+    lyc: u8,
+    bgp: u8,
+    obp0: u8,
+    obp1: u8,
+    wy: u8,
+    wx: u8,
+    cgb_mode: bool,
+    // a DMG game on CGB hardware, shades are looked up in palette RAM set up by the boot ROM
+    compat_mode: bool,
+    bcps: u8,
+    ocps: u8,
+    opri: u8,
+    bg_palettes: Vec<u8>,
+    obj_palettes: Vec<u8>,
+    mode: Mode,
+    // dots into the current line
+    dots: u32,
+    // STAT interrupts only fire when this goes from low to high
+    stat_line: bool,
+    window_line: u8,
+    // set when mode 3 ends so HBlank DMA knows to copy a block
+    hblank_started: bool,
+    renderer: Renderer,
+    // the renderer used for the line being drawn, switching only happens between lines
+    drawing_renderer: Renderer,
+    // dot up to which the FIFO has been run
+    drawing_position: u32,
+    fifo: fifo::Fifo,
+    // debug switches, these only change what ends up in the framebuffer and aren't saved
+    show_background: bool,
+    show_window: bool,
+    show_sprites: bool,
+    // bit n hides OAM entry n
+    hidden_sprites: u64,
+    // row major, a shade (0-3) per pixel on DMG or a 15-bit color in CGB mode
+    framebuffer: Vec<u16>,
+}
+
+impl Default for PPU {
+
+    fn default() -> PPU {
+        PPU {
+            lcdc: 0x00,
+            stat: 0x00,
+            scy: 0x00,
+            scx: 0x00,
+            ly: 0x00,
+            lyc: 0x00,
+            bgp: 0x00,
+            obp0: 0x00,
+            obp1: 0x00,
+            wy: 0x00,
+            wx: 0x00,
+            cgb_mode: false,
+            compat_mode: false,
+            bcps: 0x00,
+            ocps: 0x00,
+            opri: 0x00,
+            bg_palettes: vec![0xFF; PALETTE_RAM_SIZE],
+            obj_palettes: vec![0x00; PALETTE_RAM_SIZE],
+            mode: Mode::OamScan,
+            dots: 0,
+            stat_line: false,
+            window_line: 0,
+            hblank_started: false,
+            renderer: Renderer::Scanline,
+            drawing_renderer: Renderer::Scanline,
+            drawing_position: 0,
+            fifo: fifo::Fifo::new(),
+            show_background: true,
+            show_window: true,
+            show_sprites: true,
+            hidden_sprites: 0,
+            framebuffer: vec![0; SCREEN_WIDTH * SCREEN_HEIGHT],
+        }
+    }
+}
+
+impl PPU {
+
+    pub fn new() -> PPU {
+        Default::default()
+    }
+
+    pub fn read_register(&self, address: u16) -> u8 {
+        match address {
+            0xFF40 => self.lcdc,
+            0xFF41 => self.read_stat(),
+            0xFF42 => self.scy,
+            0xFF43 => self.scx,
+            0xFF44 => self.ly,
+            0xFF45 => self.lyc,
+            0xFF47 => self.bgp,
+            0xFF48 => self.obp0,
+            0xFF49 => self.obp1,
+            0xFF4A => self.wy,
+            0xFF4B => self.wx,
+            0xFF68 if self.cgb_mode => self.bcps | 0x40,
+            0xFF69 if self.cgb_mode => self.read_palette(&self.bg_palettes, self.bcps),
+            0xFF6A if self.cgb_mode => self.ocps | 0x40,
+            0xFF6B if self.cgb_mode => self.read_palette(&self.obj_palettes, self.ocps),
+            0xFF6C if self.cgb_mode => 0xFE | self.opri,
+            _ => 0xFF,
+        }
+    }
+
+    // returns the interrupts the write raised
+    pub fn write_register(&mut self, address: u16, data: u8) -> u8 {
+        match address {
+            0xFF40 => {
+                // turning the LCD off resets the current line, turning it on starts a fresh frame
+                if data & LCDC_ENABLE == 0 && self.lcdc & LCDC_ENABLE != 0 {
+                    self.ly = 0;
+                    self.dots = 0;
+                    self.window_line = 0;
+                    self.mode = Mode::HBlank;
+                    self.stat_line = false;
+                } else if data & LCDC_ENABLE != 0 && self.lcdc & LCDC_ENABLE == 0 {
+                    self.mode = Mode::OamScan;
+                }
+                self.lcdc = data;
+            },
+            // only the interrupt select bits are writable
+            0xFF41 => self.stat = data & 0b01111000,
+            0xFF42 => self.scy = data,
+            0xFF43 => self.scx = data,
+            0xFF44 => {},  // read only
+            0xFF45 => self.lyc = data,
+            0xFF47 => self.bgp = data,
+            0xFF48 => self.obp0 = data,
+            0xFF49 => self.obp1 = data,
+            0xFF4A => self.wy = data,
+            0xFF4B => self.wx = data,
+            0xFF68 if self.cgb_mode => self.bcps = data & !0x40,
+            0xFF69 if self.cgb_mode => {
+                let accessible = self.palettes_accessible();
+                write_palette(&mut self.bg_palettes, &mut self.bcps, data, accessible);
+            },
+            0xFF6A if self.cgb_mode => self.ocps = data & !0x40,
+            0xFF6B if self.cgb_mode => {
+                let accessible = self.palettes_accessible();
+                write_palette(&mut self.obj_palettes, &mut self.ocps, data, accessible);
+            },
+            0xFF6C if self.cgb_mode => self.opri = data & 0x01,
+            _ => {}
+        }
+
+        // the STAT line doesn't wait for the next tick, so writing LYC or the enable bits can make
+        // it rise (or hold it high and block the next source) straight away
+        match address {
+            0xFF40 | 0xFF41 | 0xFF45 if self.lcdc & LCDC_ENABLE != 0 => self.update_stat_line(),
+            _ => 0,
+        }
+    }
+
+    pub fn set_cgb_mode(&mut self, cgb_mode: bool) {
+        self.cgb_mode = cgb_mode;
+        self.compat_mode = false;
+    }
+
+    // loads the background palette and both sprite palettes the way the CGB boot ROM does
+    pub fn set_compat_palettes(&mut self, palettes: &CompatPalettes) {
+        self.compat_mode = true;
+
+        for (i, color) in palettes[0].iter().enumerate() {
+            self.bg_palettes[i * 2] = *color as u8;
+            self.bg_palettes[i * 2 + 1] = (*color >> 8) as u8;
+        }
+
+        for (i, color) in palettes[1].iter().chain(palettes[2].iter()).enumerate() {
+            self.obj_palettes[i * 2] = *color as u8;
+            self.obj_palettes[i * 2 + 1] = (*color >> 8) as u8;
+        }
+    }
+
+    // true when the framebuffer holds 15-bit colors rather than shades
+    pub fn color_output(&self) -> bool {
+        self.cgb_mode || self.compat_mode
+    }
+
+    // the 4 colors of a CGB palette, whatever the PPU is doing. DMG games colorized by the CGB
+    // use background palette 0 and sprite palettes 0 and 1
+    pub fn cgb_palette(&self, sprites: bool, palette: u8) -> [u16; 4] {
+        let palette_ram = if sprites { &self.obj_palettes } else { &self.bg_palettes };
+        let mut colors = [0; 4];
+        for (color, value) in colors.iter_mut().enumerate() {
+            *value = cgb_color(palette_ram, palette & 0x07, color as u8);
+        }
+
+        colors
+    }
+
+    // palette RAM is locked while the PPU is drawing
+    fn palettes_accessible(&self) -> bool {
+        self.vram_accessible()
+    }
+
+    fn read_palette(&self, palette_ram: &[u8], index: u8) -> u8 {
+        if !self.palettes_accessible() {
+            return 0xFF;
+        }
+
+        palette_ram[(index & PALETTE_INDEX) as usize]
+    }
+
+    // takes effect from the next line
+    pub fn set_renderer(&mut self, renderer: Renderer) {
+        self.renderer = renderer;
+    }
+
+    pub fn set_layer_visible(&mut self, layer: Layer, visible: bool) {
+        match layer {
+            Layer::Background => self.show_background = visible,
+            Layer::Window => self.show_window = visible,
+            Layer::Sprites => self.show_sprites = visible,
+        }
+    }
+
+    pub fn layer_visible(&self, layer: Layer) -> bool {
+        match layer {
+            Layer::Background => self.show_background,
+            Layer::Window => self.show_window,
+            Layer::Sprites => self.show_sprites,
+        }
+    }
+
+    // hidden sprites still count towards the 10 per line and still stall the FIFO
+    pub fn set_sprite_visible(&mut self, oam_index: usize, visible: bool) {
+        if oam_index >= OAM_ENTRIES {
+            return;
+        }
+
+        if visible {
+            self.hidden_sprites &= !(1 << oam_index);
+        } else {
+            self.hidden_sprites |= 1 << oam_index;
+        }
+    }
+
+    pub fn sprite_visible(&self, oam_index: usize) -> bool {
+        oam_index >= OAM_ENTRIES || self.hidden_sprites & (1 << oam_index) == 0
+    }
+
+    pub fn save_state(&self, state: &mut StateWriter) {
+        for value in &[self.lcdc, self.stat, self.scy, self.scx, self.ly, self.lyc,
+                       self.bgp, self.obp0, self.obp1, self.wy, self.wx, self.window_line,
+                       self.bcps, self.ocps, self.opri] {
+            state.write_u8(*value);
+        }
+        state.write_bool(self.cgb_mode);
+        state.write_bool(self.compat_mode);
+        state.write_bytes(&self.bg_palettes);
+        state.write_bytes(&self.obj_palettes);
+        state.write_u8(self.mode as u8);
+        state.write_u32(self.dots);
+        state.write_bool(self.stat_line);
+        for pixel in &self.framebuffer {
+            state.write_u16(*pixel);
+        }
+    }
+
+    pub fn load_state(&mut self, state: &mut StateReader) -> StateResult<()> {
+        for value in &mut [&mut self.lcdc, &mut self.stat, &mut self.scy, &mut self.scx, &mut self.ly, &mut self.lyc,
+                           &mut self.bgp, &mut self.obp0, &mut self.obp1, &mut self.wy, &mut self.wx, &mut self.window_line,
+                           &mut self.bcps, &mut self.ocps, &mut self.opri] {
+            **value = state.read_u8()?;
+        }
+        self.cgb_mode = state.read_bool()?;
+        self.compat_mode = state.read_bool()?;
+        state.read_bytes(&mut self.bg_palettes)?;
+        state.read_bytes(&mut self.obj_palettes)?;
+        self.mode = match state.read_u8()? {
+            0 => Mode::HBlank,
+            1 => Mode::VBlank,
+            2 => Mode::OamScan,
+            _ => Mode::Drawing,
+        };
+        self.dots = state.read_u32()?;
+        self.stat_line = state.read_bool()?;
+        for pixel in self.framebuffer.iter_mut() {
+            *pixel = state.read_u16()?;
+        }
+
+        // FIFO state isn't saved, so a line that was in the middle of drawing is finished whole
+        self.drawing_renderer = Renderer::Scanline;
+
+        Ok(())
+    }
+
+    pub fn framebuffer(&self) -> &[u16] {
+        &self.framebuffer
+    }
+
+    pub fn lcd_enabled(&self) -> bool {
+        self.lcdc & LCDC_ENABLE != 0
+    }
+
+    pub fn take_hblank_started(&mut self) -> bool {
+        let started = self.hblank_started;
+        self.hblank_started = false;
+
+        started
+    }
+
+    // the CPU can't see VRAM while the PPU is drawing from it
+    pub fn vram_accessible(&self) -> bool {
+        self.lcdc & LCDC_ENABLE == 0 || self.mode != Mode::Drawing
+    }
+
+    // OAM is locked from the start of OAM scan until drawing is done
+    pub fn oam_accessible(&self) -> bool {
+        self.lcdc & LCDC_ENABLE == 0 || (self.mode != Mode::OamScan && self.mode != Mode::Drawing)
+    }
+
+    // advances the PPU by a number of dots (t cycles), returns the interrupts it raised
+    pub fn tick(&mut self, cycles: u32, vram: &[u8], oam: &[u8]) -> u8 {
+        if self.lcdc & LCDC_ENABLE == 0 {
+            return 0;
+        }
+
+        let mut interrupts = 0;
+        self.dots += cycles;
+
+        loop {
+            match self.mode {
+                Mode::OamScan if self.dots >= OAM_SCAN_DOTS => {
+                    self.mode = Mode::Drawing;
+                    self.drawing_renderer = self.renderer;
+                    self.drawing_position = OAM_SCAN_DOTS;
+                    if self.drawing_renderer == Renderer::Fifo {
+                        self.start_fifo_line(oam);
+                    }
+                },
+                Mode::Drawing => {
+                    if !self.step_drawing(vram, oam) {
+                        break;
+                    }
+                    self.mode = Mode::HBlank;
+                    self.hblank_started = true;
+                },
+                Mode::HBlank if self.dots >= DOTS_PER_LINE => {
+                    self.dots -= DOTS_PER_LINE;
+                    self.ly += 1;
+
+                    if self.ly as usize == SCREEN_HEIGHT {
+                        self.mode = Mode::VBlank;
+                        interrupts |= INT_VBLANK;
+                    } else {
+                        self.mode = Mode::OamScan;
+                    }
+                },
+                Mode::VBlank if self.dots >= DOTS_PER_LINE => {
+                    self.dots -= DOTS_PER_LINE;
+                    self.ly += 1;
+
+                    if self.ly == LINES_PER_FRAME {
+                        self.ly = 0;
+                        self.window_line = 0;
+                        self.fifo.start_frame();
+                        self.mode = Mode::OamScan;
+                    }
+                },
+                _ => break,
+            }
+
+            interrupts |= self.update_stat_line();
+        }
+
+        interrupts
+    }
+
+    // catches mode 3 up to the current dot, returns true once the line is finished
+    fn step_drawing(&mut self, vram: &[u8], oam: &[u8]) -> bool {
+        match self.drawing_renderer {
+            Renderer::Scanline => {
+                if self.dots < OAM_SCAN_DOTS + DRAWING_DOTS {
+                    return false;
+                }
+                self.render_scanline(vram, oam);
+                true
+            },
+            Renderer::Fifo => {
+                while self.drawing_position < self.dots {
+                    self.drawing_position += 1;
+                    if self.step_fifo(vram) {
+                        return true;
+                    }
+                }
+                false
+            },
+        }
+    }
+
+    fn read_stat(&self) -> u8 {
+        let coincidence = if self.ly == self.lyc { STAT_COINCIDENCE } else { 0 };
+
+        // the mode reads as 0 while the LCD is off
+        let mode = if self.lcdc & LCDC_ENABLE != 0 { self.mode as u8 } else { 0 };
+
+        0x80 | self.stat | coincidence | mode
+    }
+
+    // every enabled STAT source is ORed into one line and the interrupt only fires on its rising
+    // edge, so a source that becomes true while another is already holding the line high is lost
+    fn update_stat_line(&mut self) -> u8 {
+        let line = (self.stat & STAT_LYC_INT != 0 && self.ly == self.lyc)
+            || (self.stat & STAT_HBLANK_INT != 0 && self.mode == Mode::HBlank)
+            || (self.stat & STAT_VBLANK_INT != 0 && self.mode == Mode::VBlank)
+            // the OAM source also fires on the first line of VBlank
+            || (self.stat & STAT_OAM_INT != 0
+                && (self.mode == Mode::OamScan || (self.mode == Mode::VBlank && self.ly as usize == SCREEN_HEIGHT)));
+
+        let rising = line && !self.stat_line;
+        self.stat_line = line;
+
+        if rising { INT_STAT } else { 0 }
+    }
+
+    fn render_scanline(&mut self, vram: &[u8], oam: &[u8]) {
+        let mut bg_line = [BgPixel { color: 0, attributes: 0, window: false }; SCREEN_WIDTH];
+
+        // with bit 0 off, DMG blanks both the background and the window. CGB still draws
+        // them and only uses the bit for priority
+        if self.cgb_mode || self.lcdc & LCDC_BG_ENABLE != 0 {
+            self.render_background(vram, &mut bg_line);
+            self.render_window(vram, &mut bg_line);
+        }
+
+        let mut sprite_line = [None; SCREEN_WIDTH];
+        if self.lcdc & LCDC_OBJ_ENABLE != 0 {
+            self.render_sprites(vram, oam, &mut sprite_line);
+        }
+
+        let row = self.ly as usize * SCREEN_WIDTH;
+        for x in 0..SCREEN_WIDTH {
+            self.framebuffer[row + x] = self.mix_pixel(bg_line[x], sprite_line[x]);
+        }
+    }
+
+    fn render_background(&self, vram: &[u8], bg_line: &mut [BgPixel; SCREEN_WIDTH]) {
+        let map_base = if self.lcdc & LCDC_BG_MAP != 0 { 0x1C00 } else { 0x1800 };
+        let y = self.ly.wrapping_add(self.scy);
+
+        for x in 0..SCREEN_WIDTH {
+            let map_x = (x as u8).wrapping_add(self.scx);
+            bg_line[x] = self.map_pixel(vram, map_base, map_x, y);
+        }
+    }
+
+    fn render_window(&mut self, vram: &[u8], bg_line: &mut [BgPixel; SCREEN_WIDTH]) {
+        if self.lcdc & LCDC_WINDOW_ENABLE == 0 || self.ly < self.wy || self.wx > 166 {
+            return;
+        }
+
+        let map_base = if self.lcdc & LCDC_WINDOW_MAP != 0 { 0x1C00 } else { 0x1800 };
+        let start_x = self.wx as i16 - 7;
+
+        for x in 0..SCREEN_WIDTH {
+            let window_x = x as i16 - start_x;
+            if window_x < 0 {
+                continue;
+            }
+
+            bg_line[x] = self.map_pixel(vram, map_base, window_x as u8, self.window_line);
+            bg_line[x].window = true;
+        }
+
+        // the window keeps its own line counter, so hiding it for a few lines doesn't skip rows
+        self.window_line += 1;
+    }
+
+    // a pixel in the 256x256 map starting at map_base
+    fn map_pixel(&self, vram: &[u8], map_base: usize, x: u8, y: u8) -> BgPixel {
+        let map_index = map_base + (y as usize / 8) * 32 + (x as usize / 8);
+        let tile_number = vram[map_index];
+        let attributes = self.bg_attributes(vram, map_index);
+
+        let (tile_x, tile_y) = bg_flip(attributes, x % 8, y % 8);
+        let tile_address = bg_tile_address(self.lcdc, tile_number) + bank_offset(attributes);
+
+        BgPixel {
+            color: tile_pixel(vram, tile_address, tile_x, tile_y),
+            attributes: attributes,
+            window: false,
+        }
+    }
+
+    // CGB keeps a second map in VRAM bank 1 with palette, bank, flip and priority bits for every
+    // tile of the first. DMG has no attributes
+    fn bg_attributes(&self, vram: &[u8], map_index: usize) -> u8 {
+        if self.cgb_mode {
+            vram[VRAM_BANK_SIZE + map_index]
+        } else {
+            0
+        }
+    }
+
+    fn render_sprites(&self, vram: &[u8], oam: &[u8], sprite_line: &mut [Option<SpritePixel>; SCREEN_WIDTH]) {
+        let height = self.sprite_height();
+        let mut sprites = self.sprites_on_line(oam, height);
+
+        // on DMG the sprite with the smaller x wins and ties go to the earlier OAM entry,
+        // CGB only looks at the OAM entry
+        if self.sprite_priority_by_x() {
+            sprites.sort_by_key(|sprite| (sprite.x, sprite.oam_index));
+        }
+
+        for x in 0..SCREEN_WIDTH {
+            for sprite in &sprites {
+                let sprite_x = x as i16 - sprite.x;
+                if sprite_x < 0 || sprite_x >= 8 {
+                    continue;
+                }
+
+                let color = self.sprite_pixel(vram, sprite, sprite_x as u8, (self.ly as i16 - sprite.y) as u8, height);
+                if color == 0 {
+                    // transparent, a sprite further down the list might still cover this pixel
+                    continue;
+                }
+
+                sprite_line[x] = Some(SpritePixel {
+                    color: color,
+                    attributes: sprite.attributes,
+                    oam_index: sprite.oam_index,
+                });
+                break;
+            }
+        }
+    }
+
+    fn sprite_priority_by_x(&self) -> bool {
+        !self.cgb_mode || self.opri & 0x01 != 0
+    }
+
+    fn sprite_height(&self) -> i16 {
+        if tall_sprites(self.lcdc) { 16 } else { 8 }
+    }
+
+    // the first 10 sprites in OAM order that overlap the current line
+    fn sprites_on_line(&self, oam: &[u8], height: i16) -> Vec<Sprite> {
+        let ly = self.ly as i16;
+        let mut sprites = Vec::with_capacity(MAX_SPRITES_PER_LINE);
+
+        for i in 0..OAM_ENTRIES {
+            let entry = &oam[i * 4..i * 4 + 4];
+            let y = entry[0] as i16 - 16;
+            if ly < y || ly >= y + height {
+                continue;
+            }
+
+            sprites.push(Sprite {
+                y: y,
+                x: entry[1] as i16 - 8,
+                tile: entry[2],
+                attributes: entry[3],
+                oam_index: i,
+            });
+
+            if sprites.len() == MAX_SPRITES_PER_LINE {
+                break;
+            }
+        }
+
+        sprites
+    }
+
+    fn sprite_pixel(&self, vram: &[u8], sprite: &Sprite, x: u8, y: u8, height: i16) -> u8 {
+        // a hidden sprite is transparent, so whatever is under it shows through
+        if !self.sprite_visible(sprite.oam_index) {
+            return 0;
+        }
+
+        let x = if sprite.attributes & ATTR_X_FLIP != 0 { 7 - x } else { x };
+        let y = if sprite.attributes & ATTR_Y_FLIP != 0 { height as u8 - 1 - y } else { y };
+
+        // 8x16 sprites ignore bit 0 of the tile number, the bottom half is the next tile
+        let tile = if height == 16 { sprite.tile & 0xFE } else { sprite.tile };
+        let bank = if self.cgb_mode { bank_offset(sprite.attributes) } else { 0 };
+        let tile_address = bank + tile as usize * 16;
+
+        tile_pixel(vram, tile_address, x, y)
+    }
+
+    // decides between the background and the highest priority sprite at a pixel and returns
+    // the value for the framebuffer
+    fn mix_pixel(&self, bg: BgPixel, sprite: Option<SpritePixel>) -> u16 {
+        // hidden layers come out as color 0, which also lets sprites behind them show
+        let hidden = if bg.window { !self.show_window } else { !self.show_background };
+        let bg = if hidden { BgPixel { color: 0, attributes: 0, window: bg.window } } else { bg };
+        let sprite = sprite.filter(|_| self.show_sprites);
+
+        if self.cgb_mode {
+            if let Some(sprite) = sprite {
+                // with LCDC bit 0 off sprites always win, otherwise either priority bit puts
+                // colors 1-3 of the background on top
+                let bg_on_top = self.lcdc & LCDC_BG_ENABLE != 0
+                    && bg.color != 0
+                    && (bg.attributes & ATTR_PRIORITY != 0 || sprite.attributes & ATTR_PRIORITY != 0);
+
+                if sprite.color != 0 && !bg_on_top {
+                    return cgb_color(&self.obj_palettes, sprite.attributes & ATTR_CGB_PALETTE, sprite.color);
+                }
+            }
+
+            return cgb_color(&self.bg_palettes, bg.attributes & ATTR_CGB_PALETTE, bg.color);
+        }
+
+        // with the background off every pixel counts as color 0, so sprites behind it still show
+        let bg_color = if self.lcdc & LCDC_BG_ENABLE != 0 { bg.color } else { 0 };
+
+        if let Some(sprite) = sprite {
+            if sprite.color != 0 && (sprite.attributes & ATTR_PRIORITY == 0 || bg_color == 0) {
+                let (palette, number) = if sprite.attributes & ATTR_PALETTE != 0 { (self.obp1, 1) } else { (self.obp0, 0) };
+                return self.dmg_color(&self.obj_palettes, number, palette_shade(palette, sprite.color));
+            }
+        }
+
+        // DMG shows white when the background is off, not color 0 of BGP
+        if self.lcdc & LCDC_BG_ENABLE == 0 {
+            return self.dmg_color(&self.bg_palettes, 0, 0);
+        }
+
+        self.dmg_color(&self.bg_palettes, 0, palette_shade(self.bgp, bg.color))
+    }
+
+    fn dmg_color(&self, palette_ram: &[u8], palette: u8, shade: u8) -> u16 {
+        if self.compat_mode {
+            cgb_color(palette_ram, palette, shade)
+        } else {
+            shade as u16
+        }
+    }
+}
+
+// blocked writes are dropped but still move the index forward
+fn write_palette(palette_ram: &mut [u8], index: &mut u8, data: u8, accessible: bool) {
+    if accessible {
+        palette_ram[(*index & PALETTE_INDEX) as usize] = data;
+    }
+
+    if *index & PALETTE_AUTO_INCREMENT != 0 {
+        *index = PALETTE_AUTO_INCREMENT | ((*index + 1) & PALETTE_INDEX);
+    }
+}
+
+// tiles in bank 1 start right after bank 0 in the vram slice
+fn bank_offset(attributes: u8) -> usize {
+    if attributes & ATTR_BANK != 0 { VRAM_BANK_SIZE } else { 0 }
+}
+
+fn bg_flip(attributes: u8, x: u8, y: u8) -> (u8, u8) {
+    let x = if attributes & ATTR_X_FLIP != 0 { 7 - x } else { x };
+    let y = if attributes & ATTR_Y_FLIP != 0 { 7 - y } else { y };
+
+    (x, y)
+}
+
+// LCDC bit 4 picks between unsigned numbering from 0x8000 and signed numbering from 0x9000
+pub fn bg_tile_address(lcdc: u8, tile_number: u8) -> usize {
+    if lcdc & LCDC_TILE_DATA != 0 {
+        tile_number as usize * 16
+    } else {
+        (0x1000 + (tile_number as i8 as i32) * 16) as usize
+    }
+}
+
+// LCDC bit 2 makes sprites 8x16
+pub fn tall_sprites(lcdc: u8) -> bool {
+    lcdc & LCDC_OBJ_SIZE != 0
+}
+
+// tiles are 16 bytes, two per row: the first holds the low bit of each pixel, the second the high bit
+pub fn tile_pixel(vram: &[u8], tile_address: usize, x: u8, y: u8) -> u8 {
+    let low = vram[tile_address + y as usize * 2];
+    let high = vram[tile_address + y as usize * 2 + 1];
+    let bit = 7 - x;
+
+    (((high >> bit) & 1) << 1) | ((low >> bit) & 1)
+}
+
+// palettes hold 4 shades, 2 bits each, indexed by color number
+fn palette_shade(palette: u8, color: u8) -> u8 {
+    (palette >> (color * 2)) & 0b11
+}
+
+// CGB palette RAM holds 8 palettes of 4 little endian colors, 5 bits each of red, green and blue
+fn cgb_color(palette_ram: &[u8], palette: u8, color: u8) -> u16 {
+    let idx = palette as usize * 8 + color as usize * 2;
+
+    ((palette_ram[idx + 1] as u16) << 8 | palette_ram[idx] as u16) & 0x7FFF
+}
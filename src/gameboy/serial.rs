@@ -0,0 +1,139 @@
+// Serial registers:
+// FF01   SB   the byte being sent, bits come in at the bottom as the old ones go out the top
+// FF02   SC   bit 7 starts a transfer and reads set until it's done, bit 1 picks the fast clock
+//             (CGB mode only), bit 0 drives the clock from this side
+//
+// With the internal clock a bit goes each 512 t cycles (8192 Hz), or each 16 with the fast
+// clock. With the external clock nothing happens until the other side drives it. There's no
+// cable to plug anything into, so the input line is pulled high and every bit that comes in is
+// a 1, and a transfer on the external clock waits forever.
+
+use gameboy::state::{ StateReader, StateResult, StateWriter };
+
+pub const INT_SERIAL: u8 = 0b00001000;
+
+const SB: u16 = 0xFF01;
+const SC: u16 = 0xFF02;
+
+const BIT_CYCLES: u32 = 512;
+const FAST_BIT_CYCLES: u32 = 16;
+
+// sent bytes nobody takes are dropped past this, oldest first
+const MAX_SENT: usize = 4096;
+
+#[derive(Clone)]
+pub struct Serial {
+    sb: u8,
+    sc: u8,
+    // bits left in the transfer that's running on the internal clock
+    bits_left: u8,
+    // t cycles until the next bit goes
+    bit_timer: u32,
+    // the bits that have gone out the top of SB so far
+    shifted_out: u8,
+    // bytes sent on the internal clock that the frontend hasn't taken yet
+    sent: Vec<u8>,
+}
+
+impl Default for Serial {
+
+    fn default() -> Serial {
+        Serial {
+            sb: 0x00,
+            sc: 0x00,
+            bits_left: 0,
+            bit_timer: 0,
+            shifted_out: 0,
+            sent: Vec::new(),
+        }
+    }
+}
+
+impl Serial {
+
+    pub fn new() -> Serial {
+        Default::default()
+    }
+
+    // unused bits read high, the fast clock bit only exists in CGB mode
+    pub fn read_register(&self, address: u16, cgb_mode: bool) -> u8 {
+        match address {
+            SB => self.sb,
+            SC if cgb_mode => 0x7C | self.sc,
+            SC => 0x7E | self.sc,
+            _ => 0xFF,
+        }
+    }
+
+    pub fn write_register(&mut self, address: u16, data: u8, cgb_mode: bool) {
+        match address {
+            SB => self.sb = data,
+            SC => {
+                self.sc = data & if cgb_mode { 0x83 } else { 0x81 };
+                self.bits_left = 0;
+
+                if self.sc & 0x81 == 0x81 {
+                    self.bits_left = 8;
+                    self.bit_timer = self.bit_cycles();
+                }
+            },
+            _ => {}
+        }
+    }
+
+    fn bit_cycles(&self) -> u32 {
+        if self.sc & 0x02 != 0 { FAST_BIT_CYCLES } else { BIT_CYCLES }
+    }
+
+    // t cycles at CPU speed, like the timer the serial clock runs twice as fast in double
+    // speed mode. returns the interrupts to request
+    pub fn tick(&mut self, cycles: u32) -> u8 {
+        let mut cycles = cycles;
+
+        while self.bits_left > 0 {
+            if cycles < self.bit_timer {
+                self.bit_timer -= cycles;
+                break;
+            }
+            cycles -= self.bit_timer;
+            self.bit_timer = self.bit_cycles();
+
+            self.shifted_out = self.shifted_out << 1 | self.sb >> 7;
+            self.sb = self.sb << 1 | 0x01;
+            self.bits_left -= 1;
+
+            if self.bits_left == 0 {
+                if self.sent.len() >= MAX_SENT {
+                    self.sent.remove(0);
+                }
+                self.sent.push(self.shifted_out);
+                self.sc &= 0x7F;
+                return INT_SERIAL;
+            }
+        }
+
+        0
+    }
+
+    pub fn drain_sent(&mut self, output: &mut Vec<u8>) {
+        output.extend(self.sent.drain(..));
+    }
+
+    pub fn save_state(&self, state: &mut StateWriter) {
+        state.write_u8(self.sb);
+        state.write_u8(self.sc);
+        state.write_u8(self.bits_left);
+        state.write_u16(self.bit_timer as u16);
+        state.write_u8(self.shifted_out);
+    }
+
+    pub fn load_state(&mut self, state: &mut StateReader) -> StateResult<()> {
+        self.sb = state.read_u8()?;
+        self.sc = state.read_u8()? & 0x83;
+        self.bits_left = state.read_u8()?.min(8);
+        self.bit_timer = (state.read_u16()? as u32).min(BIT_CYCLES);
+        self.shifted_out = state.read_u8()?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,607 @@
+// Super Game Boy. The game talks to the SNES side by pulsing the select lines of P1 (FF00):
+// both low resets the link, then 128 bits follow (P14 low is a 0, P15 low is a 1, each
+// followed by both high) and a final 0 stop bit. Every packet is 16 bytes and the first byte
+// of a command holds its id (upper 5 bits) and how many packets it spans (lower 3 bits).
+//
+// Commands that move bulk data (PAL_TRN, CHR_TRN, PCT_TRN, ATTR_TRN) don't send it through
+// P1, the game puts it on screen as BG tiles and the SGB grabs VRAM on the next frame.
+//
+// The output is 256x224: the colorized game screen in the middle of the border.
+
+use gameboy::ppu::{ SCREEN_HEIGHT, SCREEN_WIDTH };
+use gameboy::state::{ StateReader, StateResult, StateWriter };
+
+pub const SGB_WIDTH: usize = 256;
+pub const SGB_HEIGHT: usize = 224;
+
+// where the game screen sits inside the border
+const SCREEN_X: usize = 48;
+const SCREEN_Y: usize = 40;
+
+// the attribute map gives a palette to every 8x8 cell of the game screen
+const CELLS_X: usize = 20;
+const CELLS_Y: usize = 18;
+
+const PACKET_SIZE: usize = 16;
+const TRANSFER_SIZE: usize = 4096;
+const SYSTEM_PALETTES: usize = 512;
+const ATTR_FILES: usize = 45;
+const ATTR_FILE_SIZE: usize = 90;
+const BORDER_TILES: usize = 256;
+const BORDER_TILE_SIZE: usize = 32;
+const BORDER_MAP_WIDTH: usize = 32;
+const BORDER_MAP_HEIGHT: usize = 28;
+
+// command ids
+const PAL01: u8 = 0x00;
+const PAL23: u8 = 0x01;
+const PAL03: u8 = 0x02;
+const PAL12: u8 = 0x03;
+const ATTR_BLK: u8 = 0x04;
+const ATTR_LIN: u8 = 0x05;
+const ATTR_DIV: u8 = 0x06;
+const ATTR_CHR: u8 = 0x07;
+const PAL_SET: u8 = 0x0A;
+const PAL_TRN: u8 = 0x0B;
+const MLT_REQ: u8 = 0x11;
+const CHR_TRN: u8 = 0x13;
+const PCT_TRN: u8 = 0x14;
+const ATTR_TRN: u8 = 0x15;
+const ATTR_SET: u8 = 0x16;
+const MASK_EN: u8 = 0x17;
+
+#[derive(Clone, Copy, PartialEq)]
+enum Transfer {
+    Palettes,
+    BorderTiles(usize),
+    BorderMap,
+    AttributeFiles,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Mask {
+    Off,
+    Freeze,
+    Black,
+    Backdrop,
+}
+
+#[derive(Clone)]
+pub struct SGB {
+    // packet reception
+    receiving: bool,
+    bit_count: usize,
+    packet: [u8; PACKET_SIZE],
+    command: Vec<u8>,
+    packets_left: u8,
+    last_p1: u8,
+
+    // multiplayer
+    players: u8,
+    current_player: u8,
+
+    // the 4 palettes used on screen, color 0 is shared by all of them
+    palettes: [[u16; 4]; 4],
+    system_palettes: Vec<u16>,
+    attributes: Vec<u8>,
+    attr_files: Vec<u8>,
+    mask: Mask,
+    pending_transfer: Option<Transfer>,
+
+    border_tiles: Vec<u8>,
+    // 32x28 entries: tile number, palette (4-7) in bits 10-12, x flip bit 14, y flip bit 15
+    border_map: Vec<u16>,
+    border_palettes: [[u16; 16]; 4],
+
+    screen: Vec<u16>,
+}
+
+impl Default for SGB {
+
+    fn default() -> SGB {
+        SGB {
+            receiving: false,
+            bit_count: 0,
+            packet: [0; PACKET_SIZE],
+            command: Vec::new(),
+            packets_left: 0,
+            last_p1: 0x30,
+            players: 1,
+            current_player: 0,
+            palettes: [[0x7FFF, 0x56B5, 0x294A, 0x0000]; 4],
+            system_palettes: vec![0; SYSTEM_PALETTES * 4],
+            attributes: vec![0; CELLS_X * CELLS_Y],
+            attr_files: vec![0; ATTR_FILES * ATTR_FILE_SIZE],
+            mask: Mask::Off,
+            pending_transfer: None,
+            border_tiles: vec![0; BORDER_TILES * BORDER_TILE_SIZE],
+            border_map: vec![0; BORDER_MAP_WIDTH * BORDER_MAP_HEIGHT],
+            border_palettes: [[0; 16]; 4],
+            screen: vec![0; SGB_WIDTH * SGB_HEIGHT],
+        }
+    }
+}
+
+impl SGB {
+
+    pub fn new() -> SGB {
+        Default::default()
+    }
+
+    pub fn screen(&self) -> &[u16] {
+        &self.screen
+    }
+
+    // with more than one player, reading P1 with both lines deselected returns the current
+    // player as 0xF, 0xE, 0xD or 0xC
+    pub fn joypad_id(&self) -> Option<u8> {
+        if self.players > 1 {
+            Some(0x0F - self.current_player)
+        } else {
+            None
+        }
+    }
+
+    pub fn current_player(&self) -> u8 {
+        self.current_player
+    }
+
+    // every write to P1 goes through here, only bits 4 and 5 matter
+    pub fn write_joypad(&mut self, data: u8) {
+        let p1 = data & 0x30;
+        let last = self.last_p1;
+        self.last_p1 = p1;
+
+        // the next player is selected when P15 goes back high
+        if self.players > 1 && last & 0x20 == 0 && p1 & 0x20 != 0 {
+            self.current_player = (self.current_player + 1) % self.players;
+        }
+
+        if p1 == 0x00 {
+            self.receiving = true;
+            self.bit_count = 0;
+            self.packet = [0; PACKET_SIZE];
+            return;
+        }
+
+        // bits are only taken when a line is pulled low after both were high
+        if !self.receiving || last != 0x30 || p1 == 0x30 {
+            return;
+        }
+
+        let bit = p1 == 0x10;
+
+        if self.bit_count == PACKET_SIZE * 8 {
+            // stop bit, has to be 0 or the packet is thrown away
+            self.receiving = false;
+            if !bit {
+                let packet = self.packet;
+                self.receive_packet(&packet);
+            }
+            return;
+        }
+
+        if bit {
+            self.packet[self.bit_count / 8] |= 1 << (self.bit_count % 8);
+        }
+        self.bit_count += 1;
+    }
+
+    fn receive_packet(&mut self, packet: &[u8; PACKET_SIZE]) {
+        if self.packets_left == 0 {
+            self.command.clear();
+            self.packets_left = (packet[0] & 0x07).max(1);
+        }
+
+        self.command.extend_from_slice(packet);
+        self.packets_left -= 1;
+
+        if self.packets_left == 0 {
+            let command = self.command.clone();
+            self.run_command(&command);
+        }
+    }
+
+    fn run_command(&mut self, data: &[u8]) {
+        match data[0] >> 3 {
+            PAL01 => self.set_palette_pair(data, 0, 1),
+            PAL23 => self.set_palette_pair(data, 2, 3),
+            PAL03 => self.set_palette_pair(data, 0, 3),
+            PAL12 => self.set_palette_pair(data, 1, 2),
+            ATTR_BLK => self.attr_blk(data),
+            ATTR_LIN => self.attr_lin(data),
+            ATTR_DIV => self.attr_div(data),
+            ATTR_CHR => self.attr_chr(data),
+            PAL_SET => self.pal_set(data),
+            PAL_TRN => self.pending_transfer = Some(Transfer::Palettes),
+            MLT_REQ => {
+                self.players = match data[1] & 0x03 {
+                    1 => 2,
+                    3 => 4,
+                    _ => 1,
+                };
+                self.current_player = 0;
+            },
+            CHR_TRN => self.pending_transfer = Some(Transfer::BorderTiles((data[1] & 0x01) as usize)),
+            PCT_TRN => self.pending_transfer = Some(Transfer::BorderMap),
+            ATTR_TRN => self.pending_transfer = Some(Transfer::AttributeFiles),
+            ATTR_SET => {
+                self.apply_attr_file((data[1] & 0x3F) as usize);
+                if data[1] & 0x40 != 0 {
+                    self.mask = Mask::Off;
+                }
+            },
+            MASK_EN => {
+                self.mask = match data[1] & 0x03 {
+                    1 => Mask::Freeze,
+                    2 => Mask::Black,
+                    3 => Mask::Backdrop,
+                    _ => Mask::Off,
+                };
+            },
+            // sound, SNES code upload and the rest don't change what's on screen
+            _ => {}
+        }
+    }
+
+    // color 0, colors 1-3 of the first palette, colors 1-3 of the second
+    fn set_palette_pair(&mut self, data: &[u8], first: usize, second: usize) {
+        let color = |idx: usize| (data[idx] as u16) | ((data[idx + 1] as u16) << 8);
+
+        let color_0 = color(1);
+        for palette in self.palettes.iter_mut() {
+            palette[0] = color_0;
+        }
+
+        for i in 0..3 {
+            self.palettes[first][i + 1] = color(3 + i * 2);
+            self.palettes[second][i + 1] = color(9 + i * 2);
+        }
+    }
+
+    // rectangles, each with a palette for inside, its border and outside
+    fn attr_blk(&mut self, data: &[u8]) {
+        let count = (data[1] & 0x1F) as usize;
+
+        for set in data[2..].chunks(6).take(count) {
+            if set.len() < 6 {
+                break;
+            }
+
+            let control = set[0] & 0x07;
+            let inside = set[1] & 0x03;
+            let border = (set[1] >> 2) & 0x03;
+            let outside = (set[1] >> 4) & 0x03;
+            let (x1, y1, x2, y2) = (set[2] as usize, set[3] as usize, set[4] as usize, set[5] as usize);
+
+            // with only inside or only outside set, the border takes the same palette
+            let border = match control {
+                0x01 => Some(inside),
+                0x04 => Some(outside),
+                _ if control & 0x02 != 0 => Some(border),
+                _ => None,
+            };
+
+            for y in 0..CELLS_Y {
+                for x in 0..CELLS_X {
+                    let on_edge = (x == x1 || x == x2) && y >= y1 && y <= y2
+                        || (y == y1 || y == y2) && x >= x1 && x <= x2;
+                    let within = x > x1 && x < x2 && y > y1 && y < y2;
+
+                    let palette = if on_edge {
+                        border
+                    } else if within && control & 0x01 != 0 {
+                        Some(inside)
+                    } else if !within && control & 0x04 != 0 {
+                        Some(outside)
+                    } else {
+                        None
+                    };
+
+                    if let Some(palette) = palette {
+                        self.attributes[y * CELLS_X + x] = palette;
+                    }
+                }
+            }
+        }
+    }
+
+    // whole rows or columns, one byte each: line in bits 0-4, palette in 5-6, bit 7 set for a row
+    fn attr_lin(&mut self, data: &[u8]) {
+        let count = data[1] as usize;
+
+        for &line in data[2..].iter().take(count) {
+            let number = (line & 0x1F) as usize;
+            let palette = (line >> 5) & 0x03;
+
+            if line & 0x80 != 0 {
+                if number < CELLS_Y {
+                    for x in 0..CELLS_X {
+                        self.attributes[number * CELLS_X + x] = palette;
+                    }
+                }
+            } else if number < CELLS_X {
+                for y in 0..CELLS_Y {
+                    self.attributes[y * CELLS_X + number] = palette;
+                }
+            }
+        }
+    }
+
+    // splits the screen in two at a row or column, the line itself gets a third palette
+    fn attr_div(&mut self, data: &[u8]) {
+        let after = data[1] & 0x03;
+        let before = (data[1] >> 2) & 0x03;
+        let on_line = (data[1] >> 4) & 0x03;
+        let horizontal = data[1] & 0x40 != 0;
+        let coordinate = data[2] as usize;
+
+        for y in 0..CELLS_Y {
+            for x in 0..CELLS_X {
+                let position = if horizontal { y } else { x };
+                let palette = if position < coordinate {
+                    before
+                } else if position == coordinate {
+                    on_line
+                } else {
+                    after
+                };
+
+                self.attributes[y * CELLS_X + x] = palette;
+            }
+        }
+    }
+
+    // a palette per cell, 4 cells per byte starting with the top bits
+    fn attr_chr(&mut self, data: &[u8]) {
+        let mut x = (data[1] as usize).min(CELLS_X - 1);
+        let mut y = (data[2] as usize).min(CELLS_Y - 1);
+        let count = ((data[3] as usize) | ((data[4] as usize) << 8)).min(CELLS_X * CELLS_Y);
+        let vertical = data[5] & 0x01 != 0;
+
+        for i in 0..count {
+            let byte = match data.get(6 + i / 4) {
+                Some(byte) => *byte,
+                None => break,
+            };
+            let palette = (byte >> (6 - (i % 4) * 2)) & 0x03;
+            self.attributes[y * CELLS_X + x] = palette;
+
+            if vertical {
+                y += 1;
+                if y == CELLS_Y {
+                    y = 0;
+                    x = (x + 1) % CELLS_X;
+                }
+            } else {
+                x += 1;
+                if x == CELLS_X {
+                    x = 0;
+                    y = (y + 1) % CELLS_Y;
+                }
+            }
+        }
+    }
+
+    // copies 4 of the 512 system palettes into the screen palettes
+    fn pal_set(&mut self, data: &[u8]) {
+        for palette in 0..4 {
+            let number = ((data[1 + palette * 2] as usize) | ((data[2 + palette * 2] as usize) << 8)) % SYSTEM_PALETTES;
+            for color in 0..4 {
+                self.palettes[palette][color] = self.system_palettes[number * 4 + color];
+            }
+        }
+
+        // color 0 always comes from the first palette
+        let color_0 = self.palettes[0][0];
+        for palette in self.palettes.iter_mut() {
+            palette[0] = color_0;
+        }
+
+        if data[9] & 0x80 != 0 {
+            self.apply_attr_file((data[9] & 0x3F) as usize);
+        }
+
+        if data[9] & 0x40 != 0 {
+            self.mask = Mask::Off;
+        }
+    }
+
+    fn apply_attr_file(&mut self, file: usize) {
+        if file >= ATTR_FILES {
+            return;
+        }
+
+        let data = &self.attr_files[file * ATTR_FILE_SIZE..(file + 1) * ATTR_FILE_SIZE];
+        for (i, attribute) in self.attributes.iter_mut().enumerate() {
+            *attribute = (data[i / 4] >> (6 - (i % 4) * 2)) & 0x03;
+        }
+    }
+
+    // called at the start of every VBlank with the frame the PPU just finished
+    pub fn vblank(&mut self, vram: &[u8], lcdc: u8, framebuffer: &[u16]) {
+        if let Some(transfer) = self.pending_transfer.take() {
+            let data = transfer_data(vram, lcdc);
+            self.finish_transfer(transfer, &data);
+        }
+
+        self.render(framebuffer);
+    }
+
+    fn finish_transfer(&mut self, transfer: Transfer, data: &[u8]) {
+        match transfer {
+            Transfer::Palettes => {
+                for (i, color) in self.system_palettes.iter_mut().enumerate() {
+                    *color = (data[i * 2] as u16) | ((data[i * 2 + 1] as u16) << 8);
+                }
+            },
+            Transfer::BorderTiles(half) => {
+                let start = half * TRANSFER_SIZE;
+                self.border_tiles[start..start + TRANSFER_SIZE].copy_from_slice(data);
+            },
+            Transfer::BorderMap => {
+                for (i, entry) in self.border_map.iter_mut().enumerate() {
+                    *entry = (data[i * 2] as u16) | ((data[i * 2 + 1] as u16) << 8);
+                }
+
+                // palettes 4-7 follow the map at 0x800
+                for (palette, colors) in self.border_palettes.iter_mut().enumerate() {
+                    for (i, color) in colors.iter_mut().enumerate() {
+                        let idx = 0x800 + palette * 32 + i * 2;
+                        *color = (data[idx] as u16) | ((data[idx + 1] as u16) << 8);
+                    }
+                }
+            },
+            Transfer::AttributeFiles => {
+                let size = ATTR_FILES * ATTR_FILE_SIZE;
+                self.attr_files.copy_from_slice(&data[..size]);
+            },
+        }
+    }
+
+    // a frozen screen keeps the last frame until the mask is lifted, only the border is redrawn
+    fn render(&mut self, framebuffer: &[u16]) {
+        if self.mask != Mask::Freeze {
+            let backdrop = self.palettes[0][0];
+
+            for pixel in self.screen.iter_mut() {
+                *pixel = backdrop;
+            }
+
+            self.render_game(framebuffer);
+        }
+
+        self.render_border();
+    }
+
+    fn render_game(&mut self, framebuffer: &[u16]) {
+        for y in 0..SCREEN_HEIGHT {
+            for x in 0..SCREEN_WIDTH {
+                let color = match self.mask {
+                    Mask::Black => 0x0000,
+                    Mask::Backdrop => self.palettes[0][0],
+                    Mask::Off | Mask::Freeze => {
+                        let palette = self.attributes[(y / 8) * CELLS_X + x / 8] as usize;
+                        let shade = (framebuffer[y * SCREEN_WIDTH + x] & 0b11) as usize;
+                        self.palettes[palette][shade]
+                    },
+                };
+
+                self.screen[(SCREEN_Y + y) * SGB_WIDTH + SCREEN_X + x] = color;
+            }
+        }
+    }
+
+    // SNES 4bpp tiles drawn over everything, color 0 is see-through
+    fn render_border(&mut self) {
+        for map_y in 0..BORDER_MAP_HEIGHT {
+            for map_x in 0..BORDER_MAP_WIDTH {
+                let entry = self.border_map[map_y * BORDER_MAP_WIDTH + map_x];
+                let tile = (entry & 0xFF) as usize;
+                let palette = (((entry >> 10) & 0x07) as usize).saturating_sub(4) & 0x03;
+                let x_flip = entry & 0x4000 != 0;
+                let y_flip = entry & 0x8000 != 0;
+
+                for row in 0..8 {
+                    let tile_row = if y_flip { 7 - row } else { row };
+                    let tile_data = &self.border_tiles[tile * BORDER_TILE_SIZE..(tile + 1) * BORDER_TILE_SIZE];
+
+                    for column in 0..8 {
+                        let bit = if x_flip { column } else { 7 - column };
+                        let color = snes_tile_pixel(tile_data, tile_row, bit);
+                        if color == 0 {
+                            continue;
+                        }
+
+                        let idx = (map_y * 8 + row) * SGB_WIDTH + map_x * 8 + column;
+                        self.screen[idx] = self.border_palettes[palette][color];
+                    }
+                }
+            }
+        }
+    }
+
+    pub fn save_state(&self, state: &mut StateWriter) {
+        state.write_u8(self.players);
+        state.write_u8(self.current_player);
+        state.write_u8(self.mask as u8);
+        state.write_u8(self.last_p1);
+        for palette in self.palettes.iter() {
+            for color in palette.iter() {
+                state.write_u16(*color);
+            }
+        }
+        for palette in self.border_palettes.iter() {
+            for color in palette.iter() {
+                state.write_u16(*color);
+            }
+        }
+        for color in self.system_palettes.iter().chain(self.border_map.iter()) {
+            state.write_u16(*color);
+        }
+        state.write_bytes(&self.attributes);
+        state.write_bytes(&self.attr_files);
+        state.write_bytes(&self.border_tiles);
+    }
+
+    pub fn load_state(&mut self, state: &mut StateReader) -> StateResult<()> {
+        self.players = state.read_u8()?;
+        self.current_player = state.read_u8()?;
+        self.mask = match state.read_u8()? {
+            1 => Mask::Freeze,
+            2 => Mask::Black,
+            3 => Mask::Backdrop,
+            _ => Mask::Off,
+        };
+        self.last_p1 = state.read_u8()?;
+        for palette in self.palettes.iter_mut() {
+            for color in palette.iter_mut() {
+                *color = state.read_u16()?;
+            }
+        }
+        for palette in self.border_palettes.iter_mut() {
+            for color in palette.iter_mut() {
+                *color = state.read_u16()?;
+            }
+        }
+        for color in self.system_palettes.iter_mut().chain(self.border_map.iter_mut()) {
+            *color = state.read_u16()?;
+        }
+        state.read_bytes(&mut self.attributes)?;
+        state.read_bytes(&mut self.attr_files)?;
+        state.read_bytes(&mut self.border_tiles)?;
+
+        // a packet or transfer in flight is dropped
+        self.receiving = false;
+        self.packets_left = 0;
+        self.pending_transfer = None;
+
+        Ok(())
+    }
+}
+
+// the first 256 tiles on screen, read through the BG map the way the PPU would show them
+fn transfer_data(vram: &[u8], lcdc: u8) -> Vec<u8> {
+    let map_base = if lcdc & 0b00001000 != 0 { 0x1C00 } else { 0x1800 };
+    let mut data = Vec::with_capacity(TRANSFER_SIZE);
+
+    for i in 0..TRANSFER_SIZE / 16 {
+        let tile_number = vram[map_base + (i / CELLS_X) * 32 + i % CELLS_X];
+        let address = if lcdc & 0b00010000 != 0 {
+            tile_number as usize * 16
+        } else {
+            (0x1000 + (tile_number as i8 as i32) * 16) as usize
+        };
+
+        data.extend_from_slice(&vram[address..address + 16]);
+    }
+
+    data
+}
+
+// bitplanes 0 and 1 are interleaved in the first 16 bytes, 2 and 3 in the second
+fn snes_tile_pixel(tile: &[u8], row: usize, bit: usize) -> usize {
+    let plane = |offset: usize| ((tile[offset] >> bit) & 1) as usize;
+
+    plane(row * 2) | plane(row * 2 + 1) << 1 | plane(16 + row * 2) << 2 | plane(16 + row * 2 + 1) << 3
+}
@@ -0,0 +1,117 @@
+// Save states are a flat stream of bytes. Every component writes its fields in a
+// fixed order and reads them back in that same order, so adding a field means
+// touching both save_state and load_state of that component.
+
+const MAGIC: &[u8; 4] = b"RBSS";
+const VERSION: u8 = 1;
+
+pub type StateResult<T> = Result<T, &'static str>;
+
+pub struct StateWriter {
+    data: Vec<u8>,
+}
+
+impl StateWriter {
+
+    pub fn new() -> StateWriter {
+        let mut data = Vec::new();
+        data.extend_from_slice(MAGIC);
+        data.push(VERSION);
+
+        StateWriter { data: data }
+    }
+
+    pub fn write_u8(&mut self, value: u8) {
+        self.data.push(value);
+    }
+
+    pub fn write_bool(&mut self, value: bool) {
+        self.data.push(value as u8);
+    }
+
+    pub fn write_u16(&mut self, value: u16) {
+        self.data.push(value as u8);
+        self.data.push((value >> 8) as u8);
+    }
+
+    pub fn write_u32(&mut self, value: u32) {
+        self.write_u16(value as u16);
+        self.write_u16((value >> 16) as u16);
+    }
+
+    // length prefixed so a load can tell if the state came from a different layout
+    pub fn write_bytes(&mut self, bytes: &[u8]) {
+        self.write_u32(bytes.len() as u32);
+        self.data.extend_from_slice(bytes);
+    }
+
+    pub fn into_vec(self) -> Vec<u8> {
+        self.data
+    }
+}
+
+pub struct StateReader<'a> {
+    data: &'a [u8],
+    position: usize,
+}
+
+impl<'a> StateReader<'a> {
+
+    pub fn new(data: &'a [u8]) -> StateResult<StateReader<'a>> {
+        if data.len() < MAGIC.len() + 1 || &data[0..MAGIC.len()] != MAGIC {
+            return Err("not a rustyboi save state");
+        }
+
+        if data[MAGIC.len()] != VERSION {
+            return Err("save state version mismatch");
+        }
+
+        Ok(StateReader { data: data, position: MAGIC.len() + 1 })
+    }
+
+    pub fn read_u8(&mut self) -> StateResult<u8> {
+        if self.position >= self.data.len() {
+            return Err("save state is truncated");
+        }
+
+        let value = self.data[self.position];
+        self.position += 1;
+
+        Ok(value)
+    }
+
+    pub fn read_bool(&mut self) -> StateResult<bool> {
+        Ok(self.read_u8()? != 0)
+    }
+
+    pub fn read_u16(&mut self) -> StateResult<u16> {
+        let low = self.read_u8()? as u16;
+        let high = self.read_u8()? as u16;
+
+        Ok((high << 8) | low)
+    }
+
+    pub fn read_u32(&mut self) -> StateResult<u32> {
+        let low = self.read_u16()? as u32;
+        let high = self.read_u16()? as u32;
+
+        Ok((high << 16) | low)
+    }
+
+    // reads into an existing buffer, which must be the same size as when it was saved
+    pub fn read_bytes(&mut self, buffer: &mut [u8]) -> StateResult<()> {
+        let len = self.read_u32()? as usize;
+        if len != buffer.len() {
+            return Err("save state memory size mismatch");
+        }
+
+        if self.position + len > self.data.len() {
+            return Err("save state is truncated");
+        }
+
+        buffer.copy_from_slice(&self.data[self.position..self.position + len]);
+        self.position += len;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,246 @@
+// Timer registers:
+// FF04   DIV    upper 8 bits of the internal counter, writing anything clears the whole counter
+// FF05   TIMA   counts up, reloaded from TMA when it overflows
+// FF06   TMA    reload value
+// FF07   TAC    bit 2 enables TIMA, bits 0-1 pick the counter bit it follows
+//
+// TIMA goes up on the falling edge of (selected counter bit AND enable). Anything that drops
+// that signal counts, so clearing DIV or changing TAC can tick TIMA too. When TIMA overflows
+// it reads 0 for one m cycle, then it's loaded from TMA and the interrupt is requested.
+
+use gameboy::state::{ StateReader, StateResult, StateWriter };
+
+pub const INT_TIMER: u8 = 0b00000100;
+
+const DIV: u16 = 0xFF04;
+const TIMA: u16 = 0xFF05;
+const TMA: u16 = 0xFF06;
+const TAC: u16 = 0xFF07;
+
+// counter bits for TAC 0-3: 4096, 262144, 65536 and 16384 Hz at normal speed
+const TAC_BITS: [u16; 4] = [9, 3, 5, 7];
+
+// where the counter is when the boot ROM hands over at 0x100. the CGB boot ROM takes longer
+const DMG_BOOT_COUNTER: u16 = 0xABCC;
+const CGB_BOOT_COUNTER: u16 = 0x1EA0;
+
+#[derive(Clone)]
+pub struct Timer {
+    counter: u16,
+    tima: u8,
+    tma: u8,
+    tac: u8,
+    // TIMA overflowed last m cycle and gets reloaded on this one
+    reload_pending: bool,
+    // the m cycle the reload happened on, TIMA writes lose to the reload and TMA writes go
+    // straight through to TIMA
+    reloading: bool,
+    // t cycles left over from a tick that wasn't a whole m cycle
+    leftover: u32,
+}
+
+impl Default for Timer {
+
+    fn default() -> Timer {
+        Timer {
+            counter: DMG_BOOT_COUNTER,
+            tima: 0x00,
+            tma: 0x00,
+            tac: 0x00,
+            reload_pending: false,
+            reloading: false,
+            leftover: 0,
+        }
+    }
+}
+
+impl Timer {
+
+    pub fn new() -> Timer {
+        Default::default()
+    }
+
+    // DMG games on a CGB keep the DMG value, how long the boot ROM spends colorizing them
+    // depends on the game
+    pub fn set_cgb_mode(&mut self, cgb_mode: bool) {
+        self.counter = if cgb_mode { CGB_BOOT_COUNTER } else { DMG_BOOT_COUNTER };
+    }
+
+    pub fn read_register(&self, address: u16) -> u8 {
+        match address {
+            DIV => (self.counter >> 8) as u8,
+            TIMA => self.tima,
+            TMA => self.tma,
+            TAC => 0xF8 | self.tac,
+            _ => 0xFF,
+        }
+    }
+
+    pub fn write_register(&mut self, address: u16, data: u8) {
+        match address {
+            DIV => {
+                let before = self.signal();
+                self.counter = 0;
+                self.check_falling_edge(before);
+            },
+            TIMA => {
+                if !self.reloading {
+                    // cancels a reload that hasn't happened yet
+                    self.tima = data;
+                    self.reload_pending = false;
+                }
+            },
+            TMA => {
+                self.tma = data;
+                if self.reloading {
+                    self.tima = data;
+                }
+            },
+            TAC => {
+                let before = self.signal();
+                self.tac = data & 0x07;
+                self.check_falling_edge(before);
+            },
+            _ => {}
+        }
+    }
+
+    // t cycles at CPU speed, the timer runs twice as fast in double speed mode. returns the
+    // interrupts to request
+    pub fn tick(&mut self, cycles: u32) -> u8 {
+        let mut interrupts = 0;
+        self.leftover += cycles;
+
+        while self.leftover >= 4 {
+            self.leftover -= 4;
+            self.reloading = false;
+
+            if self.reload_pending {
+                self.reload_pending = false;
+                self.reloading = true;
+                self.tima = self.tma;
+                interrupts |= INT_TIMER;
+            }
+
+            let before = self.signal();
+            self.counter = self.counter.wrapping_add(4);
+            self.check_falling_edge(before);
+        }
+
+        interrupts
+    }
+
+    fn signal(&self) -> bool {
+        let bit = TAC_BITS[(self.tac & 0x03) as usize];
+        self.tac & 0x04 != 0 && self.counter & (1 << bit) != 0
+    }
+
+    fn check_falling_edge(&mut self, before: bool) {
+        if !before || self.signal() {
+            return;
+        }
+
+        let (tima, overflow) = self.tima.overflowing_add(1);
+        self.tima = tima;
+        if overflow {
+            self.reload_pending = true;
+        }
+    }
+
+    pub fn save_state(&self, state: &mut StateWriter) {
+        state.write_u16(self.counter);
+        state.write_u8(self.tima);
+        state.write_u8(self.tma);
+        state.write_u8(self.tac);
+        state.write_bool(self.reload_pending);
+        state.write_bool(self.reloading);
+        state.write_u8(self.leftover as u8);
+    }
+
+    pub fn load_state(&mut self, state: &mut StateReader) -> StateResult<()> {
+        self.counter = state.read_u16()?;
+        self.tima = state.read_u8()?;
+        self.tma = state.read_u8()?;
+        self.tac = state.read_u8()? & 0x07;
+        self.reload_pending = state.read_bool()?;
+        self.reloading = state.read_bool()?;
+        self.leftover = (state.read_u8()? & 0x03) as u32;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // TIMA following counter bit 3, with that bit set
+    fn timer_at_edge() -> Timer {
+        let mut timer = Timer::new();
+        timer.write_register(TAC, 0x05);
+        timer.counter = 0x000C;
+        timer
+    }
+
+    #[test]
+    fn div_write_on_the_falling_edge_ticks_tima() {
+        let mut timer = timer_at_edge();
+        timer.write_register(DIV, 0x12);
+
+        assert_eq!(timer.read_register(DIV), 0);
+        assert_eq!(timer.read_register(TIMA), 1);
+    }
+
+    #[test]
+    fn tac_change_that_drops_the_signal_ticks_tima() {
+        let mut timer = timer_at_edge();
+        // bit 9 is clear
+        timer.write_register(TAC, 0x04);
+        assert_eq!(timer.read_register(TIMA), 1);
+
+        // turning the timer off drops it too
+        timer.write_register(TAC, 0x05);
+        timer.write_register(TAC, 0x01);
+        assert_eq!(timer.read_register(TIMA), 2);
+    }
+
+    #[test]
+    fn overflow_reads_0_for_one_cycle_then_reloads_and_interrupts() {
+        let mut timer = timer_at_edge();
+        timer.write_register(TIMA, 0xFF);
+        timer.write_register(TMA, 0x80);
+
+        assert_eq!(timer.tick(4), 0);
+        assert_eq!(timer.read_register(TIMA), 0x00);
+
+        assert_eq!(timer.tick(4), INT_TIMER);
+        assert_eq!(timer.read_register(TIMA), 0x80);
+    }
+
+    #[test]
+    fn tima_write_before_the_reload_cancels_it() {
+        let mut timer = timer_at_edge();
+        timer.write_register(TIMA, 0xFF);
+        timer.write_register(TMA, 0x80);
+
+        timer.tick(4);
+        timer.write_register(TIMA, 0x42);
+
+        assert_eq!(timer.tick(4), 0);
+        assert_eq!(timer.read_register(TIMA), 0x42);
+    }
+
+    #[test]
+    fn tma_write_on_the_reload_cycle_goes_through_to_tima() {
+        let mut timer = timer_at_edge();
+        timer.write_register(TIMA, 0xFF);
+        timer.write_register(TMA, 0x80);
+
+        timer.tick(8);
+        // TIMA writes lose to the reload
+        timer.write_register(TIMA, 0x42);
+        timer.write_register(TMA, 0x77);
+
+        assert_eq!(timer.read_register(TIMA), 0x77);
+    }
+}
@@ -0,0 +1,165 @@
+// Keyboard and game controller bindings, read from the config file. Every action has a key_
+// and a pad_ setting that takes a comma separated list:
+//
+//     key_a = X, Space          SDL key names
+//     pad_a = a                 SDL controller button names
+//     pad_up = dpup, lefty-     or an axis and the direction that presses it
+//
+// The actions are the eight buttons (up down left right a b select start) and the hotkeys
+// (pause reset fast_forward save_state load_state screenshot). Fast forward is held, the other
+// hotkeys go off when they're pressed. Save and load state have no default keys, so a slip of
+// the fingers off the game's buttons can't overwrite or throw away progress.
+
+use std::collections::HashMap;
+
+use sdl2::controller::{ Axis, Button as PadButton };
+use sdl2::keyboard::Keycode;
+
+use config;
+use gameboy::Button;
+
+// how far out of 32767 a stick or trigger has to go to count as pressed
+const DEFAULT_DEAD_ZONE: i16 = 12000;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Hotkey {
+    Pause,
+    Reset,
+    FastForward,
+    SaveState,
+    LoadState,
+    Screenshot,
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Action {
+    Button(Button),
+    Hotkey(Hotkey),
+}
+
+// config name, then the default keys and pad bindings
+const ACTIONS: [(&str, Action, &str, &str); 14] = [
+    ("up", Action::Button(Button::Up), "Up", "dpup, lefty-"),
+    ("down", Action::Button(Button::Down), "Down", "dpdown, lefty+"),
+    ("left", Action::Button(Button::Left), "Left", "dpleft, leftx-"),
+    ("right", Action::Button(Button::Right), "Right", "dpright, leftx+"),
+    ("a", Action::Button(Button::A), "X", "a"),
+    ("b", Action::Button(Button::B), "Z", "b"),
+    ("select", Action::Button(Button::Select), "Backspace", "back"),
+    ("start", Action::Button(Button::Start), "Return", "start"),
+    ("pause", Action::Hotkey(Hotkey::Pause), "P", ""),
+    ("reset", Action::Hotkey(Hotkey::Reset), "R", ""),
+    ("fast_forward", Action::Hotkey(Hotkey::FastForward), "Tab", "righttrigger+"),
+    ("save_state", Action::Hotkey(Hotkey::SaveState), "", ""),
+    ("load_state", Action::Hotkey(Hotkey::LoadState), "", ""),
+    ("screenshot", Action::Hotkey(Hotkey::Screenshot), "F12", ""),
+];
+
+struct AxisBinding {
+    axis: Axis,
+    // pressed by pushing past the dead zone in the positive direction, or the negative one
+    positive: bool,
+    action: Action,
+    held: bool,
+}
+
+pub struct InputMap {
+    keys: HashMap<Keycode, Action>,
+    pad_buttons: HashMap<PadButton, Action>,
+    pad_axes: Vec<AxisBinding>,
+    dead_zone: i16,
+}
+
+impl InputMap {
+
+    // pad_dead_zone = 12000 sets how far sticks and triggers go before they count
+    pub fn from_config(config: &config::Config) -> InputMap {
+        let mut input = InputMap {
+            keys: HashMap::new(),
+            pad_buttons: HashMap::new(),
+            pad_axes: Vec::new(),
+            dead_zone: DEFAULT_DEAD_ZONE,
+        };
+
+        for &(name, action, keys, pad) in ACTIONS.iter() {
+            let key_setting = format!("key_{}", name);
+            for key in bindings(config.get(&key_setting).unwrap_or(keys)) {
+                match Keycode::from_name(key) {
+                    Some(keycode) => { input.keys.insert(keycode, action); },
+                    None => println!("{} has an unknown key {}", key_setting, key),
+                }
+            }
+
+            let pad_setting = format!("pad_{}", name);
+            for binding in bindings(config.get(&pad_setting).unwrap_or(pad)) {
+                if !input.bind_pad(binding, action) {
+                    println!("{} has an unknown controller button or axis {}", pad_setting, binding);
+                }
+            }
+        }
+
+        if let Some(value) = config.get("pad_dead_zone") {
+            match value.parse::<i16>() {
+                Ok(dead_zone) if dead_zone >= 0 => input.dead_zone = dead_zone,
+                _ => println!("pad_dead_zone should be a number from 0 to 32767, got {}", value),
+            }
+        }
+
+        input
+    }
+
+    fn bind_pad(&mut self, binding: &str, action: Action) -> bool {
+        let direction = if binding.ends_with('+') || binding.ends_with('-') {
+            binding.chars().last()
+        } else {
+            None
+        };
+
+        match direction {
+            Some(direction) => match Axis::from_string(&binding[..binding.len() - 1]) {
+                Some(axis) => {
+                    self.pad_axes.push(AxisBinding {
+                        axis: axis,
+                        positive: direction == '+',
+                        action: action,
+                        held: false,
+                    });
+                    true
+                },
+                None => false,
+            },
+            None => match PadButton::from_string(binding) {
+                Some(button) => {
+                    self.pad_buttons.insert(button, action);
+                    true
+                },
+                None => false,
+            },
+        }
+    }
+
+    pub fn key(&self, keycode: Keycode) -> Option<Action> {
+        self.keys.get(&keycode).cloned()
+    }
+
+    pub fn pad_button(&self, button: PadButton) -> Option<Action> {
+        self.pad_buttons.get(&button).cloned()
+    }
+
+    // axes send every little move, only crossing the dead zone presses or lets go of anything
+    pub fn pad_axis(&mut self, axis: Axis, value: i16, changes: &mut Vec<(Action, bool)>) {
+        let dead_zone = self.dead_zone;
+
+        for binding in self.pad_axes.iter_mut().filter(|binding| binding.axis == axis) {
+            let held = if binding.positive { value > dead_zone } else { value < -dead_zone };
+            if held != binding.held {
+                binding.held = held;
+                changes.push((binding.action, held));
+            }
+        }
+    }
+}
+
+fn bindings(value: &str) -> impl Iterator<Item = &str> {
+    value.split(',').map(|binding| binding.trim()).filter(|binding| !binding.is_empty())
+}
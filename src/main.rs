@@ -1,83 +1,627 @@
 extern crate sdl2;
 
-use sdl2::event::Event;
-use sdl2::pixels;
-use sdl2::keyboard::Keycode;
-
-use sdl2::gfx::primitives::DrawRenderer;
+use sdl2::controller::GameController;
+use sdl2::event::{ Event, WindowEvent };
+use sdl2::keyboard::{ Keycode, Mod, LSHIFTMOD, RSHIFTMOD };
+use sdl2::pixels::PixelFormatEnum;
 
+mod audio;
+mod config;
 mod gameboy;
+mod input;
+mod video;
+mod viewers;
 
+use std::env;
+use std::fs::File;
+use std::io::prelude::*;
 use std::thread::sleep;
-use std::time::Duration;
-use std::vec::Vec;
+use std::time::{ Duration, Instant, SystemTime, UNIX_EPOCH };
 
-const SCREEN_WIDTH: u32 = 160;
-const SCREEN_HEIGHT: u32 = 144;
+// 70224 dots at 4194304 Hz
+const FRAME_NANOS: u64 = 16_742_706;
 
 fn main() {
+    let config = config::Config::load(config::DEFAULT_PATH);
+    let args: Vec<String> = env::args().collect();
+
     let mut gb = gameboy::Gameboy::new();
+    gb.set_model(model_from_config(&config));
+    gb.set_renderer(renderer_from_config(&config));
+    gb.set_compat_combo(compat_combo_from_config(&config));
     gb.power_on();
-    gb.load_game();
-
-//    let title = gb.get_game_title();
-//    let sdl_context = sdl2::init().unwrap();
-//
-//    let mut canvas = get_canvas(&sdl_context, title).unwrap();
-//
-//    canvas.clear();
-//    canvas.present();
-//
-//    let mut events = sdl_context.event_pump().unwrap();
-//
-//    let mut screen_data = Vec::new();
-//    for i in 0..23041 {
-//        screen_data.push(0xFFFFFFFF);
-//    }
-//
-//    let mut x = 0;
-//    let mut y = 0;
-//    for pixel in screen_data {
-//        canvas.pixel(x as i16, y as i16, pixel as u32).unwrap();
-//        if x > 159 {
-//            x = 0;
-//            y += 1;
-//            continue
-//        }
-//
-//        x += 1;
-//    }
-//
-//    canvas.present();
-//
-//    'main: loop {
-//        for event in events.poll_iter() {
-//
-//            match event {
-//                Event::Quit {..} => {
-//                    break 'main
-//                },
-//                Event::KeyDown {keycode: Some(keycode), ..} => {
-//                    if keycode == Keycode::Escape {
-//                        break 'main
-//                    }
-//                },
-//                _ => {}
-//            }
-//        }
-//    }
-
-    for _x in 0..10 {
-        gb.step();
-        sleep(Duration::new(1, 0));
+
+    // --gbs PATH plays a GBS file instead of the game, --track N starts on that track
+    match args.iter().position(|arg| arg == "--gbs").and_then(|idx| args.get(idx + 1)) {
+        Some(path) => {
+            let track = args.iter().position(|arg| arg == "--track").and_then(|idx| args.get(idx + 1));
+            if !load_gbs(&mut gb, path, track) {
+                return;
+            }
+        },
+        None => gb.load_game(),
+    }
+    hide_sprites_from_config(&mut gb, &config);
+    mute_channels_from_config(&mut gb, &config);
+
+    let mut video = video::Video::from_config(&config);
+    let screenshot_mode = screenshot_mode_from_config(&config);
+
+    let (record_mode, record_rate) = audio::recording_from_config(&config);
+
+    // --frames N runs that many frames without opening a window, --screenshot PATH saves the
+    // last one, --record-wav PATH records the audio, --record-vgm PATH logs it as VGM and
+    // --dump-viewers DIR writes the debug viewers out
+    if let Some(idx) = args.iter().position(|arg| arg == "--frames") {
+        let frames = args.get(idx + 1).and_then(|value| value.parse::<u32>().ok()).unwrap_or(60);
+        let screenshot = args.iter().position(|arg| arg == "--screenshot").and_then(|idx| args.get(idx + 1));
+        let recorder = match args.iter().position(|arg| arg == "--record-wav").and_then(|idx| args.get(idx + 1)) {
+            Some(path) => start_recording(path, record_mode, record_rate),
+            None => None,
+        };
+        let vgm = match args.iter().position(|arg| arg == "--record-vgm").and_then(|idx| args.get(idx + 1)) {
+            Some(path) => start_vgm(&mut gb, path),
+            None => None,
+        };
+        run_headless(&mut gb, &mut video, frames, recorder, vgm);
+
+        if let Some(path) = screenshot {
+            save_screenshot(&gb, &video, screenshot_mode, path);
+        }
+        if let Some(directory) = args.iter().position(|arg| arg == "--dump-viewers").and_then(|idx| args.get(idx + 1)) {
+            viewers::dump(&gb, video.palette(), directory);
+        }
+        return;
+    }
+
+    let title = gb.get_game_title().to_string();
+    let (width, height) = gb.screen_size();
+    let scale = video.output_scale();
+
+    let sdl_context = sdl2::init().unwrap();
+    let mut canvas = get_canvas(&sdl_context, &title, (width * scale) as u32, (height * scale) as u32).unwrap();
+    let texture_creator = canvas.texture_creator();
+    let mut texture = texture_creator
+        .create_texture_streaming(PixelFormatEnum::RGB888, (width * scale) as u32, (height * scale) as u32)
+        .unwrap();
+
+    let mut audio_output = audio::AudioOutput::from_config(&sdl_context, &config);
+    let mut samples = Vec::new();
+    let mut channel_samples = Vec::new();
+    let mut recorder: Option<audio::Recorder> = None;
+    let mut vgm: Option<audio::VgmWriter> = None;
+    let mut apu_writes = Vec::new();
+
+    let mut input = input::InputMap::from_config(&config);
+    let mut actions = Vec::new();
+    let controller_subsystem = match sdl_context.game_controller() {
+        Ok(subsystem) => Some(subsystem),
+        Err(e) => {
+            println!("couldn't start game controller support: {}", e);
+            None
+        },
+    };
+    // SDL sends an added event for each controller that's already plugged in too
+    let mut controllers: Vec<GameController> = Vec::new();
+
+    let fast_forward_speed = fast_forward_speed_from_config(&config);
+    let mut fast_forward = false;
+    let mut paused = false;
+    let state_path = state_path(&title);
+
+    let mut viewer_windows = viewers::ViewerWindows::new();
+    let main_window = canvas.window().id();
+
+    let mut events = sdl_context.event_pump().unwrap();
+    let frame_time = Duration::new(0, FRAME_NANOS as u32);
+    let mut next_frame = Instant::now() + frame_time;
+
+    'main: loop {
+        for event in events.poll_iter() {
+            match event {
+                Event::Quit {..} => break 'main,
+                Event::Window { window_id, win_event: WindowEvent::Close, .. } => {
+                    if window_id == main_window {
+                        break 'main;
+                    }
+                    viewer_windows.close(window_id);
+                },
+                // left and right go through the tracks of a GBS file
+                Event::KeyDown { keycode: Some(Keycode::Left), .. } if gb.gbs_header().is_some() => change_track(&mut gb, -1),
+                Event::KeyDown { keycode: Some(Keycode::Right), .. } if gb.gbs_header().is_some() => change_track(&mut gb, 1),
+                // bound keys win over the fixed ones below, and hotkeys shouldn't go off again on repeats
+                Event::KeyDown { keycode: Some(keycode), repeat, .. } if input.key(keycode).is_some() => {
+                    if !repeat {
+                        actions.extend(input.key(keycode).map(|action| (action, true)));
+                    }
+                },
+                Event::KeyUp { keycode: Some(keycode), .. } => actions.extend(input.key(keycode).map(|action| (action, false))),
+                Event::ControllerButtonDown { button, .. } => actions.extend(input.pad_button(button).map(|action| (action, true))),
+                Event::ControllerButtonUp { button, .. } => actions.extend(input.pad_button(button).map(|action| (action, false))),
+                Event::ControllerAxisMotion { axis, value, .. } => input.pad_axis(axis, value, &mut actions),
+                Event::ControllerDeviceAdded { which, .. } => {
+                    if let Some(ref subsystem) = controller_subsystem {
+                        match subsystem.open(which) {
+                            Ok(controller) => {
+                                println!("controller connected: {}", controller.name());
+                                controllers.push(controller);
+                            },
+                            Err(e) => println!("couldn't open controller {}: {}", which, e),
+                        }
+                    }
+                },
+                Event::ControllerDeviceRemoved { which, .. } => controllers.retain(|controller| controller.instance_id() != which),
+                Event::KeyDown { keycode: Some(Keycode::F1), .. } => viewer_windows.toggle(&sdl_context, &gb, video.palette(), viewers::Viewer::Tiles),
+                Event::KeyDown { keycode: Some(Keycode::F2), .. } => viewer_windows.toggle(&sdl_context, &gb, video.palette(), viewers::Viewer::TileMap),
+                Event::KeyDown { keycode: Some(Keycode::F3), .. } => viewer_windows.toggle(&sdl_context, &gb, video.palette(), viewers::Viewer::Oam),
+                Event::KeyDown { keycode: Some(Keycode::F4), .. } => viewer_windows.toggle(&sdl_context, &gb, video.palette(), viewers::Viewer::Palettes),
+                Event::KeyDown { keycode: Some(Keycode::F5), .. } => viewer_windows.next_bank(),
+                Event::KeyDown { keycode: Some(Keycode::F6), .. } => viewer_windows.next_palette(gb.is_cgb_mode()),
+                Event::KeyDown { keycode: Some(Keycode::F7), .. } => viewer_windows.next_map(),
+                Event::KeyDown { keycode: Some(Keycode::F8), .. } => toggle_layer(&mut gb, gameboy::Layer::Background),
+                Event::KeyDown { keycode: Some(Keycode::F9), .. } => toggle_layer(&mut gb, gameboy::Layer::Window),
+                Event::KeyDown { keycode: Some(Keycode::F10), .. } => toggle_layer(&mut gb, gameboy::Layer::Sprites),
+                // F11 records a WAV, shift+F11 logs a VGM
+                Event::KeyDown { keycode: Some(Keycode::F11), keymod, .. } if keymod.intersects(LSHIFTMOD | RSHIFTMOD) => {
+                    vgm = match vgm.take() {
+                        Some(vgm) => {
+                            stop_vgm(&mut gb, vgm);
+                            None
+                        },
+                        None => {
+                            let seconds = SystemTime::now().duration_since(UNIX_EPOCH).map(|time| time.as_secs()).unwrap_or(0);
+                            start_vgm(&mut gb, &format!("recording-{}.vgm", seconds))
+                        },
+                    };
+                },
+                Event::KeyDown { keycode: Some(Keycode::F11), .. } => {
+                    recorder = match recorder.take() {
+                        Some(recorder) => {
+                            stop_recording(recorder);
+                            None
+                        },
+                        None => {
+                            let seconds = SystemTime::now().duration_since(UNIX_EPOCH).map(|time| time.as_secs()).unwrap_or(0);
+                            start_recording(&format!("recording-{}.wav", seconds), record_mode, record_rate)
+                        },
+                    };
+                },
+                Event::KeyDown { keycode: Some(Keycode::Num1), keymod, .. } => toggle_channel(&mut gb, 0, keymod),
+                Event::KeyDown { keycode: Some(Keycode::Num2), keymod, .. } => toggle_channel(&mut gb, 1, keymod),
+                Event::KeyDown { keycode: Some(Keycode::Num3), keymod, .. } => toggle_channel(&mut gb, 2, keymod),
+                Event::KeyDown { keycode: Some(Keycode::Num4), keymod, .. } => toggle_channel(&mut gb, 3, keymod),
+                Event::KeyDown { keycode: Some(Keycode::Num0), .. } => {
+                    for channel in 0..4 {
+                        gb.set_channel_muted(channel, false);
+                    }
+                    println!("every channel unmuted");
+                },
+                Event::KeyDown { keycode: Some(Keycode::Num5), .. } => viewer_windows.toggle(&sdl_context, &gb, video.palette(), viewers::Viewer::Scope),
+                Event::KeyDown { keycode: Some(Keycode::Escape), .. } => break 'main,
+                _ => {}
+            }
+        }
+
+        // hotkeys other than fast forward go off on the press and ignore the release
+        for (action, pressed) in actions.drain(..) {
+            match action {
+                input::Action::Button(button) => gb.set_button(button, pressed),
+                input::Action::Hotkey(input::Hotkey::FastForward) => fast_forward = pressed,
+                input::Action::Hotkey(input::Hotkey::Pause) if pressed => {
+                    paused = !paused;
+                    if let Some(ref mut output) = audio_output {
+                        if paused {
+                            output.clear();
+                        }
+                    }
+                    println!("{}", if paused { "paused" } else { "unpaused" });
+                },
+                input::Action::Hotkey(input::Hotkey::Reset) if pressed => match gb.reset() {
+                    Ok(()) => println!("reset"),
+                    Err(e) => println!("couldn't reset: {}", e),
+                },
+                input::Action::Hotkey(input::Hotkey::SaveState) if pressed => save_state(&gb, &state_path),
+                input::Action::Hotkey(input::Hotkey::LoadState) if pressed => load_state(&mut gb, &state_path),
+                input::Action::Hotkey(input::Hotkey::Screenshot) if pressed => {
+                    let seconds = SystemTime::now().duration_since(UNIX_EPOCH).map(|time| time.as_secs()).unwrap_or(0);
+                    save_screenshot(&gb, &video, screenshot_mode, &format!("screenshot-{}.png", seconds));
+                },
+                input::Action::Hotkey(_) => {}
+            }
+        }
+
+        // fast forward runs several frames for each one shown and leaves the sound out
+        let frames = if paused { 0 } else if fast_forward { fast_forward_speed } else { 1 };
+
+        for _ in 0..frames {
+            let wants_channels = recorder.as_ref().map_or(false, |recorder| recorder.wants_channels());
+            gb.set_channel_capture(wants_channels || viewer_windows.wants_channels());
+
+            samples.clear();
+            gb.run_frame();
+
+            gb.drain_audio(&mut samples);
+            gb.drain_channel_audio(&mut channel_samples);
+            record(&mut recorder, &samples, &channel_samples);
+            viewer_windows.push_channel_audio(&channel_samples);
+            channel_samples.clear();
+            log_vgm(&mut gb, &mut vgm, &mut apu_writes);
+        }
+
+        let frame = video.render(gb.framebuffer(), width, gb.pixel_format());
+        texture.with_lock(None, |buffer: &mut [u8], pitch: usize| {
+            copy_pixels(buffer, pitch, frame.pixels, frame.width, frame.height);
+        }).unwrap();
+
+        canvas.clear();
+        canvas.copy(&texture, None, None).unwrap();
+        canvas.present();
+
+        viewer_windows.update(&gb, video.palette());
+
+        // with sound the queue sets the pace, paused or fast forwarding the timer does
+        if let Some(ref mut output) = audio_output {
+            if frames == 1 {
+                output.push(&samples);
+                output.wait();
+                continue;
+            }
+        }
+
+        let now = Instant::now();
+        if next_frame > now {
+            sleep(next_frame - now);
+            next_frame += frame_time;
+        } else {
+            // too far behind to catch up, start timing again from here
+            next_frame = now + frame_time;
+        }
+    }
+
+    if let Some(recorder) = recorder {
+        stop_recording(recorder);
+    }
+    if let Some(vgm) = vgm {
+        stop_vgm(&mut gb, vgm);
+    }
+}
+
+// save states are named after the game so each one gets its own
+fn state_path(title: &str) -> String {
+    let name: String = title.chars().map(|c| if c.is_ascii_alphanumeric() { c } else { '_' }).collect();
+    format!("{}.state", if name.is_empty() { "rustyboi" } else { &name })
+}
+
+fn save_state(gb: &gameboy::Gameboy, path: &str) {
+    match File::create(path).and_then(|mut f| f.write_all(&gb.save_state())) {
+        Ok(_) => println!("saved state {}", path),
+        Err(e) => println!("couldn't save state {}: {}", path, e),
+    }
+}
+
+fn load_state(gb: &mut gameboy::Gameboy, path: &str) {
+    let mut data = Vec::new();
+    if let Err(e) = File::open(path).and_then(|mut f| f.read_to_end(&mut data)) {
+        println!("couldn't read state {}: {}", path, e);
+        return;
+    }
+
+    match gb.load_state(&data) {
+        Ok(_) => println!("loaded state {}", path),
+        Err(e) => println!("couldn't load state {}: {}", path, e),
+    }
+}
+
+// fast_forward_speed = 4 runs four frames for every one shown while fast forward is held
+fn fast_forward_speed_from_config(config: &config::Config) -> u32 {
+    match config.get("fast_forward_speed") {
+        Some(value) => match value.parse::<u32>() {
+            Ok(speed) if speed >= 1 => speed,
+            _ => {
+                println!("fast_forward_speed should be a whole number from 1, got {}", value);
+                4
+            },
+        },
+        None => 4,
+    }
+}
+
+// 1-4 mute or unmute a channel, with shift they solo it instead. soloing the channel that's
+// already the only one playing brings the rest back
+fn toggle_channel(gb: &mut gameboy::Gameboy, channel: usize, keymod: Mod) {
+    if !keymod.intersects(LSHIFTMOD | RSHIFTMOD) {
+        let muted = !gb.channel_muted(channel);
+        gb.set_channel_muted(channel, muted);
+        println!("channel {} {}", channel + 1, if muted { "muted" } else { "unmuted" });
+        return;
+    }
+
+    let soloed = (0..4).all(|other| gb.channel_muted(other) == (other != channel));
+    for other in 0..4 {
+        gb.set_channel_muted(other, !soloed && other != channel);
+    }
+
+    if soloed {
+        println!("every channel unmuted");
+    } else {
+        println!("channel {} soloed", channel + 1);
+    }
+}
+
+fn start_vgm(gb: &mut gameboy::Gameboy, path: &str) -> Option<audio::VgmWriter> {
+    match audio::VgmWriter::create(path) {
+        Ok(vgm) => {
+            gb.set_apu_write_logging(true);
+            println!("logging VGM to {}", path);
+            Some(vgm)
+        },
+        Err(e) => {
+            println!("couldn't start VGM log {}: {}", path, e);
+            None
+        },
+    }
+}
+
+// like record, a write that fails stops the log
+fn log_vgm(gb: &mut gameboy::Gameboy, vgm: &mut Option<audio::VgmWriter>, writes: &mut Vec<gameboy::ApuWrite>) {
+    let result = match *vgm {
+        Some(ref mut vgm) => {
+            writes.clear();
+            let cycle = gb.drain_apu_writes(writes);
+            vgm.write(writes).and_then(|_| vgm.wait_until(cycle))
+        },
+        None => return,
+    };
+
+    if let Err(e) = result {
+        println!("couldn't write VGM log, stopping it: {}", e);
+        if let Some(vgm) = vgm.take() {
+            stop_vgm(gb, vgm);
+        }
+    }
+}
+
+fn stop_vgm(gb: &mut gameboy::Gameboy, vgm: audio::VgmWriter) {
+    gb.set_apu_write_logging(false);
+
+    match vgm.finish() {
+        Ok(_) => println!("VGM log stopped"),
+        Err(e) => println!("couldn't finish VGM log: {}", e),
     }
+}
 
+fn load_gbs(gb: &mut gameboy::Gameboy, path: &str, track: Option<&String>) -> bool {
+    let mut data = Vec::new();
+    if let Err(e) = File::open(path).and_then(|mut f| f.read_to_end(&mut data)) {
+        println!("couldn't read {}: {}", path, e);
+        return false;
+    }
+
+    if let Err(e) = gb.load_gbs(&data) {
+        println!("couldn't load {}: {}", path, e);
+        return false;
+    }
+
+    if let Some(header) = gb.gbs_header() {
+        println!("{} by {}, {}", header.title, header.author, header.copyright);
+    }
+
+    // counting from 1 like the header does
+    if let Some(track) = track {
+        match track.parse::<u8>() {
+            Ok(track) if track >= 1 => gb.play_gbs_track(track - 1),
+            _ => println!("--track should be a track number from 1, got {}", track),
+        }
+    }
+    print_track(gb);
+
+    true
+}
+
+fn change_track(gb: &mut gameboy::Gameboy, delta: i32) {
+    let (track, count) = match (gb.gbs_track(), gb.gbs_header()) {
+        (Some(track), Some(header)) => (track as i32, header.track_count as i32),
+        _ => return,
+    };
+
+    gb.play_gbs_track(((track + delta + count) % count) as u8);
+    print_track(gb);
+}
+
+fn print_track(gb: &gameboy::Gameboy) {
+    if let (Some(track), Some(header)) = (gb.gbs_track(), gb.gbs_header()) {
+        println!("track {}/{}", track + 1, header.track_count);
+    }
+}
+
+fn toggle_layer(gb: &mut gameboy::Gameboy, layer: gameboy::Layer) {
+    let visible = !gb.layer_visible(layer);
+    gb.set_layer_visible(layer, visible);
+    println!("{:?} {}", layer, if visible { "shown" } else { "hidden" });
+}
+
+// hidden_sprites = 0, 12, 39 hides those OAM entries
+fn hide_sprites_from_config(gb: &mut gameboy::Gameboy, config: &config::Config) {
+    let value = match config.get("hidden_sprites") {
+        Some(value) => value,
+        None => return,
+    };
+
+    for index in value.split(',').map(|index| index.trim()).filter(|index| !index.is_empty()) {
+        match index.parse::<usize>() {
+            Ok(index) if index < 40 => gb.set_sprite_visible(index, false),
+            _ => println!("hidden_sprites should be OAM entries from 0 to 39, got {}", index),
+        }
+    }
+}
+
+// muted_channels = 1, 3 starts with those channels muted
+fn mute_channels_from_config(gb: &mut gameboy::Gameboy, config: &config::Config) {
+    let value = match config.get("muted_channels") {
+        Some(value) => value,
+        None => return,
+    };
+
+    for channel in value.split(',').map(|channel| channel.trim()).filter(|channel| !channel.is_empty()) {
+        match channel.parse::<usize>() {
+            Ok(channel) if channel >= 1 && channel <= 4 => gb.set_channel_muted(channel - 1, true),
+            _ => println!("muted_channels should be channels from 1 to 4, got {}", channel),
+        }
+    }
+}
+
+// into a locked RGB888 texture, which is 0x00RRGGBB in native order
+pub fn copy_pixels(buffer: &mut [u8], pitch: usize, pixels: &[u32], width: usize, height: usize) {
+    for y in 0..height {
+        for x in 0..width {
+            let pixel = pixels[y * width + x];
+            let idx = y * pitch + x * 4;
+            buffer[idx] = pixel as u8;
+            buffer[idx + 1] = (pixel >> 8) as u8;
+            buffer[idx + 2] = (pixel >> 16) as u8;
+            buffer[idx + 3] = 0xFF;
+        }
+    }
+}
+
+// every frame goes through video like it would on screen, so frame blending builds up the same
+fn run_headless(gb: &mut gameboy::Gameboy, video: &mut video::Video, frames: u32, mut recorder: Option<audio::Recorder>, mut vgm: Option<audio::VgmWriter>) {
+    let (width, _) = gb.screen_size();
+    let mut serial = Vec::new();
+    let mut samples = Vec::new();
+    let mut channel_samples = Vec::new();
+    let mut apu_writes = Vec::new();
+    gb.set_channel_capture(recorder.as_ref().map_or(false, |recorder| recorder.wants_channels()));
+
+    for _ in 0..frames {
+        gb.run_frame();
+        video.render(gb.framebuffer(), width, gb.pixel_format());
+        gb.drain_serial_output(&mut serial);
+
+        gb.drain_audio(&mut samples);
+        gb.drain_channel_audio(&mut channel_samples);
+        record(&mut recorder, &samples, &channel_samples);
+        samples.clear();
+        channel_samples.clear();
+        log_vgm(gb, &mut vgm, &mut apu_writes);
+    }
+
+    if let Some(recorder) = recorder {
+        stop_recording(recorder);
+    }
+    if let Some(vgm) = vgm {
+        stop_vgm(gb, vgm);
+    }
+
+    // test ROMs print their results over the link cable
+    if !serial.is_empty() {
+        println!("serial: {}", String::from_utf8_lossy(&serial));
+    }
     gb.print_registers();
 }
 
-fn get_canvas(context: &sdl2::Sdl, title: &str) -> Result<sdl2::render::WindowCanvas, sdl2::IntegerOrSdlError> {
+fn start_recording(path: &str, mode: audio::RecordMode, rate: u32) -> Option<audio::Recorder> {
+    match audio::Recorder::start(path, mode, rate) {
+        Ok(recorder) => {
+            println!("recording {:?} to {}", mode, path);
+            Some(recorder)
+        },
+        Err(e) => {
+            println!("couldn't start recording {}: {}", path, e);
+            None
+        },
+    }
+}
+
+// a write that fails stops the recording, what was written before it is kept
+fn record(recorder: &mut Option<audio::Recorder>, samples: &[f32], channel_samples: &[f32]) {
+    let result = match *recorder {
+        Some(ref mut recorder) => recorder.update(samples, channel_samples),
+        None => return,
+    };
+
+    if let Err(e) = result {
+        println!("couldn't write recording, stopping it: {}", e);
+        if let Some(recorder) = recorder.take() {
+            stop_recording(recorder);
+        }
+    }
+}
+
+fn stop_recording(recorder: audio::Recorder) {
+    match recorder.finish() {
+        Ok(_) => println!("recording stopped"),
+        Err(e) => println!("couldn't finish recording: {}", e),
+    }
+}
+
+fn save_screenshot(gb: &gameboy::Gameboy, video: &video::Video, mode: video::ScreenshotMode, path: &str) {
+    let (width, _) = gb.screen_size();
+    let png = match video.screenshot(gb.framebuffer(), width, gb.pixel_format(), mode) {
+        Ok(png) => png,
+        Err(e) => {
+            println!("couldn't take screenshot: {}", e);
+            return;
+        },
+    };
+
+    match File::create(path).and_then(|mut f| f.write_all(&png)) {
+        Ok(_) => println!("saved screenshot {}", path),
+        Err(e) => println!("couldn't save screenshot {}: {}", path, e),
+    }
+}
+
+// screenshot_mode = rgb | indexed
+fn screenshot_mode_from_config(config: &config::Config) -> video::ScreenshotMode {
+    match config.get("screenshot_mode") {
+        Some(name) => video::ScreenshotMode::from_name(name).unwrap_or_else(|| {
+            println!("unknown screenshot mode {}, using rgb", name);
+            video::ScreenshotMode::Rgb
+        }),
+        None => video::ScreenshotMode::Rgb,
+    }
+}
+
+// model = dmg | cgb | sgb
+fn model_from_config(config: &config::Config) -> gameboy::Model {
+    match config.get("model") {
+        Some("cgb") => gameboy::Model::CGB,
+        Some("sgb") => gameboy::Model::SGB,
+        Some("dmg") | None => gameboy::Model::DMG,
+        Some(value) => {
+            println!("unknown model {}, using dmg", value);
+            gameboy::Model::DMG
+        },
+    }
+}
+
+// renderer = scanline | fifo
+fn renderer_from_config(config: &config::Config) -> gameboy::Renderer {
+    match config.get("renderer") {
+        Some("fifo") => gameboy::Renderer::Fifo,
+        Some("scanline") | None => gameboy::Renderer::Scanline,
+        Some(value) => {
+            println!("unknown renderer {}, using scanline", value);
+            gameboy::Renderer::Scanline
+        },
+    }
+}
+
+// compat_combo = up | up_a | up_b | left | ... | right_b picks the colors of a DMG game on a
+// CGB like holding those buttons during the boot logo does
+fn compat_combo_from_config(config: &config::Config) -> Option<gameboy::CompatCombo> {
+    let name = config.get("compat_combo")?;
+    let combo = gameboy::CompatCombo::from_name(name);
+    if combo.is_none() {
+        println!("unknown compat combo {}, using the game's own colors", name);
+    }
+
+    combo
+}
+
+fn get_canvas(context: &sdl2::Sdl, title: &str, width: u32, height: u32) -> Result<sdl2::render::WindowCanvas, sdl2::IntegerOrSdlError> {
     let video_subsys = context.video().unwrap();
-    let window = video_subsys.window(title, SCREEN_WIDTH, SCREEN_HEIGHT)
+    let window = video_subsys.window(title, width, height)
         .position_centered()
         .opengl()
         .build()
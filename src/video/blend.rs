@@ -0,0 +1,65 @@
+// The original LCD takes a few frames to change color, and games lean on that to flicker
+// sprites into transparency. Each output frame is mixed with the previous output, so older
+// frames fade out over time instead of vanishing at once.
+
+pub struct FrameBlend {
+    // how much of the previous output survives into the next one, 0.0 turns blending off
+    weight: f32,
+    previous: Vec<u32>,
+}
+
+impl FrameBlend {
+
+    pub fn new(weight: f32) -> FrameBlend {
+        FrameBlend {
+            weight: clamp_weight(weight),
+            previous: Vec::new(),
+        }
+    }
+
+    pub fn set_weight(&mut self, weight: f32) {
+        self.weight = clamp_weight(weight);
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.weight > 0.0
+    }
+
+    // forget history, e.g. after a reset or loading a state
+    pub fn clear(&mut self) {
+        self.previous.clear();
+    }
+
+    pub fn apply(&mut self, frame: &mut [u32]) {
+        if !self.enabled() {
+            return;
+        }
+
+        if self.previous.len() != frame.len() {
+            self.previous = frame.to_vec();
+            return;
+        }
+
+        for (pixel, previous) in frame.iter_mut().zip(self.previous.iter_mut()) {
+            *pixel = mix(*pixel, *previous, self.weight);
+            *previous = *pixel;
+        }
+    }
+}
+
+// weights at or above 1.0 would freeze the picture
+fn clamp_weight(weight: f32) -> f32 {
+    weight.max(0.0).min(0.95)
+}
+
+fn mix(current: u32, previous: u32, weight: f32) -> u32 {
+    let mut result = 0;
+    for shift in &[16, 8, 0] {
+        let c = ((current >> shift) & 0xFF) as f32;
+        let p = ((previous >> shift) & 0xFF) as f32;
+        let value = c * (1.0 - weight) + p * weight;
+        result |= (value.round() as u32) << shift;
+    }
+
+    result
+}
@@ -0,0 +1,209 @@
+// Everything that turns the emulated framebuffer into something to put on screen or in a file
+// happens here, on the CPU, so it works the same with or without a window.
+
+mod blend;
+mod palette;
+mod png;
+mod scale;
+pub mod viewer;
+
+use config;
+use gameboy::PixelFormat;
+
+pub use self::palette::DmgPalette;
+pub use self::scale::Filter;
+
+use self::blend::FrameBlend;
+use self::palette::{ ColorCorrection, cgb_to_rgb };
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum ScreenshotMode {
+    // the last rendered frame as it shows on screen, blended, filtered and scaled
+    Rgb,
+    // 2-bit indices that are exactly the DMG shades, for comparing against reference images
+    Indexed,
+}
+
+impl ScreenshotMode {
+
+    pub fn from_name(name: &str) -> Option<ScreenshotMode> {
+        match name {
+            "rgb" => Some(ScreenshotMode::Rgb),
+            "indexed" => Some(ScreenshotMode::Indexed),
+            _ => None,
+        }
+    }
+}
+
+// 0xRRGGBB pixels, row major
+pub struct Frame<'a> {
+    pub pixels: &'a [u32],
+    pub width: usize,
+    pub height: usize,
+}
+
+pub struct Video {
+    palette: DmgPalette,
+    color_correction: Option<ColorCorrection>,
+    blend: FrameBlend,
+    filter: Filter,
+    // total integer scale, anything the filter doesn't cover is done with nearest neighbour
+    scale: usize,
+    lcd_grid: bool,
+    output: Vec<u32>,
+    filtered: Vec<u32>,
+    scaled: Vec<u32>,
+    // of the last rendered frame in scaled
+    scaled_width: usize,
+}
+
+impl Video {
+
+    pub fn new(palette: DmgPalette, color_correction: bool) -> Video {
+        Video {
+            palette: palette,
+            color_correction: if color_correction { Some(ColorCorrection::new()) } else { None },
+            blend: FrameBlend::new(0.0),
+            filter: Filter::Nearest,
+            scale: 1,
+            lcd_grid: false,
+            output: Vec::new(),
+            filtered: Vec::new(),
+            scaled: Vec::new(),
+            scaled_width: 0,
+        }
+    }
+
+    pub fn from_config(config: &config::Config) -> Video {
+        let mut video = Video::new(DmgPalette::from_config(config), config.get_bool("color_correction", true));
+
+        // frame_blend = 0.5 keeps half of the previous frame, 0 turns it off
+        if let Some(value) = config.get("frame_blend") {
+            match value.parse::<f32>() {
+                Ok(weight) => video.set_frame_blend(weight),
+                Err(_) => println!("frame_blend should be a number from 0 to 1, got {}", value),
+            }
+        }
+
+        // filter = nearest | scale2x | scale3x | smooth2x
+        if let Some(name) = config.get("filter") {
+            match Filter::from_name(name) {
+                Some(filter) => video.set_filter(filter),
+                None => println!("unknown filter {}, using nearest", name),
+            }
+        }
+
+        if let Some(value) = config.get("scale") {
+            match value.parse::<usize>() {
+                Ok(scale) => {
+                    video.set_scale(scale);
+                    // the filter's own factor has to go into it evenly
+                    if video.output_scale() != scale {
+                        println!("scale {} doesn't suit the {}x filter, using {}", scale, video.filter.factor(), video.output_scale());
+                    }
+                },
+                Err(_) => println!("scale should be a whole number, got {}", value),
+            }
+        }
+
+        video.set_lcd_grid(config.get_bool("lcd_grid", false));
+
+        video
+    }
+
+    pub fn set_palette(&mut self, palette: DmgPalette) {
+        self.palette = palette;
+    }
+
+    pub fn palette(&self) -> &DmgPalette {
+        &self.palette
+    }
+
+    pub fn set_color_correction(&mut self, enabled: bool) {
+        if enabled && self.color_correction.is_none() {
+            self.color_correction = Some(ColorCorrection::new());
+        } else if !enabled {
+            self.color_correction = None;
+        }
+    }
+
+    pub fn set_frame_blend(&mut self, weight: f32) {
+        self.blend.set_weight(weight);
+    }
+
+    pub fn set_filter(&mut self, filter: Filter) {
+        self.filter = filter;
+    }
+
+    pub fn set_scale(&mut self, scale: usize) {
+        self.scale = scale.max(1);
+    }
+
+    pub fn set_lcd_grid(&mut self, enabled: bool) {
+        self.lcd_grid = enabled;
+    }
+
+    // the final scale, at least the filter's own factor
+    pub fn output_scale(&self) -> usize {
+        let factor = self.filter.factor();
+
+        factor * (self.scale / factor).max(1)
+    }
+
+    // call when the picture jumps, so the old frame doesn't bleed into the new one
+    pub fn clear_history(&mut self) {
+        self.blend.clear();
+    }
+
+    // takes the framebuffer from Gameboy::framebuffer, width pixels wide
+    pub fn render(&mut self, framebuffer: &[u16], width: usize, format: PixelFormat) -> Frame {
+        self.output.clear();
+        map_colors(&self.palette, self.color_correction.as_ref(), framebuffer, format, &mut self.output);
+
+        self.blend.apply(&mut self.output);
+
+        let height = self.output.len() / width;
+        let factor = self.filter.factor();
+        let output_scale = self.output_scale();
+
+        self.filter.apply(&self.output, width, height, &mut self.filtered);
+        scale::nearest(&self.filtered, width * factor, height * factor, output_scale / factor, &mut self.scaled);
+
+        if self.lcd_grid {
+            scale::lcd_grid(&mut self.scaled, width * output_scale, output_scale);
+        }
+        self.scaled_width = width * output_scale;
+
+        Frame {
+            pixels: &self.scaled,
+            width: width * output_scale,
+            height: height * output_scale,
+        }
+    }
+
+    // a PNG of the last frame from render, or of the framebuffer at its original size for
+    // indexed, which only works for DMG shades
+    pub fn screenshot(&self, framebuffer: &[u16], width: usize, format: PixelFormat, mode: ScreenshotMode) -> Result<Vec<u8>, &'static str> {
+        match mode {
+            ScreenshotMode::Indexed if format != PixelFormat::Shades => Err("indexed screenshots need a DMG game without colors"),
+            ScreenshotMode::Indexed => Ok(png::encode_indexed(framebuffer, width, framebuffer.len() / width, &self.palette.colors())),
+            ScreenshotMode::Rgb if self.scaled.is_empty() => Err("no frame has been rendered yet"),
+            ScreenshotMode::Rgb => Ok(png::encode_rgb(&self.scaled, self.scaled_width, self.scaled.len() / self.scaled_width)),
+        }
+    }
+}
+
+fn map_colors(palette: &DmgPalette, correction: Option<&ColorCorrection>, framebuffer: &[u16], format: PixelFormat, output: &mut Vec<u32>) {
+    match format {
+        PixelFormat::Shades => {
+            let colors = palette.colors();
+            output.extend(framebuffer.iter().map(|&shade| colors[(shade & 0b11) as usize]));
+        },
+        // the SGB goes through the TV, which doesn't need the LCD correction
+        PixelFormat::SnesColors => output.extend(framebuffer.iter().map(|&color| cgb_to_rgb(color))),
+        PixelFormat::CgbColors => match correction {
+            Some(correction) => output.extend(framebuffer.iter().map(|&color| correction.correct(color))),
+            None => output.extend(framebuffer.iter().map(|&color| cgb_to_rgb(color))),
+        },
+    }
+}
@@ -0,0 +1,122 @@
+use config;
+
+// 4 colors as 0xRRGGBB, lightest first, that DMG shades 0-3 are drawn with
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum DmgPalette {
+    ClassicGreen,
+    PocketGrey,
+    Custom([u32; 4]),
+}
+
+const CLASSIC_GREEN: [u32; 4] = [0x9BBC0F, 0x8BAC0F, 0x306230, 0x0F380F];
+const POCKET_GREY: [u32; 4] = [0xC4CFA1, 0x8B956D, 0x4D533C, 0x1F1F1F];
+
+impl DmgPalette {
+
+    pub fn colors(&self) -> [u32; 4] {
+        match *self {
+            DmgPalette::ClassicGreen => CLASSIC_GREEN,
+            DmgPalette::PocketGrey => POCKET_GREY,
+            DmgPalette::Custom(colors) => colors,
+        }
+    }
+
+    // palette = green | pocket | custom, custom reads its colors from palette_colors:
+    //
+    //     palette = custom
+    //     palette_colors = #E0F8D0 #88C070 #346856 #081820
+    pub fn from_config(config: &config::Config) -> DmgPalette {
+        match config.get("palette") {
+            None | Some("green") => DmgPalette::ClassicGreen,
+            Some("pocket") => DmgPalette::PocketGrey,
+            Some("custom") => {
+                match config.get("palette_colors").and_then(parse_colors) {
+                    Some(colors) => DmgPalette::Custom(colors),
+                    None => {
+                        println!("palette_colors needs 4 hex colors, using the classic palette");
+                        DmgPalette::ClassicGreen
+                    },
+                }
+            },
+            Some(name) => {
+                println!("unknown palette {}, using the classic palette", name);
+                DmgPalette::ClassicGreen
+            },
+        }
+    }
+}
+
+fn parse_colors(value: &str) -> Option<[u32; 4]> {
+    let mut colors = [0; 4];
+    let mut count = 0;
+
+    for part in value.split_whitespace() {
+        if count == 4 {
+            return None;
+        }
+        colors[count] = config::parse_hex_color(part)?;
+        count += 1;
+    }
+
+    if count == 4 { Some(colors) } else { None }
+}
+
+// the CGB screen is darker than a PC monitor and its subpixels bleed into each other, so raw
+// colors come out oversaturated. games were drawn for the real screen, this approximates it
+const LCD_GAMMA: f32 = 2.5;
+const DISPLAY_GAMMA: f32 = 2.2;
+
+// how much of each input channel ends up in each output channel, rows add up to 32
+const CHANNEL_MIX: [[f32; 3]; 3] = [
+    [26.0, 4.0, 2.0],
+    [0.0, 24.0, 8.0],
+    [6.0, 4.0, 22.0],
+];
+
+pub struct ColorCorrection {
+    // every 15-bit color precomputed, so correcting a frame is one lookup per pixel
+    table: Vec<u32>,
+}
+
+impl ColorCorrection {
+
+    pub fn new() -> ColorCorrection {
+        let mut table = Vec::with_capacity(0x8000);
+        for color in 0..0x8000u32 {
+            let linear = [
+                channel_to_linear(color & 0x1F),
+                channel_to_linear((color >> 5) & 0x1F),
+                channel_to_linear((color >> 10) & 0x1F),
+            ];
+
+            let mut rgb = 0;
+            for mix in CHANNEL_MIX.iter() {
+                let value = (mix[0] * linear[0] + mix[1] * linear[1] + mix[2] * linear[2]) / 32.0;
+                let encoded = value.powf(1.0 / DISPLAY_GAMMA) * 255.0;
+                rgb = (rgb << 8) | (encoded.round().min(255.0) as u32);
+            }
+
+            table.push(rgb);
+        }
+
+        ColorCorrection { table: table }
+    }
+
+    pub fn correct(&self, color: u16) -> u32 {
+        self.table[(color & 0x7FFF) as usize]
+    }
+}
+
+fn channel_to_linear(value: u32) -> f32 {
+    (value as f32 / 31.0).powf(LCD_GAMMA)
+}
+
+// straight 5 to 8 bit expansion, for when color correction is off
+pub fn cgb_to_rgb(color: u16) -> u32 {
+    let expand = |value: u16| -> u32 {
+        let value = (value & 0x1F) as u32;
+        (value << 3) | (value >> 2)
+    };
+
+    (expand(color) << 16) | (expand(color >> 5) << 8) | expand(color >> 10)
+}
@@ -0,0 +1,128 @@
+// Just enough of PNG to write screenshots: no filtering, and the image data is stored in
+// uncompressed deflate blocks. Files come out bigger than they need to be but every decoder
+// reads them and the pixels are exact.
+
+const SIGNATURE: [u8; 8] = [0x89, b'P', b'N', b'G', 0x0D, 0x0A, 0x1A, 0x0A];
+
+// color types from IHDR
+const COLOR_TYPE_RGB: u8 = 2;
+const COLOR_TYPE_INDEXED: u8 = 3;
+
+// the most a stored deflate block can hold
+const MAX_STORED_BLOCK: usize = 65535;
+
+// 8-bit RGB from 0xRRGGBB pixels
+pub fn encode_rgb(pixels: &[u32], width: usize, height: usize) -> Vec<u8> {
+    let mut data = Vec::with_capacity((width * 3 + 1) * height);
+
+    for row in pixels.chunks(width).take(height) {
+        data.push(0);  // filter type none
+        for &pixel in row {
+            data.extend_from_slice(&[(pixel >> 16) as u8, (pixel >> 8) as u8, pixel as u8]);
+        }
+    }
+
+    encode(width, height, 8, COLOR_TYPE_RGB, None, &data)
+}
+
+// 2 bits per pixel, every pixel is its index into palette as is
+pub fn encode_indexed(indices: &[u16], width: usize, height: usize, palette: &[u32; 4]) -> Vec<u8> {
+    let row_bytes = (width * 2 + 7) / 8;
+    let mut data = Vec::with_capacity((row_bytes + 1) * height);
+
+    for row in indices.chunks(width).take(height) {
+        data.push(0);
+        for pixels in row.chunks(4) {
+            let mut byte = 0;
+            for (i, &index) in pixels.iter().enumerate() {
+                byte |= ((index & 0b11) as u8) << (6 - i * 2);
+            }
+            data.push(byte);
+        }
+    }
+
+    let mut plte = Vec::with_capacity(12);
+    for &color in palette {
+        plte.extend_from_slice(&[(color >> 16) as u8, (color >> 8) as u8, color as u8]);
+    }
+
+    encode(width, height, 2, COLOR_TYPE_INDEXED, Some(&plte), &data)
+}
+
+fn encode(width: usize, height: usize, bit_depth: u8, color_type: u8, plte: Option<&[u8]>, data: &[u8]) -> Vec<u8> {
+    let mut png = SIGNATURE.to_vec();
+
+    let mut ihdr = Vec::with_capacity(13);
+    ihdr.extend_from_slice(&(width as u32).to_be_bytes());
+    ihdr.extend_from_slice(&(height as u32).to_be_bytes());
+    // bit depth, color type, compression, filter method, no interlacing
+    ihdr.extend_from_slice(&[bit_depth, color_type, 0, 0, 0]);
+    write_chunk(&mut png, b"IHDR", &ihdr);
+
+    if let Some(plte) = plte {
+        write_chunk(&mut png, b"PLTE", plte);
+    }
+
+    write_chunk(&mut png, b"IDAT", &zlib_stored(data));
+    write_chunk(&mut png, b"IEND", &[]);
+
+    png
+}
+
+fn write_chunk(png: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+    png.extend_from_slice(&(data.len() as u32).to_be_bytes());
+
+    let start = png.len();
+    png.extend_from_slice(kind);
+    png.extend_from_slice(data);
+
+    // the CRC covers the chunk type and data but not the length
+    let crc = crc32(&png[start..]);
+    png.extend_from_slice(&crc.to_be_bytes());
+}
+
+fn zlib_stored(data: &[u8]) -> Vec<u8> {
+    // deflate, 32K window, no preset dictionary, check bits so the header is a multiple of 31
+    let mut zlib = vec![0x78, 0x01];
+
+    let blocks = data.chunks(MAX_STORED_BLOCK).count().max(1);
+    for (i, block) in data.chunks(MAX_STORED_BLOCK).enumerate() {
+        let last = i + 1 == blocks;
+        zlib.push(last as u8);
+        zlib.extend_from_slice(&(block.len() as u16).to_le_bytes());
+        zlib.extend_from_slice(&(!(block.len() as u16)).to_le_bytes());
+        zlib.extend_from_slice(block);
+    }
+
+    if data.is_empty() {
+        zlib.extend_from_slice(&[0x01, 0x00, 0x00, 0xFF, 0xFF]);
+    }
+
+    zlib.extend_from_slice(&adler32(data).to_be_bytes());
+
+    zlib
+}
+
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = 0xFFFFFFFFu32;
+
+    for &byte in data {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 { 0xEDB88320 ^ (crc >> 1) } else { crc >> 1 };
+        }
+    }
+
+    !crc
+}
+
+fn adler32(data: &[u8]) -> u32 {
+    let (mut a, mut b) = (1u32, 0u32);
+
+    for &byte in data {
+        a = (a + byte as u32) % 65521;
+        b = (b + a) % 65521;
+    }
+
+    (b << 16) | a
+}
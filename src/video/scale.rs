@@ -0,0 +1,239 @@
+// Integer upscaling filters. All of them work on 0xRRGGBB pixels and return the output size.
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Filter {
+    Nearest,
+    Scale2x,
+    Scale3x,
+    Smooth2x,
+}
+
+impl Filter {
+
+    pub fn from_name(name: &str) -> Option<Filter> {
+        match name {
+            "nearest" => Some(Filter::Nearest),
+            "scale2x" => Some(Filter::Scale2x),
+            "scale3x" => Some(Filter::Scale3x),
+            "smooth2x" => Some(Filter::Smooth2x),
+            _ => None,
+        }
+    }
+
+    // how much the filter itself scales by, the rest is made up with nearest neighbour
+    pub fn factor(&self) -> usize {
+        match *self {
+            Filter::Nearest => 1,
+            Filter::Scale2x | Filter::Smooth2x => 2,
+            Filter::Scale3x => 3,
+        }
+    }
+
+    pub fn apply(&self, input: &[u32], width: usize, height: usize, output: &mut Vec<u32>) {
+        match *self {
+            Filter::Nearest => {
+                output.clear();
+                output.extend_from_slice(input);
+            },
+            Filter::Scale2x => scale2x(input, width, height, output),
+            Filter::Scale3x => scale3x(input, width, height, output),
+            Filter::Smooth2x => smooth2x(input, width, height, output),
+        }
+    }
+}
+
+pub fn nearest(input: &[u32], width: usize, height: usize, factor: usize, output: &mut Vec<u32>) {
+    output.clear();
+    output.reserve(width * height * factor * factor);
+
+    for y in 0..height {
+        let row = &input[y * width..(y + 1) * width];
+        for _ in 0..factor {
+            for &pixel in row {
+                for _ in 0..factor {
+                    output.push(pixel);
+                }
+            }
+        }
+    }
+}
+
+// darkens the edges of every cell so the picture looks like the dot matrix of the real screen
+pub fn lcd_grid(pixels: &mut [u32], width: usize, cell: usize) {
+    if cell < 2 {
+        return;
+    }
+
+    for (i, pixel) in pixels.iter_mut().enumerate() {
+        let x = (i % width) % cell;
+        let y = (i / width) % cell;
+
+        if x == cell - 1 || y == cell - 1 {
+            *pixel = darken(*pixel);
+        }
+    }
+}
+
+fn darken(pixel: u32) -> u32 {
+    let mut result = 0;
+    for shift in &[16, 8, 0] {
+        let value = (pixel >> shift) & 0xFF;
+        result |= (value * 3 / 4) << shift;
+    }
+
+    result
+}
+
+// neighbours of (x, y), clamped at the edges:
+// a b c
+// d e f
+// g h i
+fn neighbours(input: &[u32], width: usize, height: usize, x: usize, y: usize) -> [u32; 9] {
+    let left = if x > 0 { x - 1 } else { x };
+    let right = if x + 1 < width { x + 1 } else { x };
+    let up = if y > 0 { y - 1 } else { y };
+    let down = if y + 1 < height { y + 1 } else { y };
+    let at = |x: usize, y: usize| input[y * width + x];
+
+    [
+        at(left, up), at(x, up), at(right, up),
+        at(left, y), at(x, y), at(right, y),
+        at(left, down), at(x, down), at(right, down),
+    ]
+}
+
+// writes a factor x factor block for source pixel (x, y), rows of the block come in order
+fn put_block(output: &mut [u32], out_width: usize, x: usize, y: usize, block: &[u32], factor: usize) {
+    for by in 0..factor {
+        for bx in 0..factor {
+            output[(y * factor + by) * out_width + x * factor + bx] = block[by * factor + bx];
+        }
+    }
+}
+
+// AdvMAME2x: copies a neighbour into a corner when two edges meet there
+fn scale2x(input: &[u32], width: usize, height: usize, output: &mut Vec<u32>) {
+    output.clear();
+    output.resize(width * height * 4, 0);
+
+    for y in 0..height {
+        for x in 0..width {
+            let n = neighbours(input, width, height, x, y);
+            let (b, d, e, f, h) = (n[1], n[3], n[4], n[5], n[7]);
+
+            let block = if b != h && d != f {
+                [
+                    if d == b { d } else { e },
+                    if b == f { f } else { e },
+                    if d == h { d } else { e },
+                    if h == f { f } else { e },
+                ]
+            } else {
+                [e; 4]
+            };
+
+            put_block(output, width * 2, x, y, &block, 2);
+        }
+    }
+}
+
+// AdvMAME3x, the same idea with the edge centers filled in as well
+fn scale3x(input: &[u32], width: usize, height: usize, output: &mut Vec<u32>) {
+    output.clear();
+    output.resize(width * height * 9, 0);
+
+    for y in 0..height {
+        for x in 0..width {
+            let n = neighbours(input, width, height, x, y);
+            let (a, b, c, d, e, f, g, h, i) = (n[0], n[1], n[2], n[3], n[4], n[5], n[6], n[7], n[8]);
+
+            let block = if b != h && d != f {
+                [
+                    if d == b { d } else { e },
+                    if (d == b && e != c) || (b == f && e != a) { b } else { e },
+                    if b == f { f } else { e },
+                    if (d == b && e != g) || (d == h && e != a) { d } else { e },
+                    e,
+                    if (b == f && e != i) || (h == f && e != c) { f } else { e },
+                    if d == h { d } else { e },
+                    if (d == h && e != i) || (h == f && e != g) { h } else { e },
+                    if h == f { f } else { e },
+                ]
+            } else {
+                [e; 9]
+            };
+
+            put_block(output, width * 3, x, y, &block, 3);
+        }
+    }
+}
+
+// Scale2x with softer edges, borrowing from hq2x without its lookup table: neighbours are
+// compared in YUV with hq2x's thresholds, so near identical colors count as the same, and
+// corners where two edges meet are blended 2:1:1 with those edges instead of being replaced
+fn smooth2x(input: &[u32], width: usize, height: usize, output: &mut Vec<u32>) {
+    output.clear();
+    output.resize(width * height * 4, 0);
+
+    for y in 0..height {
+        for x in 0..width {
+            let n = neighbours(input, width, height, x, y);
+            let (b, d, e, f, h) = (n[1], n[3], n[4], n[5], n[7]);
+
+            let corner = |side_1: u32, side_2: u32, other_1: u32, other_2: u32| -> u32 {
+                if similar(side_1, side_2) && !similar(side_1, other_1) && !similar(side_2, other_2) && !similar(e, side_1) {
+                    blend(e, side_1, side_2)
+                } else {
+                    e
+                }
+            };
+
+            let block = [
+                corner(d, b, h, f),
+                corner(b, f, d, h),
+                corner(d, h, b, f),
+                corner(h, f, d, b),
+            ];
+
+            put_block(output, width * 2, x, y, &block, 2);
+        }
+    }
+}
+
+const Y_THRESHOLD: i32 = 48;
+const U_THRESHOLD: i32 = 7;
+const V_THRESHOLD: i32 = 6;
+
+fn similar(first: u32, second: u32) -> bool {
+    if first == second {
+        return true;
+    }
+
+    let (y1, u1, v1) = yuv(first);
+    let (y2, u2, v2) = yuv(second);
+
+    (y1 - y2).abs() <= Y_THRESHOLD && (u1 - u2).abs() <= U_THRESHOLD && (v1 - v2).abs() <= V_THRESHOLD
+}
+
+fn yuv(pixel: u32) -> (i32, i32, i32) {
+    let r = ((pixel >> 16) & 0xFF) as i32;
+    let g = ((pixel >> 8) & 0xFF) as i32;
+    let b = (pixel & 0xFF) as i32;
+
+    let y = (r + g + b) >> 2;
+    let u = 128 + ((r - b) >> 2);
+    let v = 128 + ((2 * g - r - b) >> 3);
+
+    (y, u, v)
+}
+
+// 2 parts center, 1 part each side
+fn blend(center: u32, side_1: u32, side_2: u32) -> u32 {
+    let mut result = 0;
+    for shift in &[16, 8, 0] {
+        let value = ((center >> shift) & 0xFF) * 2 + ((side_1 >> shift) & 0xFF) + ((side_2 >> shift) & 0xFF);
+        result |= (value / 4) << shift;
+    }
+
+    result
+}
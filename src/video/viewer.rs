@@ -0,0 +1,447 @@
+// Debug views of what's in VRAM, OAM and palette RAM, plus an oscilloscope of the sound
+// channels. Each one is drawn into a plain image so it can go to a window or straight into a PNG.
+
+use std::fmt;
+
+use gameboy::{ Gameboy, PixelFormat, bg_tile_address, tall_sprites, tile_pixel };
+
+use video::DmgPalette;
+use video::palette::cgb_to_rgb;
+use video::png;
+
+const TILE_BYTES: usize = 16;
+const TILES_PER_BANK: usize = 384;
+const TILES_PER_ROW: usize = 16;
+const MAP_SIZE: usize = 256;
+const OAM_ENTRIES: usize = 40;
+const OAM_COLUMNS: usize = 8;
+// the sprite on the left, three lines of its attributes on the right
+const OAM_CELL_WIDTH: usize = 56;
+const OAM_CELL_HEIGHT: usize = 24;
+const SWATCH_SIZE: usize = 16;
+const SCOPE_WIDTH: usize = 256;
+const SCOPE_LANE_HEIGHT: usize = 48;
+
+// channel samples the scope shows across its width, a bit under a frame's worth
+const SCOPE_SAMPLES: usize = 2048;
+// how many it wants to be given, the extra is where it looks for a rising edge to start from
+pub const SCOPE_HISTORY: usize = SCOPE_SAMPLES * 2;
+
+const VIEWPORT_COLOR: u32 = 0xFF0000;
+const CELL_BACKGROUND: u32 = 0x404040;
+const SCOPE_COLORS: [u32; 4] = [0x40E040, 0x40C0E0, 0xE0C040, 0xE06060];
+const SCOPE_MUTED_COLOR: u32 = 0x606060;
+const SCOPE_AXIS_COLOR: u32 = 0x303030;
+const TEXT_COLOR: u32 = 0xE0E0E0;
+
+// 3x5 glyphs for the OAM attributes, a row per byte with the left pixel in bit 2
+const GLYPH_WIDTH: usize = 3;
+const GLYPH_HEIGHT: usize = 5;
+const GLYPHS: [(char, [u8; GLYPH_HEIGHT]); 23] = [
+    ('0', [0b111, 0b101, 0b101, 0b101, 0b111]),
+    ('1', [0b010, 0b110, 0b010, 0b010, 0b111]),
+    ('2', [0b111, 0b001, 0b111, 0b100, 0b111]),
+    ('3', [0b111, 0b001, 0b111, 0b001, 0b111]),
+    ('4', [0b101, 0b101, 0b111, 0b001, 0b001]),
+    ('5', [0b111, 0b100, 0b111, 0b001, 0b111]),
+    ('6', [0b111, 0b100, 0b111, 0b101, 0b111]),
+    ('7', [0b111, 0b001, 0b001, 0b001, 0b001]),
+    ('8', [0b111, 0b101, 0b111, 0b101, 0b111]),
+    ('9', [0b111, 0b101, 0b111, 0b001, 0b111]),
+    ('A', [0b010, 0b101, 0b111, 0b101, 0b101]),
+    ('B', [0b110, 0b101, 0b110, 0b101, 0b110]),
+    ('C', [0b011, 0b100, 0b100, 0b100, 0b011]),
+    ('D', [0b110, 0b101, 0b101, 0b101, 0b110]),
+    ('E', [0b111, 0b100, 0b110, 0b100, 0b111]),
+    ('F', [0b111, 0b100, 0b110, 0b100, 0b100]),
+    ('H', [0b101, 0b101, 0b111, 0b101, 0b101]),
+    ('P', [0b110, 0b101, 0b110, 0b100, 0b100]),
+    ('T', [0b111, 0b010, 0b010, 0b010, 0b010]),
+    ('V', [0b101, 0b101, 0b101, 0b101, 0b010]),
+    ('X', [0b101, 0b101, 0b010, 0b101, 0b101]),
+    ('Y', [0b101, 0b101, 0b010, 0b010, 0b010]),
+    ('-', [0b000, 0b000, 0b111, 0b000, 0b000]),
+];
+
+// registers the viewers look at
+const LCDC: u16 = 0xFF40;
+const SCY: u16 = 0xFF42;
+const SCX: u16 = 0xFF43;
+const BGP: u16 = 0xFF47;
+const OBP0: u16 = 0xFF48;
+const OBP1: u16 = 0xFF49;
+
+// which colors tiles are drawn with in the tile viewer
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum TilePalette {
+    // color numbers 0-3 straight through the DMG palette
+    Raw,
+    Background(u8),
+    Sprite(u8),
+}
+
+impl TilePalette {
+
+    // Raw, then every background palette, then every sprite palette
+    pub fn next(self, cgb_mode: bool) -> TilePalette {
+        let last = if cgb_mode { 7 } else { 1 };
+
+        match self {
+            TilePalette::Raw => TilePalette::Background(0),
+            TilePalette::Background(n) if cgb_mode && n < last => TilePalette::Background(n + 1),
+            TilePalette::Background(_) => TilePalette::Sprite(0),
+            TilePalette::Sprite(n) if n < last => TilePalette::Sprite(n + 1),
+            TilePalette::Sprite(_) => TilePalette::Raw,
+        }
+    }
+}
+
+// 0xRRGGBB pixels, row major
+pub struct Image {
+    pub pixels: Vec<u32>,
+    pub width: usize,
+    pub height: usize,
+}
+
+impl Image {
+
+    fn new(width: usize, height: usize, color: u32) -> Image {
+        Image {
+            pixels: vec![color; width * height],
+            width: width,
+            height: height,
+        }
+    }
+
+    fn set(&mut self, x: usize, y: usize, color: u32) {
+        self.pixels[y * self.width + x] = color;
+    }
+
+    pub fn to_png(&self) -> Vec<u8> {
+        png::encode_rgb(&self.pixels, self.width, self.height)
+    }
+}
+
+pub struct OamEntry {
+    pub index: usize,
+    pub y: u8,
+    pub x: u8,
+    pub tile: u8,
+    pub attributes: u8,
+}
+
+impl OamEntry {
+
+    pub fn behind_background(&self) -> bool {
+        self.attributes & 0x80 != 0
+    }
+
+    pub fn y_flip(&self) -> bool {
+        self.attributes & 0x40 != 0
+    }
+
+    pub fn x_flip(&self) -> bool {
+        self.attributes & 0x20 != 0
+    }
+
+    pub fn dmg_palette(&self) -> u8 {
+        (self.attributes >> 4) & 0x01
+    }
+
+    pub fn bank(&self) -> usize {
+        ((self.attributes >> 3) & 0x01) as usize
+    }
+
+    pub fn cgb_palette(&self) -> u8 {
+        self.attributes & 0x07
+    }
+}
+
+impl fmt::Display for OamEntry {
+
+    // positions are shown as on screen, OAM stores them offset by (8, 16)
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "#{:02} x={:4} y={:4} tile=${:02X} obp={} cgb_pal={} bank={} flip={}{} {}",
+            self.index,
+            self.x as i16 - 8,
+            self.y as i16 - 16,
+            self.tile,
+            self.dmg_palette(),
+            self.cgb_palette(),
+            self.bank(),
+            if self.x_flip() { 'x' } else { '-' },
+            if self.y_flip() { 'y' } else { '-' },
+            if self.behind_background() { "behind" } else { "front" })
+    }
+}
+
+pub fn oam_entries(gb: &Gameboy) -> Vec<OamEntry> {
+    gb.oam().chunks(4).take(OAM_ENTRIES).enumerate().map(|(index, entry)| {
+        OamEntry {
+            index: index,
+            y: entry[0],
+            x: entry[1],
+            tile: entry[2],
+            attributes: entry[3],
+        }
+    }).collect()
+}
+
+// all 384 tiles of a bank, 16 to a row
+pub fn tiles(gb: &Gameboy, dmg_palette: &DmgPalette, bank: usize, palette: TilePalette) -> Image {
+    let colors = palette_colors(gb, dmg_palette, palette);
+    let rows = TILES_PER_BANK / TILES_PER_ROW;
+    let mut image = Image::new(TILES_PER_ROW * 8, rows * 8, 0);
+    let bank = if gb.is_cgb_mode() { bank & 0x01 } else { 0 };
+
+    for tile in 0..TILES_PER_BANK {
+        let address = bank * 0x2000 + tile * TILE_BYTES;
+        let (tile_x, tile_y) = ((tile % TILES_PER_ROW) * 8, (tile / TILES_PER_ROW) * 8);
+
+        for y in 0..8 {
+            for x in 0..8 {
+                let color = tile_pixel(gb.vram(), address, x as u8, y as u8) as usize;
+                image.set(tile_x + x, tile_y + y, colors[color]);
+            }
+        }
+    }
+
+    image
+}
+
+// the whole 256x256 background map at 0x9800 (0) or 0x9C00 (1), with the part that's on
+// screen outlined
+pub fn tile_map(gb: &Gameboy, dmg_palette: &DmgPalette, map: usize) -> Image {
+    let vram = gb.vram();
+    let lcdc = gb.ppu_register(LCDC);
+    let map_base = if map == 0 { 0x1800 } else { 0x1C00 };
+    let mut image = Image::new(MAP_SIZE, MAP_SIZE, 0);
+
+    for entry in 0..32 * 32 {
+        let tile_number = vram[map_base + entry];
+        // CGB attributes sit in bank 1: palette, tile bank, x flip and y flip
+        let attributes = if gb.is_cgb_mode() { vram[0x2000 + map_base + entry] } else { 0 };
+        let colors = palette_colors(gb, dmg_palette, TilePalette::Background(attributes & 0x07));
+
+        let bank = ((attributes >> 3) & 0x01) as usize;
+        let address = bank * 0x2000 + bg_tile_address(lcdc, tile_number);
+
+        let (tile_x, tile_y) = ((entry % 32) * 8, (entry / 32) * 8);
+        for y in 0..8 {
+            for x in 0..8 {
+                let tile_x_pixel = if attributes & 0x20 != 0 { 7 - x } else { x };
+                let tile_y_pixel = if attributes & 0x40 != 0 { 7 - y } else { y };
+                let color = tile_pixel(vram, address, tile_x_pixel as u8, tile_y_pixel as u8) as usize;
+                image.set(tile_x + x, tile_y + y, colors[color]);
+            }
+        }
+    }
+
+    outline_viewport(&mut image, gb.ppu_register(SCX) as usize, gb.ppu_register(SCY) as usize);
+
+    image
+}
+
+// the screen is 160x144 and wraps around the edges of the map
+fn outline_viewport(image: &mut Image, scx: usize, scy: usize) {
+    for i in 0..160 {
+        let x = (scx + i) % MAP_SIZE;
+        image.set(x, scy, VIEWPORT_COLOR);
+        image.set(x, (scy + 143) % MAP_SIZE, VIEWPORT_COLOR);
+    }
+
+    for i in 0..144 {
+        let y = (scy + i) % MAP_SIZE;
+        image.set(scx, y, VIEWPORT_COLOR);
+        image.set((scx + 159) % MAP_SIZE, y, VIEWPORT_COLOR);
+    }
+}
+
+// every sprite in its own cell next to its attributes, 8 to a row, in OAM order
+pub fn oam(gb: &Gameboy, dmg_palette: &DmgPalette) -> Image {
+    let rows = OAM_ENTRIES / OAM_COLUMNS;
+    let mut image = Image::new(OAM_COLUMNS * OAM_CELL_WIDTH, rows * OAM_CELL_HEIGHT, CELL_BACKGROUND);
+    let tall = tall_sprites(gb.ppu_register(LCDC));
+    let height = if tall { 16 } else { 8 };
+
+    for entry in oam_entries(gb) {
+        let palette = if gb.is_cgb_mode() { entry.cgb_palette() } else { entry.dmg_palette() };
+        let colors = palette_colors(gb, dmg_palette, TilePalette::Sprite(palette));
+        let bank = if gb.is_cgb_mode() { entry.bank() } else { 0 };
+        let tile = if tall { entry.tile & 0xFE } else { entry.tile } as usize;
+
+        let cell_x = (entry.index % OAM_COLUMNS) * OAM_CELL_WIDTH + 4;
+        let cell_y = (entry.index / OAM_COLUMNS) * OAM_CELL_HEIGHT + 4;
+
+        for y in 0..height {
+            let sprite_y = if entry.y_flip() { height - 1 - y } else { y };
+            let address = bank * 0x2000 + tile * TILE_BYTES + (sprite_y / 8) * TILE_BYTES;
+
+            for x in 0..8 {
+                let sprite_x = if entry.x_flip() { 7 - x } else { x };
+                let color = tile_pixel(gb.vram(), address, sprite_x as u8, (sprite_y % 8) as u8) as usize;
+                // color 0 is transparent
+                if color != 0 {
+                    image.set(cell_x + x, cell_y + y, colors[color]);
+                }
+            }
+        }
+
+        draw_attributes(&mut image, cell_x + 12, cell_y + 2, &entry, gb.is_cgb_mode());
+    }
+
+    image
+}
+
+// the raw OAM bytes, so x and y aren't adjusted to the screen:
+// X0A Y10    x and y
+// T3C P1 B0  tile, palette (OBP0/1, or 0-7 on CGB) and CGB tile bank
+// HVP        x flip, y flip, behind the background, or - for each that's off
+fn draw_attributes(image: &mut Image, x: usize, y: usize, entry: &OamEntry, cgb_mode: bool) {
+    let line_height = GLYPH_HEIGHT + 1;
+    let palette = if cgb_mode {
+        format!("T{:02X} P{} B{}", entry.tile, entry.cgb_palette(), entry.bank())
+    } else {
+        format!("T{:02X} P{}", entry.tile, entry.dmg_palette())
+    };
+    let flags: String = [(entry.x_flip(), 'H'), (entry.y_flip(), 'V'), (entry.behind_background(), 'P')].iter()
+        .map(|&(set, letter)| if set { letter } else { '-' })
+        .collect();
+
+    draw_text(image, x, y, &format!("X{:02X} Y{:02X}", entry.x, entry.y));
+    draw_text(image, x, y + line_height, &palette);
+    draw_text(image, x, y + line_height * 2, &flags);
+}
+
+// characters without a glyph come out as spaces
+fn draw_text(image: &mut Image, x: usize, y: usize, text: &str) {
+    for (i, character) in text.chars().enumerate() {
+        let rows = match GLYPHS.iter().find(|&&(glyph, _)| glyph == character) {
+            Some(&(_, rows)) => rows,
+            None => continue,
+        };
+        let left = x + i * (GLYPH_WIDTH + 1);
+
+        for (row, bits) in rows.iter().enumerate() {
+            for column in 0..GLYPH_WIDTH {
+                if bits & (0b100 >> column) != 0 {
+                    image.set(left + column, y + row, TEXT_COLOR);
+                }
+            }
+        }
+    }
+}
+
+// one row of 4 swatches per palette: the 8 background palettes then the 8 sprite palettes
+// when there are colors, BGP, OBP0 and OBP1 otherwise
+pub fn palettes(gb: &Gameboy, dmg_palette: &DmgPalette) -> Image {
+    let rows: Vec<[u32; 4]> = if gb.is_cgb_mode() {
+        (0..8).map(|n| TilePalette::Background(n))
+            .chain((0..8).map(|n| TilePalette::Sprite(n)))
+            .map(|palette| palette_colors(gb, dmg_palette, palette))
+            .collect()
+    } else {
+        [TilePalette::Background(0), TilePalette::Sprite(0), TilePalette::Sprite(1)].iter()
+            .map(|&palette| palette_colors(gb, dmg_palette, palette))
+            .collect()
+    };
+
+    let mut image = Image::new(4 * SWATCH_SIZE, rows.len() * SWATCH_SIZE, 0);
+    for (row, colors) in rows.iter().enumerate() {
+        for (column, &color) in colors.iter().enumerate() {
+            for y in 0..SWATCH_SIZE {
+                for x in 0..SWATCH_SIZE {
+                    image.set(column * SWATCH_SIZE + x, row * SWATCH_SIZE + y, color);
+                }
+            }
+        }
+    }
+
+    image
+}
+
+// channels 1-4 top to bottom, from samples interleaved the way Gameboy::drain_channel_audio
+// gives them. each lane starts on a rising edge when there is one so a steady tone holds still,
+// and muted channels are drawn grey
+pub fn scope(gb: &Gameboy, channels: &[f32]) -> Image {
+    let mut image = Image::new(SCOPE_WIDTH, 4 * SCOPE_LANE_HEIGHT, 0);
+    let length = channels.len() / 4;
+
+    for channel in 0..4 {
+        let middle = channel * SCOPE_LANE_HEIGHT + SCOPE_LANE_HEIGHT / 2;
+        for x in 0..SCOPE_WIDTH {
+            image.set(x, middle, SCOPE_AXIS_COLOR);
+        }
+
+        if length < 2 {
+            continue;
+        }
+
+        let sample = |i: usize| channels[i * 4 + channel];
+        let latest = length.saturating_sub(SCOPE_SAMPLES);
+        let start = (0..latest).find(|&i| sample(i) <= 0.0 && sample(i + 1) > 0.0).unwrap_or(latest);
+        let shown = SCOPE_SAMPLES.min(length - start);
+
+        let color = if gb.channel_muted(channel) { SCOPE_MUTED_COLOR } else { SCOPE_COLORS[channel] };
+        let to_y = |value: f32| {
+            let offset = (value.max(-1.0).min(1.0) * (SCOPE_LANE_HEIGHT / 2 - 2) as f32).round() as isize;
+            (middle as isize - offset) as usize
+        };
+
+        // each column covers a run of samples and draws from its lowest to its highest, starting
+        // from where the last column ended so the trace doesn't break up
+        let mut previous = sample(start);
+        for x in 0..SCOPE_WIDTH {
+            let first = start + x * shown / SCOPE_WIDTH;
+            let last = (start + (x + 1) * shown / SCOPE_WIDTH).max(first + 1).min(start + shown);
+
+            let (mut low, mut high) = (previous, previous);
+            for i in first..last {
+                low = low.min(sample(i));
+                high = high.max(sample(i));
+            }
+            previous = sample(last - 1);
+
+            for y in to_y(high)..=to_y(low) {
+                image.set(x, y, color);
+            }
+        }
+    }
+
+    image
+}
+
+// what color numbers 0-3 come out as with the given palette
+fn palette_colors(gb: &Gameboy, dmg_palette: &DmgPalette, palette: TilePalette) -> [u32; 4] {
+    let shades = dmg_palette.colors();
+
+    if gb.is_cgb_mode() {
+        let (sprites, number) = match palette {
+            TilePalette::Raw => return shades,
+            TilePalette::Background(n) => (false, n),
+            TilePalette::Sprite(n) => (true, n),
+        };
+
+        let colors = gb.cgb_palette(sprites, number);
+        return [cgb_to_rgb(colors[0]), cgb_to_rgb(colors[1]), cgb_to_rgb(colors[2]), cgb_to_rgb(colors[3])];
+    }
+
+    let (register, sprites, number) = match palette {
+        TilePalette::Raw => return shades,
+        TilePalette::Background(_) => (gb.ppu_register(BGP), false, 0),
+        TilePalette::Sprite(n) if n & 0x01 == 0 => (gb.ppu_register(OBP0), true, 0),
+        TilePalette::Sprite(_) => (gb.ppu_register(OBP1), true, 1),
+    };
+
+    // a DMG game colorized by the CGB picks its shades out of the CGB palettes
+    let colorized = gb.pixel_format() == PixelFormat::CgbColors;
+    let compat_colors = gb.cgb_palette(sprites, number);
+
+    let mut colors = [0; 4];
+    for (color, value) in colors.iter_mut().enumerate() {
+        let shade = ((register >> (color * 2)) & 0b11) as usize;
+        *value = if colorized { cgb_to_rgb(compat_colors[shade]) } else { shades[shade] };
+    }
+
+    colors
+}
@@ -0,0 +1,187 @@
+// Debug windows next to the game: F1 tiles, F2 background map, F3 OAM, F4 palettes. F5 flips
+// the tile viewer between VRAM banks, F6 cycles the palette it draws with and F7 switches
+// between the two background maps. 5 opens an oscilloscope of the four sound channels.
+
+use sdl2;
+use sdl2::pixels::PixelFormatEnum;
+use sdl2::render::WindowCanvas;
+
+use std::fs::File;
+use std::io::prelude::*;
+
+use gameboy::Gameboy;
+use video::DmgPalette;
+use video::viewer::{ self, Image, TilePalette };
+
+const WINDOW_SCALE: usize = 2;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Viewer {
+    Tiles,
+    TileMap,
+    Oam,
+    Palettes,
+    Scope,
+}
+
+pub struct ViewerWindows {
+    windows: Vec<(Viewer, WindowCanvas)>,
+    tile_bank: usize,
+    tile_palette: TilePalette,
+    map: usize,
+    // recent channel samples for the scope, interleaved like Gameboy::drain_channel_audio
+    channel_history: Vec<f32>,
+}
+
+impl Default for ViewerWindows {
+
+    fn default() -> ViewerWindows {
+        ViewerWindows {
+            windows: Vec::new(),
+            tile_bank: 0,
+            tile_palette: TilePalette::Raw,
+            map: 0,
+            channel_history: Vec::new(),
+        }
+    }
+}
+
+impl ViewerWindows {
+
+    pub fn new() -> ViewerWindows {
+        Default::default()
+    }
+
+    // opens the viewer, or closes it if it's already open
+    pub fn toggle(&mut self, context: &sdl2::Sdl, gb: &Gameboy, palette: &DmgPalette, kind: Viewer) {
+        if let Some(idx) = self.windows.iter().position(|&(open, _)| open == kind) {
+            self.windows.remove(idx);
+            if kind == Viewer::Scope {
+                self.channel_history.clear();
+            }
+            return;
+        }
+
+        let image = self.image(gb, palette, kind);
+        let (width, height) = ((image.width * WINDOW_SCALE) as u32, (image.height * WINDOW_SCALE) as u32);
+        match open_canvas(context, &format!("{:?}", kind), width, height) {
+            Ok(canvas) => self.windows.push((kind, canvas)),
+            Err(e) => println!("couldn't open the {:?} viewer: {}", kind, e),
+        }
+
+        if kind == Viewer::Oam {
+            for entry in viewer::oam_entries(gb) {
+                println!("{}", entry);
+            }
+        }
+    }
+
+    // for when a viewer window is closed with its close button
+    pub fn close(&mut self, window_id: u32) {
+        self.windows.retain(|&(_, ref canvas)| canvas.window().id() != window_id);
+        if !self.wants_channels() {
+            self.channel_history.clear();
+        }
+    }
+
+    // the scope only has something to show while Gameboy::set_channel_capture is on
+    pub fn wants_channels(&self) -> bool {
+        self.windows.iter().any(|&(kind, _)| kind == Viewer::Scope)
+    }
+
+    pub fn push_channel_audio(&mut self, samples: &[f32]) {
+        if !self.wants_channels() {
+            return;
+        }
+
+        self.channel_history.extend_from_slice(samples);
+        let max = viewer::SCOPE_HISTORY * 4;
+        if self.channel_history.len() > max {
+            let excess = self.channel_history.len() - max;
+            self.channel_history.drain(..excess);
+        }
+    }
+
+    pub fn next_bank(&mut self) {
+        self.tile_bank ^= 1;
+    }
+
+    pub fn next_palette(&mut self, cgb_mode: bool) {
+        self.tile_palette = self.tile_palette.next(cgb_mode);
+    }
+
+    pub fn next_map(&mut self) {
+        self.map ^= 1;
+    }
+
+    pub fn update(&mut self, gb: &Gameboy, palette: &DmgPalette) {
+        for idx in 0..self.windows.len() {
+            let image = self.image(gb, palette, self.windows[idx].0);
+            let canvas = &mut self.windows[idx].1;
+
+            let texture_creator = canvas.texture_creator();
+            let mut texture = match texture_creator.create_texture_streaming(PixelFormatEnum::RGB888, image.width as u32, image.height as u32) {
+                Ok(texture) => texture,
+                Err(e) => {
+                    println!("couldn't draw viewer: {}", e);
+                    continue;
+                },
+            };
+
+            texture.with_lock(None, |buffer: &mut [u8], pitch: usize| {
+                super::copy_pixels(buffer, pitch, &image.pixels, image.width, image.height);
+            }).unwrap();
+
+            canvas.clear();
+            canvas.copy(&texture, None, None).unwrap();
+            canvas.present();
+        }
+    }
+
+    fn image(&self, gb: &Gameboy, palette: &DmgPalette, kind: Viewer) -> Image {
+        match kind {
+            Viewer::Tiles => viewer::tiles(gb, palette, self.tile_bank, self.tile_palette),
+            Viewer::TileMap => viewer::tile_map(gb, palette, self.map),
+            Viewer::Oam => viewer::oam(gb, palette),
+            Viewer::Palettes => viewer::palettes(gb, palette),
+            Viewer::Scope => viewer::scope(gb, &self.channel_history),
+        }
+    }
+}
+
+// SDL's errors come as different types, they're all just shown as text
+fn open_canvas(context: &sdl2::Sdl, title: &str, width: u32, height: u32) -> Result<WindowCanvas, String> {
+    let window = context.video()?
+        .window(title, width, height)
+        .build()
+        .map_err(|e| e.to_string())?;
+
+    window.into_canvas().build().map_err(|e| e.to_string())
+}
+
+// writes every viewer into directory as PNGs, plus the decoded OAM as text
+pub fn dump(gb: &Gameboy, palette: &DmgPalette, directory: &str) {
+    let banks = if gb.is_cgb_mode() { 2 } else { 1 };
+
+    let mut images = Vec::new();
+    for bank in 0..banks {
+        images.push((format!("tiles{}.png", bank), viewer::tiles(gb, palette, bank, TilePalette::Raw)));
+    }
+    images.push(("tilemap0.png".to_string(), viewer::tile_map(gb, palette, 0)));
+    images.push(("tilemap1.png".to_string(), viewer::tile_map(gb, palette, 1)));
+    images.push(("oam.png".to_string(), viewer::oam(gb, palette)));
+    images.push(("palettes.png".to_string(), viewer::palettes(gb, palette)));
+
+    for (name, image) in images {
+        write_file(&format!("{}/{}", directory, name), &image.to_png());
+    }
+
+    let oam: Vec<String> = viewer::oam_entries(gb).iter().map(|entry| entry.to_string()).collect();
+    write_file(&format!("{}/oam.txt", directory), (oam.join("\n") + "\n").as_bytes());
+}
+
+fn write_file(path: &str, data: &[u8]) {
+    if let Err(e) = File::create(path).and_then(|mut f| f.write_all(data)) {
+        println!("couldn't write {}: {}", path, e);
+    }
+}